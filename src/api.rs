@@ -0,0 +1,776 @@
+// The HTTP API. Every command is an endpoint under API_PREFIX, named by its
+// path in the command tree, e.g. /api/v0/block/put. Query parameters become
+// the command's options, except `arg`, which gives its arguments in order, and
+// the request body is the content of its file argument. The response is the
+// command's output, JSON-encoded as one object per line unless the `encoding`
+// parameter says otherwise, and errors are reported with the status code
+// matching their kind and a JSON body with the message.
+//
+// With `progress=true`, the response is streamed instead, as one JSON object
+// per line: {"progress": <event>} for each progress event the command
+// reports, then {"output": <the output as a string>} or {"error": <the error
+// body>}. The status is sent before the command finishes, so it's always 200.
+//
+// A mutating call can be given an id with the Idempotency-Key header or the
+// `request-id` parameter, so that a client can retry it without it running
+// twice: a call with the id of one of the last IDEMPOTENCY_CACHE_SIZE such
+// calls is answered with that call's result, once it has one, unless it's a
+// different call, which is refused.
+//
+// While a daemon serves the API for a repo, the command line sends commands
+// to it with `forward_to_daemon` instead of running them itself.
+
+use audit;
+use commands::{self, Command, CommandError};
+use commands::cli::ParseResult;
+use commands::progress;
+use commands::response;
+use commands::request::{self, Arg, FileArg, Opt};
+use fsrepo;
+use hash;
+use health::Health;
+use ipfs_commands::root;
+use util;
+
+use hyper::client::{self, Client};
+use hyper::header::ContentType;
+use hyper::method::Method;
+use hyper::server::{self, Handler, Listening, Server};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use rustc_serialize::json::{Json, ToJson};
+use hyper::header::Headers;
+use rust_multihash::Multihash;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Condvar, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+
+pub const API_PREFIX: &'static str = "/api/v0/";
+
+// where the daemon serves the API unless told otherwise
+pub const DEFAULT_API_ADDR: &'static str = "127.0.0.1:5001";
+
+// the encoding used unless a request asks for another one
+const API_ENCODING: &'static str = "json";
+
+// how many connections are handled at once. Each command runs in a thread of
+// its own, so that its progress can be streamed while it runs, alongside the
+// others unless one of them may modify the repo.
+const API_THREADS: usize = 4;
+
+// commands that only make sense with a terminal or files on the local
+// machine, or that take the repo lock the daemon holds
+const LOCAL_COMMANDS: &'static [&'static str] = &["shell", "batch", "config edit", "daemon",
+                                                  "init"];
+
+// commands that don't touch the repo, so there's no point sending them to the
+// daemon, though they can still be run over the API
+const CLIENT_COMMANDS: &'static [&'static str] = &["help", "completion"];
+
+// options a client mustn't set, since they point at files on the server
+const LOCAL_OPTIONS: &'static [&'static str] = &["repo-dir", "trace", "trace-file", "man-dir"];
+
+// options that only affect the process they're given to, so they aren't
+// forwarded to the daemon. --request-id is sent as the Idempotency-Key header.
+const CLIENT_OPTIONS: &'static [&'static str] = &["repo-dir", "trace", "trace-file", "debug",
+                                                  "request-id"];
+
+const IDEMPOTENCY_KEY_HEADER: &'static str = "Idempotency-Key";
+
+// how many mutating calls given an idempotency key are remembered
+const IDEMPOTENCY_CACHE_SIZE: usize = 256;
+
+pub struct ApiHandler {
+    root: Arc<Command>, // shared by all the requests
+    repo_path: PathBuf,
+    read_only: bool,
+    online: bool,
+    // held for reading by the commands that only read the repo, which can run
+    // at once, and for writing by those that may modify it, which run alone,
+    // as they would from the command line, since nothing stops two of them
+    // from writing the repo at once
+    running: Arc<RwLock<()>>,
+    // the mutating calls given an idempotency key, oldest first
+    replays: Arc<Mutex<VecDeque<Arc<Replay>>>>,
+    health: Health, // the daemon's, for `diag health`
+}
+
+// A mutating call given an idempotency key, and its result once it has one
+struct Replay {
+    key: String,
+    // the call's command line and the hash of its body, which a retry must repeat
+    args: Vec<String>,
+    body: Multihash,
+    result: Mutex<Option<Result<Vec<u8>, CommandError>>>,
+    done: Condvar,
+}
+
+impl Replay {
+    fn finish(&self, result: &Result<Vec<u8>, CommandError>) {
+        *self.result.lock().unwrap() = Some(result.clone());
+        self.done.notify_all();
+    }
+
+    // The call's result, once it's finished
+    fn wait(&self) -> Result<Vec<u8>, CommandError> {
+        let mut result = self.result.lock().unwrap();
+        while result.is_none() {
+            result = self.done.wait(result).unwrap();
+        }
+        result.clone().unwrap()
+    }
+}
+
+enum Remembered {
+    First(Arc<Replay>), // the call hasn't been made before, so it runs
+    Retry(Arc<Replay>), // the call made before, whose result it gets
+}
+
+// Looks up the call given the idempotency key of `call`, remembering `call`
+// as it if there's none. Only the last IDEMPOTENCY_CACHE_SIZE are kept.
+fn remember(replays: &Mutex<VecDeque<Arc<Replay>>>,
+            key: &str,
+            call: &ApiCall)
+            -> Result<Remembered, CommandError> {
+    let body = hash::SHA2_256.multihash(&call.body[..]);
+    let mut replays = replays.lock().unwrap();
+    if let Some(replay) = replays.iter().find(|replay| replay.key == key) {
+        if replay.args != call.args || replay.body != body {
+            return Err(CommandError::Client(format!("Idempotency key {:?} was already used \
+                                                     for another request",
+                                                    key)));
+        }
+        return Ok(Remembered::Retry(replay.clone()));
+    }
+
+    let replay = Arc::new(Replay {
+        key: key.to_string(),
+        args: call.args.clone(),
+        body: body,
+        result: Mutex::new(None),
+        done: Condvar::new(),
+    });
+    if replays.len() == IDEMPOTENCY_CACHE_SIZE {
+        replays.pop_front();
+    }
+    replays.push_back(replay.clone());
+    Ok(Remembered::First(replay))
+}
+
+impl ApiHandler {
+    pub fn new(repo_path: PathBuf, read_only: bool, online: bool, health: Health) -> Self {
+        ApiHandler {
+            root: root::make_command(),
+            repo_path: repo_path,
+            read_only: read_only,
+            online: online,
+            running: Arc::new(RwLock::new(())),
+            replays: Arc::new(Mutex::new(VecDeque::new())),
+            health: health,
+        }
+    }
+
+    // Reads the command line and the body of a request
+    fn call(&self, req: &mut server::Request) -> Result<ApiCall, CommandError> {
+        if req.method != Method::Get && req.method != Method::Post {
+            return Err(CommandError::Client(format!("Method {:?} not allowed; use GET or POST",
+                                                    req.method)));
+        }
+        let uri = match req.uri {
+            RequestUri::AbsolutePath(ref uri) => uri.clone(),
+            _ => return Err(CommandError::NotFound("Requests must be for a path".to_string())),
+        };
+        let mut body = Vec::new();
+        try!(req.read_to_end(&mut body)
+                .map_err(|e| format!("Error reading request body: {}", e)));
+
+        let mut call = try!(ApiCall::parse(&uri, &*self.root, body));
+        if let Some(key) = req.headers.get_raw(IDEMPOTENCY_KEY_HEADER).and_then(|v| v.first()) {
+            call.idempotency_key = Some(String::from_utf8_lossy(key).into_owned());
+        }
+        Ok(call)
+    }
+
+    // Starts running the command a call is for, sending the progress it
+    // reports to `events`. The thread returns the command's output. A retry
+    // of a mutating call returns the first call's result instead, with no
+    // progress.
+    fn start(&self,
+             call: ApiCall,
+             events: mpsc::Sender<progress::Event>)
+             -> JoinHandle<Result<Vec<u8>, CommandError>> {
+        let root = self.root.clone();
+        let repo_path = self.repo_path.clone();
+        let read_only = self.read_only;
+        let online = self.online;
+        let running = self.running.clone();
+        let replays = self.replays.clone();
+        let health = self.health.clone();
+        thread::spawn(move || {
+            let mutating = call.mutating;
+            let replay = match call.idempotency_key {
+                Some(ref key) if mutating => {
+                    match try!(remember(&replays, key, &call)) {
+                        Remembered::First(replay) => Some(replay),
+                        Remembered::Retry(replay) => return replay.wait(),
+                    }
+                }
+                _ => None,
+            };
+
+            let run = move || {
+                // a command that panicked doesn't stop the others from running
+                if mutating {
+                    let _running = running.write().unwrap_or_else(|e| e.into_inner());
+                    run_call(&*root, call, repo_path, read_only, online, health, events)
+                } else {
+                    let _running = running.read().unwrap_or_else(|e| e.into_inner());
+                    run_call(&*root, call, repo_path, read_only, online, health, events)
+                }
+            };
+            // a retry waiting on the result gets one even if the command panicked
+            let result = panic::catch_unwind(AssertUnwindSafe(run))
+                             .unwrap_or_else(|_| Err(panicked()));
+            if let Some(replay) = replay {
+                replay.finish(&result);
+            }
+            result
+        })
+    }
+}
+
+// Runs the command an API call is for, returning its output
+fn run_call(root: &Command,
+            call: ApiCall,
+            repo_path: PathBuf,
+            read_only: bool,
+            online: bool,
+            health: Health,
+            events: mpsc::Sender<progress::Event>)
+            -> Result<Vec<u8>, CommandError> {
+    let (cmd, mut args, opts) = try!(commands::cli::parse(call.args.into_iter(), root)
+                                         .map_err(CommandError::Client));
+    // there's no stdin to fall back on, so a file argument is always given,
+    // if only with no files
+    if let Some(file_arg) = cmd.get_arguments().find(|arg| arg.is_file()) {
+        let files = if call.body.is_empty() {
+            vec![]
+        } else {
+            vec![FileArg::from_data(call.body)]
+        };
+        args.push((file_arg.name(), Arg::new_file_arg(files)));
+    }
+
+    let node = try!(::construct_node(repo_path.clone()));
+    // a request can make itself read-only or offline, but not the reverse
+    let read_only = read_only || option_set(&opts, "read-only");
+    let online = online && !option_set(&opts, "offline");
+    let mut context = request::Context::new(repo_path, node.as_ref(), read_only, online);
+    context.health = Some(health);
+
+    let output = response::SharedBuffer::new();
+    try!(::run_parsed((cmd, args, opts),
+                      call.command_path,
+                      context,
+                      audit::ACTOR_API,
+                      Box::new(output.clone()),
+                      false,
+                      events));
+    Ok(output.contents())
+}
+
+fn panicked() -> CommandError {
+    CommandError::Internal("The command panicked".to_string())
+}
+
+// Waits for a command started with `ApiHandler::start`
+fn finished(running: JoinHandle<Result<Vec<u8>, CommandError>>) -> Result<Vec<u8>, CommandError> {
+    running.join().unwrap_or_else(|_| Err(panicked()))
+}
+
+impl Handler for ApiHandler {
+    fn handle<'a, 'k>(&'a self, mut req: server::Request<'a, 'k>, mut res: server::Response<'a>) {
+        let result = self.call(&mut req).and_then(|call| {
+            let (events, received) = mpsc::channel();
+            let (json, streamed) = (call.json, call.progress);
+            let running = self.start(call, events);
+            if streamed {
+                Ok(Reply::Streamed(received, running))
+            } else {
+                finished(running).map(|output| Reply::Output(json, output))
+            }
+        });
+
+        let (status, json, body) = match result {
+            Ok(Reply::Output(json, output)) => (StatusCode::Ok, json, output),
+            Ok(Reply::Streamed(received, running)) => {
+                if let Err(e) = stream(res, received, running) {
+                    debug!("Error streaming API response: {}", e);
+                }
+                return;
+            }
+            Err(e) => {
+                let status = match e {
+                    CommandError::Client(_) => StatusCode::BadRequest,
+                    CommandError::NotFound(_) => StatusCode::NotFound,
+                    CommandError::Internal(_) => StatusCode::InternalServerError,
+                };
+                (status, true, error_body(&e).into_bytes())
+            }
+        };
+
+        *res.status_mut() = status;
+        res.headers_mut().set(if json {
+            ContentType::json()
+        } else {
+            ContentType::plaintext()
+        });
+        if let Err(e) = res.send(&body) {
+            debug!("Error sending API response: {}", e);
+        }
+    }
+}
+
+// How a request that got as far as running its command is answered
+enum Reply {
+    Output(bool, Vec<u8>), // whether the output is JSON, and the output
+    Streamed(mpsc::Receiver<progress::Event>, JoinHandle<Result<Vec<u8>, CommandError>>),
+}
+
+// Sends each progress event as it's received, then the output or error of
+// the command once it's done
+fn stream(mut res: server::Response,
+          received: mpsc::Receiver<progress::Event>,
+          running: JoinHandle<Result<Vec<u8>, CommandError>>)
+          -> io::Result<()> {
+    res.headers_mut().set(ContentType::json());
+    let mut res = try!(res.start());
+    for event in received.iter() {
+        let mut obj = BTreeMap::new();
+        obj.insert("progress".to_string(), event.to_json());
+        try!(writeln!(res, "{}", Json::Object(obj)));
+        try!(res.flush());
+    }
+
+    let mut obj = BTreeMap::new();
+    match finished(running) {
+        Ok(output) => {
+            let output = String::from_utf8_lossy(&output).into_owned();
+            obj.insert("output".to_string(), output.to_json());
+        }
+        Err(e) => {
+            obj.insert("error".to_string(), error_json(&e));
+        }
+    }
+    try!(writeln!(res, "{}", Json::Object(obj)));
+    res.end()
+}
+
+fn option_set(opts: &[(commands::OptName, Opt)], name: &str) -> bool {
+    opts.iter().any(|&(opt_name, ref value)| opt_name == name && value.to_string() == "true")
+}
+
+fn error_json(e: &CommandError) -> Json {
+    let mut obj = BTreeMap::new();
+    obj.insert("Message".to_string(), e.message().to_json());
+    obj.insert("Code".to_string(), e.exit_code().to_json());
+    Json::Object(obj)
+}
+
+fn error_body(e: &CommandError) -> String {
+    format!("{}", error_json(e))
+}
+
+// The command line an API request amounts to
+struct ApiCall {
+    args: Vec<String>,
+    command_path: String, // as given to the audit log and stats
+    json: bool, // whether the output is JSON-encoded
+    mutating: bool, // whether the command may modify the repo
+    progress: bool, // whether the response is streamed with the command's progress
+    body: Vec<u8>, // the content of the command's file argument
+    // from the Idempotency-Key header or the request-id parameter
+    idempotency_key: Option<String>,
+}
+
+impl ApiCall {
+    fn parse(uri: &str, root: &Command, body: Vec<u8>) -> Result<Self, CommandError> {
+        let (path, query) = match uri.find('?') {
+            Some(i) => (&uri[..i], &uri[i + 1..]),
+            None => (uri, ""),
+        };
+        if !path.starts_with(API_PREFIX) && path != &API_PREFIX[..API_PREFIX.len() - 1] {
+            return Err(CommandError::NotFound(format!("No API endpoint at {}", path)));
+        }
+
+        let mut names = Vec::new();
+        let mut cmd = root;
+        for name in path[API_PREFIX.len() - 1..].split('/').filter(|name| !name.is_empty()) {
+            cmd = match cmd.get_subcommand(name) {
+                Some(subcmd) => subcmd,
+                None => return Err(CommandError::NotFound(format!("No API endpoint at {}", path))),
+            };
+            names.push(cmd.get_name().to_string());
+        }
+        let command_path = names.join(" ");
+        if LOCAL_COMMANDS.contains(&&command_path[..]) {
+            return Err(CommandError::Client(format!("`{}` can't be run over the API",
+                                                    command_path)));
+        }
+        let takes_files = cmd.get_arguments().any(|arg| arg.is_file());
+
+        let mut args = names;
+        let mut positional = Vec::new();
+        let mut encoding = None;
+        let mut progress = false;
+        let mut idempotency_key = None;
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let (key, value) = match param.find('=') {
+                Some(i) => (try!(percent_decode(&param[..i])),
+                            Some(try!(percent_decode(&param[i + 1..])))),
+                None => (try!(percent_decode(param)), None),
+            };
+            if key == "arg" {
+                if takes_files {
+                    return Err(CommandError::Client(format!("`{}` takes its file as the \
+                                                             request body, not as an arg",
+                                                            command_path)));
+                }
+                positional.push(value.unwrap_or(String::new()));
+                continue;
+            }
+            if LOCAL_OPTIONS.contains(&&key[..]) {
+                return Err(CommandError::Client(format!("Option --{} can't be given over the \
+                                                         API",
+                                                        key)));
+            }
+            if key == "encoding" {
+                encoding = value.clone();
+            }
+            if key == "progress" {
+                progress = value.as_ref().map_or(true, |value| value == "true");
+            }
+            if key == "request-id" {
+                idempotency_key = value.clone();
+            }
+            args.push(match value {
+                Some(value) => format!("--{}={}", key, value),
+                None => format!("--{}", key),
+            });
+        }
+        if encoding.is_none() {
+            args.push(format!("--encoding={}", API_ENCODING));
+        }
+        args.push("--".to_string());
+        args.extend(positional);
+
+        Ok(ApiCall {
+            args: args,
+            command_path: command_path,
+            json: encoding.as_ref().map_or(API_ENCODING, |e| &e[..]) == "json",
+            mutating: cmd.is_mutating(),
+            progress: progress,
+            body: body,
+            idempotency_key: idempotency_key,
+        })
+    }
+}
+
+// Decodes a query string component, where `+` stands for a space
+fn percent_decode(s: &str) -> Result<String, CommandError> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                if i + 2 >= bytes.len() ||
+                   !bytes[i + 1..i + 3].iter().all(|&b| (b as char).is_digit(16)) {
+                    return Err(CommandError::Client(format!("Bad escape in query: {:?}", s)));
+                }
+                let hex = &bytes[i + 1..i + 3];
+                // the digits are ASCII, so they are valid UTF-8
+                let hex = ::std::str::from_utf8(hex).unwrap();
+                decoded.push(u8::from_str_radix(hex, 16).unwrap());
+                i += 2;
+            }
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8(decoded)
+        .map_err(|_| CommandError::Client(format!("Query is not UTF-8: {:?}", s)))
+}
+
+// Starts serving the API for the repo at `repo_path` on `addr`, e.g.
+// DEFAULT_API_ADDR, reporting the daemon's `health` to `diag health`. The
+// server runs until the returned Listening is closed.
+pub fn serve(addr: &str,
+             repo_path: PathBuf,
+             read_only: bool,
+             online: bool,
+             health: Health)
+             -> Result<Listening, String> {
+    let server = try!(Server::http(addr)
+                          .map_err(|e| format!("Error listening on {}: {}", addr, e)));
+    server.handle_threads(ApiHandler::new(repo_path, read_only, online, health), API_THREADS)
+          .map_err(|e| format!("Error serving the API on {}: {}", addr, e))
+}
+
+// Whether a command always runs in the process it was given to, even with a
+// daemon up
+pub fn runs_locally(command_path: &str) -> bool {
+    LOCAL_COMMANDS.contains(&command_path) || CLIENT_COMMANDS.contains(&command_path)
+}
+
+// Sends a parsed command line to the daemon serving the repo at `repo_path`,
+// if there is one, and writes its output to `out`. Stdin is sent along for
+// the command's file argument if `stdin` is set. Returns None if there's no
+// daemon to send it to, so the command should run here instead.
+pub fn forward_to_daemon(repo_path: &Path,
+                         command_path: &str,
+                         parse: &ParseResult,
+                         stdin: bool,
+                         out: &mut Write)
+                         -> Option<Result<(), CommandError>> {
+    let addr = match fsrepo::read_api_file(repo_path) {
+        Ok(Some(addr)) => addr,
+        Ok(None) => return None,
+        Err(e) => return Some(Err(CommandError::Internal(e))),
+    };
+    let result = forward(&addr, command_path, parse, stdin, out);
+    // a daemon that was killed leaves its api file behind; if nothing holds
+    // the lock, the daemon is gone. A read-only repo is left as it is.
+    if result.is_none() && !option_set(&parse.2, "read-only") &&
+       fsrepo::lock_repo(repo_path).is_ok() {
+        let _ = fsrepo::remove_api_file(repo_path);
+    }
+    result
+}
+
+fn forward(addr: &str,
+           command_path: &str,
+           parse: &ParseResult,
+           stdin: bool,
+           out: &mut Write)
+           -> Option<Result<(), CommandError>> {
+    let &(cmd, ref args, ref opts) = parse;
+
+    let mut query = Vec::new();
+    let mut encoding_given = false;
+    for &(name, ref value) in opts.iter() {
+        if CLIENT_OPTIONS.contains(&name) {
+            continue;
+        }
+        encoding_given |= name == "encoding";
+        match *value {
+            Opt::Strings(ref values) => {
+                for value in values.iter() {
+                    query.push(format!("{}={}", name, percent_encode(value)));
+                }
+            }
+            _ => query.push(format!("{}={}", name, percent_encode(&value.to_string()))),
+        }
+    }
+    // the output is printed as the daemon sends it
+    if !encoding_given {
+        query.push(format!("encoding={}", commands::response::DEFAULT_ENCODING));
+    }
+
+    let mut body = Vec::new();
+    let mut files_given = false;
+    for &(_, ref arg) in args.iter() {
+        match *arg {
+            Arg::Strings(ref values) => {
+                for value in values.iter() {
+                    query.push(format!("arg={}", percent_encode(value)));
+                }
+            }
+            Arg::Files(ref files) => {
+                files_given = true;
+                body = match read_body(files) {
+                    Ok(body) => body,
+                    Err(e) => return Some(Err(e)),
+                };
+            }
+        }
+    }
+    // the daemon can't read our stdin, so it's sent along as it would have
+    // been read here
+    let takes_files = cmd.get_arguments().any(|arg| arg.is_file());
+    if stdin && takes_files && !files_given && util::stdin_is_piped() {
+        body = match read_body(&[FileArg::stdin()]) {
+            Ok(body) => body,
+            Err(e) => return Some(Err(e)),
+        };
+    }
+
+    let url = format!("http://{}{}{}?{}",
+                      addr,
+                      API_PREFIX,
+                      command_path.replace(" ", "/"),
+                      query.join("&"));
+    let mut headers = Headers::new();
+    for &(name, ref value) in opts.iter() {
+        if name == "request-id" {
+            headers.set_raw(IDEMPOTENCY_KEY_HEADER, vec![value.to_string().into_bytes()]);
+        }
+    }
+    let mut res = match Client::new().post(&url).headers(headers).body(&body[..]).send() {
+        Ok(res) => res,
+        Err(e) => {
+            debug!("Could not reach the daemon at {}, running locally: {}", addr, e);
+            return None;
+        }
+    };
+
+    // a streamed response is only started once the command is under way, so
+    // a request that fails before that is answered as usual
+    if res.status == StatusCode::Ok && option_set(opts, "progress") {
+        return Some(print_stream(res, out));
+    }
+
+    let mut output = Vec::new();
+    if let Err(e) = res.read_to_end(&mut output) {
+        return Some(Err(CommandError::Internal(format!("Error reading the daemon's response: {}",
+                                                        e))));
+    }
+    if res.status != StatusCode::Ok {
+        return Some(Err(error_from_body(&output)));
+    }
+    Some(write_output(&output, out))
+}
+
+fn write_output(output: &[u8], out: &mut Write) -> Result<(), CommandError> {
+    out.write_all(output)
+       .and_then(|_| out.flush())
+       .map_err(|e| CommandError::Internal(format!("Error writing output: {}", e)))
+}
+
+// Shows the progress events of a streamed response on stderr as they arrive,
+// then writes the output to `out`
+fn print_stream(res: client::Response, out: &mut Write) -> Result<(), CommandError> {
+    let (events, received) = mpsc::channel();
+    let bar = progress::show_on_stderr(received);
+    let result = read_stream(res, events);
+    let _ = bar.join();
+    write_output(try!(result).as_bytes(), out)
+}
+
+// Sends the progress events of a streamed response to `events`, returning the
+// output that ends it
+fn read_stream(res: client::Response,
+               events: mpsc::Sender<progress::Event>)
+               -> Result<String, CommandError> {
+    for line in BufReader::new(res).lines() {
+        let line = try!(line.map_err(|e| format!("Error reading the daemon's response: {}", e)));
+        let json = try!(Json::from_str(&line).map_err(|_| {
+            format!("The daemon sent an invalid line: {:?}", line)
+        }));
+        if let Some(event) = json.find("progress").and_then(progress::Event::from_json) {
+            let _ = events.send(event);
+        } else if let Some(output) = json.find("output").and_then(|output| output.as_string()) {
+            return Ok(output.to_string());
+        } else if let Some(error) = json.find("error") {
+            return Err(error_from_body(format!("{}", error).as_bytes()));
+        }
+    }
+    Err(CommandError::Internal("The daemon's response ended before the command did".to_string()))
+}
+
+// The content of a command's file argument, as sent in a request body. Only
+// one file can be sent, and directories can't be sent at all.
+fn read_body(files: &[FileArg]) -> Result<Vec<u8>, CommandError> {
+    if files.len() > 1 || files.iter().any(|f| f.path().map_or(false, |path| path.is_dir())) {
+        return Err(CommandError::Client("Only one file can be sent to the daemon; add several \
+                                         files or directories by stopping the daemon first"
+                                            .to_string()));
+    }
+    let mut body = Vec::new();
+    if let Some(file) = files.first() {
+        try!(try!(file.open())
+                 .read_to_end(&mut body)
+                 .map_err(|e| format!("Error reading {:?}: {}", file, e)));
+    }
+    Ok(body)
+}
+
+// The CommandError an error response stands for
+fn error_from_body(body: &[u8]) -> CommandError {
+    let json = ::std::str::from_utf8(body).ok().and_then(|s| Json::from_str(s).ok());
+    let message = json.as_ref()
+                      .and_then(|json| json.find("Message"))
+                      .and_then(|message| message.as_string())
+                      .map(|message| message.to_string())
+                      .unwrap_or(format!("The daemon sent an invalid error: {:?}",
+                                         String::from_utf8_lossy(body)));
+    match json.as_ref().and_then(|json| json.find("Code")).and_then(|code| code.as_i64()) {
+        Some(2) => CommandError::Client(message),
+        Some(3) => CommandError::NotFound(message),
+        _ => CommandError::Internal(message),
+    }
+}
+
+// Encodes a query string component
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for &b in s.as_bytes() {
+        match b {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{remember, ApiCall, Remembered, IDEMPOTENCY_CACHE_SIZE};
+    use ipfs_commands::root;
+
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    fn put(id: &str, body: &[u8]) -> ApiCall {
+        let uri = format!("/api/v0/block/put?request-id={}", id);
+        ApiCall::parse(&uri, &*root::make_command(), body.to_vec()).ok().unwrap()
+    }
+
+    #[test]
+    fn retried_calls_get_the_first_ones_result() {
+        let replays = Mutex::new(VecDeque::new());
+        let call = put("a", b"data");
+        assert_eq!(call.idempotency_key, Some("a".to_string()));
+        let first = match remember(&replays, "a", &call).ok().unwrap() {
+            Remembered::First(replay) => replay,
+            Remembered::Retry(_) => panic!("a new call was taken for a retry"),
+        };
+        first.finish(&Ok(b"output".to_vec()));
+        match remember(&replays, "a", &put("a", b"data")).ok().unwrap() {
+            Remembered::Retry(replay) => assert_eq!(replay.wait().ok(), Some(b"output".to_vec())),
+            Remembered::First(_) => panic!("a retry was run again"),
+        }
+        // the key can't be reused for something else
+        assert!(remember(&replays, "a", &put("a", b"other")).is_err());
+    }
+
+    #[test]
+    fn only_the_latest_calls_are_remembered() {
+        let replays = Mutex::new(VecDeque::new());
+        for i in 0..IDEMPOTENCY_CACHE_SIZE + 1 {
+            let key = format!("{}", i);
+            assert!(remember(&replays, &key, &put(&key, b"data")).is_ok());
+        }
+        assert_eq!(replays.lock().unwrap().len(), IDEMPOTENCY_CACHE_SIZE);
+        match remember(&replays, "0", &put("0", b"data")).ok().unwrap() {
+            Remembered::First(_) => {}
+            Remembered::Retry(_) => panic!("the oldest call was kept"),
+        }
+    }
+}
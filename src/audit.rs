@@ -0,0 +1,158 @@
+// An append-only record of every command run against the repo that may
+// modify it. Each line of <repo>/logs/audit.log is one JSON-encoded entry.
+
+use commands::CommandError;
+use commands::request;
+use fsrepo;
+use util;
+
+use rustc_serialize::json::{self, Json, ToJson};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+const AUDIT_LOG_FILE: &'static str = "audit.log";
+
+// Who ran a command: the command line, or a client of the HTTP API
+pub const ACTOR_CLI: &'static str = "cli";
+pub const ACTOR_API: &'static str = "api";
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct AuditEntry {
+    pub time: u64, // seconds since the unix epoch
+    pub actor: String,
+    pub command: String,
+    pub options: Vec<String>,
+    pub result: String, // "ok", or the error the command failed with
+}
+
+impl AuditEntry {
+    pub fn new(actor: &str,
+               command: String,
+               req: &request::Request,
+               result: &Result<(), CommandError>)
+               -> Self {
+        AuditEntry {
+            time: req.context.clock.unix_time(),
+            actor: actor.to_string(),
+            command: command,
+            options: normalize_options(req),
+            result: match *result {
+                Ok(()) => "ok".to_string(),
+                Err(ref e) => e.to_string(),
+            },
+        }
+    }
+}
+
+impl ToJson for AuditEntry {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert("time".to_string(), self.time.to_json());
+        obj.insert("actor".to_string(), self.actor.to_json());
+        obj.insert("command".to_string(), self.command.to_json());
+        obj.insert("options".to_string(), self.options.to_json());
+        obj.insert("result".to_string(), self.result.to_json());
+        Json::Object(obj)
+    }
+}
+
+// The options as they'd be given on the command line, by canonical name and
+// sorted, so the same invocation is always recorded the same way
+fn normalize_options(req: &request::Request) -> Vec<String> {
+    let mut options: Vec<String> = req.options()
+                                      .flat_map(|(name, opt)| {
+                                          match *opt {
+                                              request::Opt::Bool(true) => {
+                                                  vec![format!("--{}", name)]
+                                              }
+                                              request::Opt::Bool(false) => {
+                                                  vec![format!("--{} false", name)]
+                                              }
+                                              request::Opt::String(ref s) => {
+                                                  vec![format!("--{} {}", name, s)]
+                                              }
+                                              request::Opt::Int(n) => {
+                                                  vec![format!("--{} {}", name, n)]
+                                              }
+                                              request::Opt::Strings(ref v) => {
+                                                  v.iter()
+                                                   .map(|s| format!("--{} {}", name, s))
+                                                   .collect()
+                                              }
+                                          }
+                                      })
+                                      .collect();
+    options.sort();
+    options
+}
+
+fn log_file(mut repo_path: PathBuf) -> PathBuf {
+    repo_path.push(fsrepo::LOGS_DIR);
+    repo_path.push(AUDIT_LOG_FILE);
+    repo_path
+}
+
+pub fn record(repo_path: PathBuf, entry: &AuditEntry) -> Result<(), String> {
+    let mut logs_dir = repo_path.clone();
+    logs_dir.push(fsrepo::LOGS_DIR);
+    try!(util::ensure_dir_writable(&logs_dir)
+             .map_err(|e| format!("Error checking writability of logs dir: {}", e)));
+
+    let encoded = try!(json::encode(entry)
+                           .map_err(|e| format!("Error encoding audit log entry: {}", e)));
+    let path = log_file(repo_path);
+    let mut file = try!(OpenOptions::new()
+                            .append(true)
+                            .create(true)
+                            .open(&path)
+                            .map_err(|e| format!("Error opening audit log {:?}: {}", path, e)));
+    writeln!(file, "{}", encoded).map_err(|e| format!("Error writing audit log {:?}: {}", path, e))
+}
+
+// The recorded entries, oldest first
+pub fn read(repo_path: PathBuf) -> Result<Vec<AuditEntry>, String> {
+    let path = log_file(repo_path);
+    if !try!(util::file_exists(&path).map_err(|e| format!("{}", e))) {
+        return Ok(vec![]);
+    }
+
+    let file = try!(File::open(&path)
+                        .map_err(|e| format!("Error opening audit log {:?}: {}", path, e)));
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = try!(line.map_err(|e| format!("Error reading audit log {:?}: {}", path, e)));
+        if line.is_empty() {
+            continue;
+        }
+        entries.push(try!(json::decode(&line).map_err(|e| {
+            format!("Error decoding audit log entry {:?}: {}", line, e)
+        })));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuditEntry, ACTOR_CLI};
+    use clock::MockClock;
+    use commands::cli;
+    use commands::request::{Context, Request};
+    use ipfs_commands::root;
+
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    #[test]
+    fn entries_are_timestamped_by_the_context_clock() {
+        let root = root::make_command();
+        let mut context = Context::new(PathBuf::from("/nonexistent/ipfs-repo"), None, false, true);
+        context.clock = Arc::new(MockClock::new(1500000000));
+        let args = vec!["repo".to_string(), "compress".to_string()];
+        let (cmd, args, opts) = cli::parse(args.into_iter(), &*root).unwrap();
+        let req = Request::new(cmd, args, opts, context);
+        let entry = AuditEntry::new(ACTOR_CLI, "repo compress".to_string(), &req, &Ok(()));
+        assert_eq!(entry.time, 1500000000);
+    }
+}
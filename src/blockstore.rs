@@ -1,9 +1,13 @@
 use block::Block;
+use crypto::BlockCipher;
+use delta::{self, DeltaIndex};
+use trace;
 use util;
 
-use atomicwrites::{AtomicFile, DisallowOverwrite};
+use atomicwrites::{AtomicFile, AllowOverwrite, DisallowOverwrite};
 use rust_multihash::Multihash;
-use rustc_serialize::hex::ToHex;
+use rustc_serialize::hex::{FromHex, ToHex};
+use zstd;
 use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::{self, Write, Read};
@@ -13,9 +17,32 @@ const BLOCKFILE_EXT: &'static str = ".data";
 const BLOCKSTORE_PREFIX_LENGTH: u8 = 4; // in bytes
 pub const BLOCKSTORE_DIR: &'static str = "blocks";
 
+// Marks a block file whose contents are zstd-compressed. Encoded dag nodes never
+// begin with a zero byte, so uncompressed blocks can't be mistaken for compressed ones.
+const COMPRESSED_BLOCK_HEADER: &'static [u8] = b"\x00zst";
+const COMPRESSION_LEVEL: i32 = 3;
+
+// Marks a block file whose contents are encrypted. Compression, if enabled, is
+// applied before encryption, so the decrypted contents may be compressed.
+const ENCRYPTED_BLOCK_HEADER: &'static [u8] = b"\x00enc";
+
+// Marks a block stored as a delta against another block, whose multihash
+// follows, prefixed with its length. The delta itself may be compressed, and
+// the whole is encrypted, if enabled. Without this header the (decrypted)
+// contents are either compressed or don't begin with a zero byte, so they
+// can't be mistaken for a delta.
+const DELTA_BLOCK_HEADER: &'static [u8] = b"\x00dlt";
+
+// a delta is only kept if it's at most 1/MAX_DELTA_RATIO of the block's size
+const MAX_DELTA_RATIO: usize = 2;
+
 pub struct Blockstore {
     path: PathBuf,
     hex_prefix_length: u8, // length of the prefix in hex digits
+    compress: bool, // whether newly put blocks are compressed
+    cipher: Option<BlockCipher>, // if set, newly put blocks are encrypted
+    delta_encode: bool, // whether newly put blocks are stored as deltas where possible
+    deltas: DeltaIndex, // kept up to date even when delta_encode is off
 }
 
 impl Blockstore {
@@ -24,13 +51,34 @@ impl Blockstore {
     // <blockstore dir>/<hex encoding of [x1 ... xk]/
     // where k = prefix_len
     pub fn new(path: PathBuf) -> Self {
+        Self::with_compression(path, false)
+    }
+
+    pub fn with_compression(path: PathBuf, compress: bool) -> Self {
         Blockstore {
+            deltas: DeltaIndex::new(path.join(delta::DELTA_INDEX_FILE)),
             path: path,
             hex_prefix_length: 2 * BLOCKSTORE_PREFIX_LENGTH,
+            compress: compress,
+            cipher: None,
+            delta_encode: false,
         }
     }
 
+    pub fn with_cipher(mut self, cipher: BlockCipher) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    // Stores newly put blocks that are similar to one already in the store
+    // as a delta against it
+    pub fn with_delta_encoding(mut self, delta_encode: bool) -> Self {
+        self.delta_encode = delta_encode;
+        self
+    }
+
     pub fn has(&self, multihash: &Multihash) -> Result<bool, String> {
+        let _span = trace::span("Blockstore::has");
         util::file_exists(self.block_file(multihash)).map_err(|e| {
             format!("Error checking for existence of file in Blockstore::has: {}",
                     e)
@@ -38,6 +86,27 @@ impl Blockstore {
     }
 
     pub fn get(&self, hash: &Multihash) -> Result<Block, String> {
+        let _span = trace::span("Blockstore::get");
+        let (base, data) = try!(self.read(hash));
+        let data = match base {
+            None => data,
+            Some(base) => {
+                let (base_base, base_data) = try!(self.read(&base));
+                // bases are always stored in full
+                if base_base.is_some() {
+                    return Err(format!("Base block {} of {} is itself a delta", base, hash));
+                }
+                try!(delta::apply(&base_data[..], &data[..]).map_err(|e| {
+                    format!("Error applying the delta of block {} to {}: {}", hash, base, e)
+                }))
+            }
+        };
+        Ok(Block::with_hash(data, hash.clone()))
+    }
+
+    // The contents of a block file, decoded, along with the base block if
+    // it's stored as a delta
+    fn read(&self, hash: &Multihash) -> Result<(Option<Multihash>, Vec<u8>), String> {
         let fname = self.block_file(hash);
         trace!("Blockstore::get, file name = {:?}", fname);
         let mut file = try!(File::open(&fname).map_err(|e| {
@@ -55,10 +124,16 @@ impl Blockstore {
                              hash,
                              e)
                  }));
-        Ok(Block::with_hash(data, hash.clone()))
+        decode_block_data(hash, &data[..], self.cipher.as_ref()).map_err(|e| {
+            format!("Error decoding file {:?} for hash {} in Blockstore::get: {}",
+                    fname,
+                    hash,
+                    e)
+        })
     }
 
     pub fn put(&self, multihash: &Multihash, data: &[u8]) -> Result<(), String> {
+        let _span = trace::span("Blockstore::put");
         match self.has(multihash) {
             Ok(true) => return Ok(()),
             _ => {}
@@ -72,9 +147,196 @@ impl Blockstore {
 
         dir.push(filename);
         let file_path = dir; // rename for clarity
+        let features = if self.delta_encode && data.len() >= delta::MIN_BLOCK_SIZE {
+            Some(delta::features(data))
+        } else {
+            None
+        };
+        let similar = match features {
+            Some(ref features) => try!(self.smallest_delta(data, features)),
+            None => None,
+        };
+        let encoded = {
+            let (base, payload) = match similar {
+                Some((ref base, ref delta)) => (Some(base), &delta[..]),
+                None => (None, data),
+            };
+            try!(encode_block_data(multihash, payload, base, self.compress, self.cipher.as_ref())
+                     .map_err(|e| format!("Error encoding block for put: {}", e)))
+        };
         let file = AtomicFile::new(file_path, DisallowOverwrite);
-        file.write(|f| f.write_all(data))
-            .map_err(|e| format!("Error writing block file for put: {}", e))
+        try!(file.write(|f| f.write_all(&encoded[..]))
+                 .map_err(|e| format!("Error writing block file for put: {}", e)));
+
+        match (similar, features) {
+            (Some((base, _)), _) => self.deltas.add_delta(multihash, &base),
+            (None, Some(features)) => self.deltas.add_base(multihash, &features),
+            (None, None) => Ok(()),
+        }
+    }
+
+    // The smallest delta of `data` against a base block with some of the same
+    // features, if one is small enough to be worth storing
+    fn smallest_delta(&self,
+                      data: &[u8],
+                      features: &[u64])
+                      -> Result<Option<(Multihash, Vec<u8>)>, String> {
+        let mut smallest: Option<(Multihash, Vec<u8>)> = None;
+        for base in try!(self.deltas.similar(features)) {
+            // the index may name blocks that have since been removed by hand
+            let base_data = match self.read(&base) {
+                Ok((None, base_data)) => base_data,
+                _ => continue,
+            };
+            let delta = delta::encode(&base_data[..], data);
+            if delta.len() * MAX_DELTA_RATIO > data.len() {
+                continue;
+            }
+            if smallest.as_ref().map_or(true, |&(_, ref other)| delta.len() < other.len()) {
+                smallest = Some((base, delta));
+            }
+        }
+        Ok(smallest)
+    }
+
+    // Rewrites every block file in the store using the given compression setting
+    // and cipher (None meaning plaintext). Blocks already stored that way are
+    // left as they are, and blocks that can't be decoded with the current
+    // cipher are tried with the new one, so an interrupted migration can simply
+    // be rerun. Returns the number of block files that were rewritten.
+    pub fn migrate(&self, compress: bool, cipher: Option<&BlockCipher>) -> Result<usize, String> {
+        let mut rewritten = 0;
+        for (hash, file_path) in try!(self.block_files()) {
+            let mut raw = Vec::new();
+            try!(File::open(&file_path)
+                     .and_then(|mut f| f.read_to_end(&mut raw))
+                     .map_err(|e| format!("Error reading block file {:?}: {}", file_path, e)));
+            if is_encoded_as(&hash, &raw[..], compress, cipher) {
+                continue;
+            }
+
+            // deltas stay deltas against the same base, so the base doesn't
+            // have to be read
+            let (base, data) = match decode_block_data(&hash, &raw[..], self.cipher.as_ref()) {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    if cipher.is_none() {
+                        return Err(format!("Error decoding block file {:?}: {}", file_path, e));
+                    }
+                    try!(decode_block_data(&hash, &raw[..], cipher).map_err(|e| {
+                        format!("Error decoding block file {:?}: {}", file_path, e)
+                    }))
+                }
+            };
+            let encoded = try!(encode_block_data(&hash, &data[..], base.as_ref(), compress, cipher)
+                                   .map_err(|e| {
+                                       format!("Error encoding block file {:?}: {}", file_path, e)
+                                   }));
+
+            let file = AtomicFile::new(&file_path, AllowOverwrite);
+            try!(file.write(|f| f.write_all(&encoded[..]))
+                     .map_err(|e| format!("Error rewriting block file {:?}: {}", file_path, e)));
+            rewritten += 1;
+        }
+        Ok(rewritten)
+    }
+
+    // Blocks stored as deltas against the block are rewritten in full first,
+    // so they can still be read without it
+    pub fn delete(&self, hash: &Multihash) -> Result<(), String> {
+        for delta in try!(self.deltas.deltas_of(hash)) {
+            let block = match self.get(&delta) {
+                Ok(block) => block,
+                // a delta that was removed by hand
+                Err(_) => continue,
+            };
+            let encoded = try!(encode_block_data(&delta,
+                                                 block.get_data(),
+                                                 None,
+                                                 self.compress,
+                                                 self.cipher.as_ref())
+                                   .map_err(|e| format!("Error encoding block {}: {}", delta, e)));
+            let file = AtomicFile::new(self.block_file(&delta), AllowOverwrite);
+            try!(file.write(|f| f.write_all(&encoded[..]))
+                     .map_err(|e| format!("Error rewriting block {} in full: {}", delta, e)));
+            try!(self.deltas.rewritten_in_full(&delta));
+        }
+
+        let fname = self.block_file(hash);
+        try!(fs::remove_file(&fname).map_err(|e| {
+            format!("Error removing file {:?} for hash {} in Blockstore::delete: {}",
+                    fname,
+                    hash,
+                    e)
+        }));
+        self.deltas.removed(hash)
+    }
+
+    // The size in bytes of the file `put` writes for `data` if it's stored in
+    // full, i.e. what the block adds to `size_on_disk` at most, which the
+    // repo's storage_max is checked against
+    pub fn encoded_size(&self, multihash: &Multihash, data: &[u8]) -> Result<u64, String> {
+        let encoded = try!(encode_block_data(multihash,
+                                             data,
+                                             None,
+                                             self.compress,
+                                             self.cipher.as_ref())
+                               .map_err(|e| format!("Error encoding block: {}", e)));
+        Ok(encoded.len() as u64)
+    }
+
+    // Total size in bytes of all block files in the store
+    pub fn size_on_disk(&self) -> Result<u64, String> {
+        let mut total = 0;
+        for (_, file_path) in try!(self.block_files()) {
+            let metadata = try!(fs::metadata(&file_path).map_err(|e| {
+                format!("Error reading metadata of block file {:?}: {}", file_path, e)
+            }));
+            total += metadata.len();
+        }
+        Ok(total)
+    }
+
+    pub fn cipher(&self) -> Option<&BlockCipher> {
+        self.cipher.as_ref()
+    }
+
+    pub fn compresses(&self) -> bool {
+        self.compress
+    }
+
+    // Lists the multihash and file path of every block in the store
+    fn block_files(&self) -> Result<Vec<(Multihash, PathBuf)>, String> {
+        let mut files = Vec::new();
+        let prefix_dirs = try!(fs::read_dir(&self.path).map_err(|e| {
+            format!("Error reading blockstore directory {:?}: {}", self.path, e)
+        }));
+
+        for prefix_dir in prefix_dirs {
+            let prefix_dir = try!(prefix_dir.map_err(|e| format!("{}", e))).path();
+            if !prefix_dir.is_dir() {
+                continue;
+            }
+
+            let entries = try!(fs::read_dir(&prefix_dir).map_err(|e| {
+                format!("Error reading blockstore directory {:?}: {}", prefix_dir, e)
+            }));
+            for entry in entries {
+                let path = try!(entry.map_err(|e| format!("{}", e))).path();
+                let hash = match path.file_name().and_then(|name| name.to_str()) {
+                    Some(name) if name.ends_with(BLOCKFILE_EXT) => {
+                        let hex = &name[..name.len() - BLOCKFILE_EXT.len()];
+                        match hex.from_hex() {
+                            Ok(bytes) => Multihash::from_vec(bytes),
+                            Err(_) => continue,
+                        }
+                    }
+                    _ => continue,
+                };
+                files.push((hash, path));
+            }
+        }
+        Ok(files)
     }
 
     fn block_dir_and_file(&self, multihash: &Multihash) -> (PathBuf, String) {
@@ -92,6 +354,120 @@ impl Blockstore {
     }
 }
 
+// The multihash is authenticated along with encrypted block data, so that
+// block files can't be swapped for one another undetected. If `base` is
+// given, `data` is a delta against it.
+fn encode_block_data(hash: &Multihash,
+                     data: &[u8],
+                     base: Option<&Multihash>,
+                     compress: bool,
+                     cipher: Option<&BlockCipher>)
+                     -> Result<Vec<u8>, String> {
+    // raw blocks (from `ipfs block put`) may begin with a zero byte, so those are
+    // always compressed to keep them from being taken for a header
+    let compress = compress || data.first() == Some(&0);
+    let mut encoded = if compress {
+        let compressed = try!(zstd::encode_all(data, COMPRESSION_LEVEL)
+                                  .map_err(|e| format!("Error compressing block: {}", e)));
+        let mut v = Vec::with_capacity(COMPRESSED_BLOCK_HEADER.len() + compressed.len());
+        v.extend_from_slice(COMPRESSED_BLOCK_HEADER);
+        v.extend_from_slice(&compressed[..]);
+        v
+    } else {
+        data.to_vec()
+    };
+
+    if let Some(base) = base {
+        let mut v = Vec::with_capacity(DELTA_BLOCK_HEADER.len() + base.len() + 1 + encoded.len());
+        v.extend_from_slice(DELTA_BLOCK_HEADER);
+        delta::push_varint(&mut v, base.len() as u64);
+        v.extend_from_slice(&base[..]);
+        v.extend_from_slice(&encoded[..]);
+        encoded = v;
+    }
+
+    if let Some(cipher) = cipher {
+        let sealed = cipher.seal(&encoded[..], &hash.clone().into_bytes()[..]);
+        encoded = Vec::with_capacity(ENCRYPTED_BLOCK_HEADER.len() + sealed.len());
+        encoded.extend_from_slice(ENCRYPTED_BLOCK_HEADER);
+        encoded.extend_from_slice(&sealed[..]);
+    }
+    Ok(encoded)
+}
+
+// Returns the base block along with the delta if the block is stored as one
+fn decode_block_data(hash: &Multihash,
+                     data: &[u8],
+                     cipher: Option<&BlockCipher>)
+                     -> Result<(Option<Multihash>, Vec<u8>), String> {
+    let decrypted = if data.starts_with(ENCRYPTED_BLOCK_HEADER) {
+        let cipher = match cipher {
+            None => return Err("Block is encrypted, but no repo key is loaded".to_string()),
+            Some(cipher) => cipher,
+        };
+        try!(cipher.open(&data[ENCRYPTED_BLOCK_HEADER.len()..],
+                         &hash.clone().into_bytes()[..]))
+    } else {
+        data.to_vec()
+    };
+
+    let (base, body) = try!(split_delta(&decrypted[..]));
+    if !body.starts_with(COMPRESSED_BLOCK_HEADER) {
+        return Ok((base, body.to_vec()));
+    }
+    let data = try!(zstd::decode_all(&body[COMPRESSED_BLOCK_HEADER.len()..])
+                        .map_err(|e| format!("Error decompressing block: {}", e)));
+    Ok((base, data))
+}
+
+// The base block and the rest of a decrypted block, if it's stored as a delta
+fn split_delta(decrypted: &[u8]) -> Result<(Option<Multihash>, &[u8]), String> {
+    if !decrypted.starts_with(DELTA_BLOCK_HEADER) {
+        return Ok((None, decrypted));
+    }
+    let mut pos = DELTA_BLOCK_HEADER.len();
+    let len = try!(delta::read_varint(decrypted, &mut pos)) as usize;
+    if len > decrypted.len() - pos {
+        return Err("Delta block ends in the middle of its base's hash".to_string());
+    }
+    let base = Multihash::from_vec(decrypted[pos..pos + len].to_vec());
+    Ok((Some(base), &decrypted[pos + len..]))
+}
+
+// Whether `raw` is the block as `encode_block_data` would store it with this
+// compression setting and cipher, but for the nonce
+fn is_encoded_as(hash: &Multihash,
+                 raw: &[u8],
+                 compress: bool,
+                 cipher: Option<&BlockCipher>)
+                 -> bool {
+    if raw.starts_with(ENCRYPTED_BLOCK_HEADER) != cipher.is_some() {
+        return false;
+    }
+    let decrypted = match cipher {
+        Some(cipher) => {
+            let aad = hash.clone().into_bytes();
+            match cipher.open(&raw[ENCRYPTED_BLOCK_HEADER.len()..], &aad[..]) {
+                Ok(decrypted) => decrypted,
+                // sealed with another key
+                Err(_) => return false,
+            }
+        }
+        None => raw.to_vec(),
+    };
+    let body = match split_delta(&decrypted[..]) {
+        Ok((_, body)) => body,
+        Err(_) => return false,
+    };
+    if body.starts_with(COMPRESSED_BLOCK_HEADER) == compress {
+        return true;
+    }
+    // blocks beginning with a zero byte are compressed either way
+    !compress &&
+    zstd::decode_all(&body[COMPRESSED_BLOCK_HEADER.len()..])
+        .map(|data| data.first() == Some(&0))
+        .unwrap_or(false)
+}
 
 fn make_prefix_dir<P: AsRef<Path>>(path: P) -> Result<(), io::Error> {
     try!(fs::create_dir(&path));
@@ -100,3 +476,106 @@ fn make_prefix_dir<P: AsRef<Path>>(path: P) -> Result<(), io::Error> {
     let f = try!(fs::File::open(&path));
     f.sync_all()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Blockstore;
+
+    use rust_multihash::Multihash;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn blockstore_path() -> PathBuf {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+        let path = env::temp_dir().join(format!("blockstore-test-{}", nanos));
+        fs::create_dir(&path).unwrap();
+        path
+    }
+
+    // blocks are filed by the first bytes of their hash, so each test block
+    // gets its own
+    fn hash(n: u8) -> Multihash {
+        Multihash::from_vec(vec![n; 34])
+    }
+
+    fn noise(len: usize) -> Vec<u8> {
+        let mut x: u64 = 1;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    }
+
+    fn edited(data: &[u8]) -> Vec<u8> {
+        let mut edited = data.to_vec();
+        edited[1000..1010].copy_from_slice(b"0123456789");
+        edited
+    }
+
+    #[test]
+    fn blocks_take_up_their_encoded_size() {
+        let path = blockstore_path();
+        let bs = Blockstore::new(path.clone());
+        // a leading zero byte gets a block compressed even in an uncompressed store
+        let mut data = noise(1000);
+        data[0] = 0;
+        let size = bs.encoded_size(&hash(1), &data[..]).unwrap();
+        assert!(size != data.len() as u64);
+        bs.put(&hash(1), &data[..]).unwrap();
+        assert_eq!(bs.size_on_disk(), Ok(size));
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn similar_blocks_are_stored_as_deltas() {
+        let path = blockstore_path();
+        let bs = Blockstore::new(path.clone()).with_delta_encoding(true);
+        let base = noise(4096);
+        bs.put(&hash(1), &base[..]).unwrap();
+        bs.put(&hash(2), &edited(&base[..])[..]).unwrap();
+
+        assert_eq!(bs.deltas.deltas_of(&hash(1)), Ok(vec![hash(2)]));
+        assert!(bs.size_on_disk().unwrap() < 2 * 4096);
+        assert_eq!(bs.get(&hash(1)).unwrap().get_data(), &base[..]);
+        assert_eq!(bs.get(&hash(2)).unwrap().get_data(), &edited(&base[..])[..]);
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn deltas_are_rewritten_in_full_when_their_base_is_deleted() {
+        let path = blockstore_path();
+        let bs = Blockstore::with_compression(path.clone(), true).with_delta_encoding(true);
+        let base = noise(4096);
+        bs.put(&hash(1), &base[..]).unwrap();
+        bs.put(&hash(2), &edited(&base[..])[..]).unwrap();
+        bs.delete(&hash(1)).unwrap();
+
+        assert_eq!(bs.has(&hash(1)), Ok(false));
+        assert_eq!(bs.deltas.deltas_of(&hash(1)), Ok(vec![]));
+        assert_eq!(bs.get(&hash(2)).unwrap().get_data(), &edited(&base[..])[..]);
+        // and can still be read once the store is reopened
+        let reopened = Blockstore::new(path.clone());
+        assert_eq!(reopened.get(&hash(2)).unwrap().get_data(), &edited(&base[..])[..]);
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn blocks_unlike_any_stored_are_stored_in_full() {
+        let path = blockstore_path();
+        let bs = Blockstore::new(path.clone()).with_delta_encoding(true);
+        let base = noise(4096);
+        let other: Vec<u8> = base.iter().rev().cloned().collect();
+        bs.put(&hash(1), &base[..]).unwrap();
+        bs.put(&hash(2), &other[..]).unwrap();
+
+        assert_eq!(bs.deltas.deltas_of(&hash(1)), Ok(vec![]));
+        assert_eq!(bs.get(&hash(2)).unwrap().get_data(), &other[..]);
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
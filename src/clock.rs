@@ -0,0 +1,68 @@
+// The time as commands see it, through request::Context. Outside tests it's
+// the system's; tests give commands a MockClock instead, which only moves
+// when it's told to, so the timestamps and latencies they record can be
+// checked.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+#[cfg(test)]
+use std::sync::Mutex;
+
+pub trait Clock: Send + Sync {
+    // Seconds since the unix epoch, for timestamps
+    fn unix_time(&self) -> u64;
+    // The time since some point the clock chose, for measuring how long
+    // things take. It never goes backwards.
+    fn monotonic(&self) -> Duration;
+}
+
+pub struct SystemClock {
+    created: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock { created: Instant::now() }
+    }
+}
+
+impl Clock for SystemClock {
+    fn unix_time(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+
+    fn monotonic(&self) -> Duration {
+        self.created.elapsed()
+    }
+}
+
+// A clock that stands still until it's advanced
+#[cfg(test)]
+pub struct MockClock {
+    now: Mutex<Duration>, // since the unix epoch
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new(unix_time: u64) -> Self {
+        MockClock { now: Mutex::new(Duration::from_secs(unix_time)) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now = *now + by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn unix_time(&self) -> u64 {
+        self.now.lock().unwrap().as_secs()
+    }
+
+    fn monotonic(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+}
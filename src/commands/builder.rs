@@ -0,0 +1,282 @@
+use commands::{CommandName, Opt, Argument, HelpText, Command, CommandOptions, CommandError,
+               RunFn};
+use commands::request;
+use commands::response::Response;
+
+use std::collections::HashMap;
+use std::slice;
+use std::sync::Arc;
+
+pub type PostRunFn = fn(&request::Request, &mut Response) -> Result<(), CommandError>;
+
+// Declares a command step by step and checks the definition when it's done:
+//
+//     CommandBuilder::new("put", PutHelpText)
+//         .option(hash)
+//         .arg(arg_data)
+//         .mutating()
+//         .validate(validate_put)
+//         .run(run_put)
+//
+// `alias` adds another name the parent command finds it by, and `hidden`
+// leaves it out of help and completion. `run` takes the function that
+// carries out the command and returns it, ready for `subcommand` or to be
+// the root. The steps the command doesn't name do nothing.
+pub struct CommandBuilder {
+    name: CommandName,
+    aliases: Vec<CommandName>,
+    hidden: bool,
+    help_text: HelpText,
+    options: Vec<Opt>,
+    arguments: Vec<Argument>,
+    subcommands: Vec<Arc<Command>>,
+    mutating: bool,
+    edits_config: bool,
+    validate: Option<RunFn>,
+    pre_run: Option<RunFn>,
+    post_run: Option<PostRunFn>,
+}
+
+impl CommandBuilder {
+    pub fn new(name: CommandName, help_text: HelpText) -> Self {
+        CommandBuilder {
+            name: name,
+            aliases: Vec::new(),
+            hidden: false,
+            help_text: help_text,
+            options: Vec::new(),
+            arguments: Vec::new(),
+            subcommands: Vec::new(),
+            mutating: false,
+            edits_config: false,
+            validate: None,
+            pre_run: None,
+            post_run: None,
+        }
+    }
+
+    pub fn alias(mut self, name: CommandName) -> Self {
+        self.aliases.push(name);
+        self
+    }
+
+    pub fn hidden(mut self) -> Self {
+        self.hidden = true;
+        self
+    }
+
+    pub fn option(mut self, opt: Opt) -> Self {
+        self.options.push(opt);
+        self
+    }
+
+    // Arguments are parsed in the order they're added
+    pub fn arg(mut self, arg: Argument) -> Self {
+        self.arguments.push(arg);
+        self
+    }
+
+    pub fn subcommand(mut self, cmd: Arc<Command>) -> Self {
+        self.subcommands.push(cmd);
+        self
+    }
+
+    // Marks the command as one that may modify the repo
+    pub fn mutating(mut self) -> Self {
+        self.mutating = true;
+        self
+    }
+
+    // Marks the command as one that modifies nothing but the config
+    pub fn edits_config(mut self) -> Self {
+        self.mutating = true;
+        self.edits_config = true;
+        self
+    }
+
+    // checks the request's options against the node
+    pub fn validate(mut self, f: RunFn) -> Self {
+        self.validate = Some(f);
+        self
+    }
+
+    // setup shared with other commands
+    pub fn pre_run(mut self, f: RunFn) -> Self {
+        self.pre_run = Some(f);
+        self
+    }
+
+    // teardown shared with other commands
+    pub fn post_run(mut self, f: PostRunFn) -> Self {
+        self.post_run = Some(f);
+        self
+    }
+
+    // Panics if the definition is inconsistent: two options or arguments
+    // share a name, two subcommands share a name or alias, the arguments can't be
+    // parsed unambiguously, or the command has both arguments and
+    // subcommands.
+    pub fn run(self, f: RunFn) -> Arc<Command> {
+        check_options(self.name, &self.options);
+        check_arguments(self.name, &self.arguments, self.subcommands.len());
+
+        // every name and alias of a subcommand, to its name
+        let mut names = HashMap::new();
+        let mut subcommands = HashMap::new();
+        for cmd in self.subcommands {
+            let name = cmd.get_name();
+            for alias in Some(&name).into_iter().chain(cmd.get_aliases()) {
+                if let Some(other) = names.insert(*alias, name) {
+                    panic!("Command {:?}: subcommands {:?} and {:?} are both named {:?}",
+                           self.name,
+                           other,
+                           name,
+                           alias);
+                }
+            }
+            subcommands.insert(name, cmd);
+        }
+
+        Arc::new(BuiltCommand {
+            name: self.name,
+            aliases: self.aliases,
+            hidden: self.hidden,
+            help_text: self.help_text,
+            options: self.options,
+            arguments: self.arguments,
+            subcommands: subcommands,
+            subcommand_names: names,
+            mutating: self.mutating,
+            edits_config: self.edits_config,
+            run: f,
+            validate: self.validate,
+            pre_run: self.pre_run,
+            post_run: self.post_run,
+        })
+    }
+}
+
+fn check_options(name: CommandName, options: &[Opt]) {
+    let mut seen = HashMap::new();
+    for opt in options {
+        for opt_name in opt.get_names() {
+            if let Some(other) = seen.insert(*opt_name, opt.get_name()) {
+                panic!("Command {:?}: options --{} and --{} are both named {:?}",
+                       name,
+                       other,
+                       opt.get_name(),
+                       opt_name);
+            }
+        }
+    }
+}
+
+fn check_arguments(name: CommandName, arguments: &[Argument], num_subcommands: usize) {
+    if !arguments.is_empty() && num_subcommands > 0 {
+        panic!("Command {:?} has both arguments and subcommands", name);
+    }
+    for (i, arg) in arguments.iter().enumerate() {
+        if arguments[..i].iter().any(|other| other.name() == arg.name()) {
+            panic!("Command {:?} has two arguments named <{}>", name, arg.name());
+        }
+        if arg.is_variadic() && i + 1 < arguments.len() {
+            panic!("Command {:?}: variadic argument <{}> has to be the last argument",
+                   name,
+                   arg.name());
+        }
+        if arg.is_required() && i > 0 && !arguments[i - 1].is_required() {
+            panic!("Command {:?}: required argument <{}> comes after optional argument <{}>",
+                   name,
+                   arg.name(),
+                   arguments[i - 1].name());
+        }
+    }
+}
+
+// The command a CommandBuilder produces
+struct BuiltCommand {
+    name: CommandName,
+    aliases: Vec<CommandName>,
+    hidden: bool,
+    help_text: HelpText,
+    options: Vec<Opt>,
+    arguments: Vec<Argument>,
+    subcommands: HashMap<CommandName, Arc<Command>>,
+    subcommand_names: HashMap<CommandName, CommandName>, // including aliases
+    mutating: bool,
+    edits_config: bool,
+    run: RunFn,
+    validate: Option<RunFn>,
+    pre_run: Option<RunFn>,
+    post_run: Option<PostRunFn>,
+}
+
+impl Command for BuiltCommand {
+    fn run(&self, req: &request::Request) -> Result<(), CommandError> {
+        (self.run)(req)
+    }
+
+    fn validate(&self, req: &request::Request) -> Result<(), CommandError> {
+        self.validate.map_or(Ok(()), |f| f(req))
+    }
+
+    fn pre_run(&self, req: &request::Request) -> Result<(), CommandError> {
+        self.pre_run.map_or(Ok(()), |f| f(req))
+    }
+
+    fn post_run(&self,
+                req: &request::Request,
+                response: &mut Response)
+                -> Result<(), CommandError> {
+        self.post_run.map_or(Ok(()), |f| f(req, response))
+    }
+
+    fn get_name(&self) -> CommandName {
+        self.name
+    }
+
+    fn get_aliases(&self) -> &[CommandName] {
+        &self.aliases
+    }
+
+    fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    fn get_help_text(&self) -> &HelpText {
+        &self.help_text
+    }
+
+    fn get_options(&self) -> CommandOptions {
+        CommandOptions::new(self.options.iter())
+    }
+
+    fn get_subcommand(&self, subcmd: &str) -> Option<&Command> {
+        self.subcommand_names
+            .get(subcmd)
+            .and_then(|name| self.subcommands.get(name))
+            .map(|cmd| &**cmd)
+    }
+
+    fn get_subcommands(&self) -> Vec<&Command> {
+        let mut subcommands: Vec<&Command> = self.subcommands.values().map(|cmd| &**cmd).collect();
+        subcommands.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+        subcommands
+    }
+
+    fn num_args(&self) -> usize {
+        self.arguments.len()
+    }
+
+    fn get_arguments(&self) -> slice::Iter<Argument> {
+        self.arguments.iter()
+    }
+
+    fn is_mutating(&self) -> bool {
+        self.mutating
+    }
+
+    fn edits_config(&self) -> bool {
+        self.edits_config
+    }
+}
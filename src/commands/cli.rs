@@ -1,6 +1,9 @@
 use super::{Command, OptType};
+use super::help;
 use super::request::{self, Request};
+use util;
 
+use std::cmp;
 use std::collections::HashMap;
 use std::slice;
 
@@ -8,8 +11,9 @@ pub type ParseError = String;
 pub type ParseResult<'a> = (&'a Command,
                             Vec<(super::ArgName, request::Arg)>,
                             Vec<(super::OptName, request::Opt)>);
-// TODO: support command arguments
-// TODO: support setting option arguments with equal sign (--opt=value)
+// Options can be given as `--name value` or `--name=value`, and single-letter
+// options can be grouped, so `-rq` is short for `-r -q`. Everything after a
+// `--` is taken as arguments, even if it starts with a dash.
 pub fn parse<I>(mut input: I, root: &Command) -> Result<ParseResult, ParseError>
     where I: Iterator<Item = String>
 {
@@ -20,6 +24,8 @@ pub fn parse<I>(mut input: I, root: &Command) -> Result<ParseResult, ParseError>
     //
     // Then current_cmd will be a reference to (the Command associated with) <cmd3>
     let mut current_cmd = root;
+    // the names of the commands parsed so far, for usage in errors
+    let mut path: Vec<&str> = Vec::new();
 
     // Options submitted in the request
     let mut opts: Vec<(super::OptName, request::Opt)> = Vec::new();
@@ -30,9 +36,13 @@ pub fn parse<I>(mut input: I, root: &Command) -> Result<ParseResult, ParseError>
     // Arguments for a single command argument (arguments can be variadic)
     let mut args_one = Vec::new();
 
-    // Stores all the command options for each command in the command path
-    let mut cmd_opts = HashMap::new();
-    cmd_opts.extend(root.get_options());
+    // The options of the root command are global: they're recognized anywhere
+    // on the command line, before or after the subcommand. Other options are
+    // only recognized after the command that declares them.
+    let global_opts: HashMap<super::OptName, &super::Opt> = root.get_options().collect();
+
+    // Stores the options recognized at the current point of the command line
+    let mut cmd_opts = global_opts.clone();
 
     // Stores the command args when we encounter a command with arguments
     let mut cmd_args: Option<slice::Iter<super::Argument>> = None;
@@ -40,6 +50,8 @@ pub fn parse<I>(mut input: I, root: &Command) -> Result<ParseResult, ParseError>
     let mut curr_arg = None;
 
     let mut token: String;
+    // set once a `--` has been seen
+    let mut options_ended = false;
 
     loop {
         token = match input.next() {
@@ -47,44 +59,49 @@ pub fn parse<I>(mut input: I, root: &Command) -> Result<ParseResult, ParseError>
             Some(s) => s,
         };
 
-        // TODO: doesn't parse short args correctly, for now
-        // you should be able to combine them, like -a -b -c can be written
-        // -abc, with the caveat that all but the last option need to be boolean
-        if token.starts_with("--") || token.starts_with("-") {
-            let cmd_opt = {
-                let opt_name = if token.starts_with("--") {
-                    &token[2..]
-                } else {
-                    &token[1..]
-                };
+        if token == "--" && !options_ended {
+            options_ended = true;
+            continue;
+        }
 
-                match cmd_opts.get(opt_name) {
-                    None => return Err(format!("Option not recognized: {}", opt_name)),
-                    Some(opt) => opt,
-                }
-            };
+        // a lone dash is an argument, as it commonly stands for stdin
+        if token.starts_with("-") && token != "-" && !options_ended {
+            let given = try!(options_in_token(&token, &cmd_opts));
+            let last = given.len() - 1;
 
-            match cmd_opt.opt_type {
-                OptType::Bool => {
-                    opts.push((cmd_opt.get_name(), request::Opt::Bool(true)))
-                }
-                _ => {
-                    // just assume the option argument is the next token.
-                    // eventually this will check if theres an equal sign
-                    // and split the token based on that
-                    token = match input.next() {
-                        None => {
-                            return Err(format!("Expecting option argument for option \
-                                                {}, but no more tokens.",
-                                               cmd_opt.get_name()))
+            for (i, (cmd_opt, value)) in given.into_iter().enumerate() {
+                let value = match (cmd_opt.opt_type, value) {
+                    (_, Some(value)) => value,
+                    (OptType::Bool, None) => {
+                        add_opt(&mut opts, cmd_opt.get_name(), request::Opt::Bool(true));
+                        continue;
+                    }
+                    (_, None) if i < last => {
+                        return Err(format!("Option {} takes a value, so it has to come last \
+                                            in {}",
+                                           cmd_opt.get_name(),
+                                           token))
+                    }
+                    // the option's value is the next token
+                    (_, None) => {
+                        match input.next() {
+                            None => {
+                                return Err(format!("Expecting option argument for option \
+                                                    {}, but no more tokens.",
+                                                   cmd_opt.get_name()))
+                            }
+                            Some(s) => s,
                         }
-                        Some(s) => s,
-                    };
+                    }
+                };
 
-                    let req_opt = try!(request::Opt::parse_string(token,
-                                                                  cmd_opt.opt_type));
-                    opts.push((cmd_opt.get_name(), req_opt));
-                }
+                let req_opt = try!(request::Opt::parse_string(value, cmd_opt.opt_type)
+                                       .map_err(|e| {
+                                           format!("Invalid value for option {}: {}",
+                                                   cmd_opt.get_name(),
+                                                   e)
+                                       }));
+                add_opt(&mut opts, cmd_opt.get_name(), req_opt);
             }
 
         } else {
@@ -95,12 +112,25 @@ pub fn parse<I>(mut input: I, root: &Command) -> Result<ParseResult, ParseError>
             let num_args = current_cmd.num_args();
             if num_args == 0 {
                 let subcmd = match current_cmd.get_subcommand(&token) {
-                    None => return Err(format!("Subcommand {} not found", &token)),
+                    None => {
+                        let names = current_cmd.get_visible_subcommands()
+                                               .iter()
+                                               .map(|cmd| cmd.get_name())
+                                               .collect();
+                        return Err(match closest(&token, names) {
+                            Some(name) => {
+                                format!("Subcommand {} not found. Did you mean {}?", token, name)
+                            }
+                            None => format!("Subcommand {} not found", token),
+                        });
+                    }
                     Some(cmd) => cmd,
                 };
 
+                cmd_opts = global_opts.clone();
                 cmd_opts.extend(subcmd.get_options());
                 current_cmd = subcmd;
+                path.push(subcmd.get_name());
 
                 // initialize cmd_args if necessary
                 let num_args = current_cmd.num_args();
@@ -109,11 +139,13 @@ pub fn parse<I>(mut input: I, root: &Command) -> Result<ParseResult, ParseError>
                     curr_arg = cmd_args.as_mut().unwrap().next();
                 }
             } else {
-                // TODO: handle optional arguments
-
-                // Command arg index has been incremented past the end of cmd_args slice
+                // Command arg index has been incremented past the end of cmd_args slice.
+                // Optional arguments always come after the required ones, so
+                // once they're all taken there is nothing left to assign to.
                 if curr_arg.is_none() {
-                    return Err(format!("Unexpected argument: {}", token));
+                    return Err(usage_error(format!("Unexpected argument: {}", token),
+                                           current_cmd,
+                                           &path));
                 }
 
                 args_one.push(token);
@@ -138,13 +170,149 @@ pub fn parse<I>(mut input: I, root: &Command) -> Result<ParseResult, ParseError>
     }
 
 
-    if !curr_arg.is_none() {
-        return Err(format!("Missing argument for <{}>", curr_arg.unwrap().name()));
+    // asking for help doesn't need the arguments the command would need to run
+    let help = opts.iter().any(|&(name, _)| {
+        name == help::SHORT_HELP_OPTION || name == help::LONG_HELP_OPTION
+    });
+
+    if let Some(arg) = curr_arg {
+        // a missing file argument is read from stdin if something is piped in
+        let piped = match arg.arg_type() {
+            super::ArgumentType::File => util::stdin_is_piped(),
+            super::ArgumentType::String => false,
+        };
+        if arg.is_required() && !help && !piped {
+            return Err(usage_error(format!("Missing argument for <{}>", arg.name()),
+                                   current_cmd,
+                                   &path));
+        }
+    }
+
+    // the options recognized for the final command are checked for whether
+    // they're required, and given their defaults if they weren't set
+    let mut checked = Vec::new();
+    for (_, opt) in cmd_opts {
+        if checked.contains(&opt.get_name()) ||
+           opts.iter().any(|&(name, _)| name == opt.get_name()) {
+            continue;
+        }
+        checked.push(opt.get_name());
+
+        if opt.is_required() && !help {
+            return Err(format!("Missing required option --{}", opt.get_name()));
+        }
+        if let Some(default) = opt.get_default() {
+            opts.push((opt.get_name(), default.clone()));
+        }
     }
 
     Ok((current_cmd, args, opts))
 }
 
+// Adds an option given on the command line. The values of a repeated option
+// are collected into one Opt; for any other option, the last value given wins.
+fn add_opt(opts: &mut Vec<(super::OptName, request::Opt)>,
+           name: super::OptName,
+           value: request::Opt) {
+    if let request::Opt::Strings(new) = value {
+        for &mut (opt_name, ref mut opt) in opts.iter_mut() {
+            if let request::Opt::Strings(ref mut values) = *opt {
+                if opt_name == name {
+                    values.extend(new);
+                    return;
+                }
+            }
+        }
+        opts.push((name, request::Opt::Strings(new)));
+        return;
+    }
+    opts.push((name, value));
+}
+
+// The options an option token stands for, each with the value given in the
+// token itself, if any
+fn options_in_token<'a>(token: &str,
+                        cmd_opts: &HashMap<super::OptName, &'a super::Opt>)
+                        -> Result<Vec<(&'a super::Opt, Option<String>)>, ParseError> {
+    let (body, long) = if token.starts_with("--") {
+        (&token[2..], true)
+    } else {
+        (&token[1..], false)
+    };
+    let (name, value) = match body.find('=') {
+        Some(i) => (&body[..i], Some(body[i + 1..].to_string())),
+        None => (body, None),
+    };
+
+    if let Some(opt) = cmd_opts.get(name) {
+        return Ok(vec![(*opt, value)]);
+    }
+
+    // a group of single-letter options, the last of which may take a value
+    let letters: Vec<String> = name.chars().map(|c| c.to_string()).collect();
+    if !long && letters.len() > 1 && letters.iter().all(|l| cmd_opts.contains_key(&l[..])) {
+        let last = letters.len() - 1;
+        return Ok(letters.iter()
+                         .enumerate()
+                         .map(|(i, l)| {
+                             let value = if i == last {
+                                 value.clone()
+                             } else {
+                                 None
+                             };
+                             (*cmd_opts.get(&l[..]).unwrap(), value)
+                         })
+                         .collect());
+    }
+
+    let dashes = |name: &str| if name.len() == 1 { "-" } else { "--" };
+    Err(match closest(name, cmd_opts.keys().cloned().collect()) {
+        Some(known) => {
+            format!("Option not recognized: {}{}. Did you mean {}{}?",
+                    dashes(name),
+                    name,
+                    dashes(known),
+                    known)
+        }
+        None => format!("Option not recognized: {}{}", dashes(name), name),
+    })
+}
+
+// The candidate closest to `name` by edit distance, if any is close enough to
+// be a likely typo of it
+fn closest<'a>(name: &str, mut candidates: Vec<&'a str>) -> Option<&'a str> {
+    let max_distance = cmp::max(1, name.len() / 3);
+    candidates.sort();
+    candidates.into_iter()
+              .map(|candidate| (edit_distance(name, candidate), candidate))
+              .filter(|&(distance, _)| distance <= max_distance)
+              .min_by_key(|&(distance, _)| distance)
+              .map(|(_, candidate)| candidate)
+}
+
+// The Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == *cb { 0 } else { 1 };
+            curr.push(cmp::min(substitution, cmp::min(prev[j + 1], curr[j]) + 1));
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
+
+// An error followed by the command's usage line, like the start of its help page
+fn usage_error(msg: String, cmd: &Command, path: &[&str]) -> ParseError {
+    format!("{}\n\nUSAGE\n    {}\n\nUse '{} --help' for more information.",
+            msg,
+            help::usage(cmd, &path.join(" ")),
+            path.iter().fold("ipfs".to_string(), |acc, name| format!("{} {}", acc, name)))
+}
+
 fn parse_arg_tokens(cmd_arg: &super::Argument,
                     args: Vec<String>)
                     -> Result<request::Arg, String> {
@@ -152,9 +320,14 @@ fn parse_arg_tokens(cmd_arg: &super::Argument,
         super::ArgumentType::String => Ok(request::Arg::new_string_arg(args)),
 
         super::ArgumentType::File => {
-            let mut file_args = Vec::new();
+            let mut file_args: Vec<request::FileArg> = Vec::new();
             for arg in args.into_iter() {
                 let file_arg = try!(request::FileArg::new(arg));
+                if file_arg.is_stdin() && file_args.iter().any(|f| f.is_stdin()) {
+                    return Err(format!("Stdin ({}) can only be given once for <{}>",
+                                       request::STDIN_ARG,
+                                       cmd_arg.name()));
+                }
                 file_args.push(file_arg);
             }
             Ok(request::Arg::new_file_arg(file_args))
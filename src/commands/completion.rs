@@ -0,0 +1,420 @@
+// Generates shell completion scripts from the command tree, so they offer
+// every subcommand and option without being kept up to date by hand.
+//
+// Each script works out which command is being typed by following the words
+// on the command line that name subcommands, skipping options, then offers
+// that command's subcommands and options along with the root's, which are
+// global. Files are offered for commands with file arguments and for the
+// values of string options, and an enum option's choices for its value.
+// Hidden commands aren't offered, and neither are aliases, so the words are
+// only followed as far as they're the commands' own names.
+
+use super::{Command, Opt, OptType};
+use super::help;
+
+use std::fs;
+
+const PROGRAM: &'static str = "ipfs";
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    pub fn by_name(name: &str) -> Result<Self, String> {
+        match name {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(format!("Unknown shell {:?}: expected bash, zsh or fish", name)),
+        }
+    }
+}
+
+pub fn script(root: &Command, shell: Shell) -> String {
+    // the names leading to each command are each preceded by a space, e.g.
+    // " repo compress", so a path is extended by appending " <word>"
+    let commands: Vec<(String, &Command)> = help::all_commands(root, "")
+                                                .into_iter()
+                                                .map(|(path, cmd)| {
+                                                    if path.is_empty() {
+                                                        (path, cmd)
+                                                    } else {
+                                                        (format!(" {}", path), cmd)
+                                                    }
+                                                })
+                                                .collect();
+    match shell {
+        Shell::Bash => bash(root, &commands),
+        Shell::Zsh => zsh(root, &commands),
+        Shell::Fish => fish(&commands),
+    }
+}
+
+// The command's options, once each rather than once per name
+fn options(cmd: &Command) -> Vec<&Opt> {
+    let mut options: Vec<&Opt> = Vec::new();
+    for (_, opt) in cmd.get_options() {
+        if !options.iter().any(|o| o.get_name() == opt.get_name()) {
+            options.push(opt);
+        }
+    }
+    options
+}
+
+fn spellings(opt: &Opt) -> Vec<String> {
+    opt.get_names()
+       .map(|name| {
+           if name.len() == 1 {
+               format!("-{}", name)
+           } else {
+               format!("--{}", name)
+           }
+       })
+       .collect()
+}
+
+fn takes_files(cmd: &Command) -> bool {
+    cmd.get_arguments().any(|arg| arg.is_file())
+}
+
+fn subcommand_names(cmd: &Command) -> Vec<&'static str> {
+    cmd.get_visible_subcommands().iter().map(|subcmd| subcmd.get_name()).collect()
+}
+
+// The words a command's options can be spelled as, the root's included
+fn option_words(root: &Command, path: &str, cmd: &Command) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    let mut opts = options(root);
+    if !path.is_empty() {
+        opts.extend(options(cmd));
+    }
+    for opt in opts {
+        words.extend(spellings(opt));
+    }
+    words
+}
+
+// What to complete an option's value with
+#[derive(PartialEq)]
+enum Value {
+    Files,
+    Nothing,
+}
+
+fn value_of(opt_type: OptType) -> Option<Value> {
+    match opt_type {
+        OptType::Bool | OptType::Enum(_) => None,
+        OptType::String | OptType::Repeated => Some(Value::Files),
+        OptType::Int => Some(Value::Nothing),
+    }
+}
+
+// The spellings of every option whose value is completed with `value`
+fn value_options(commands: &[(String, &Command)], value: Value) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    for &(_, cmd) in commands.iter() {
+        for opt in options(cmd) {
+            if value_of(opt.opt_type).as_ref() == Some(&value) {
+                for spelling in spellings(opt) {
+                    if !words.contains(&spelling) {
+                        words.push(spelling);
+                    }
+                }
+            }
+        }
+    }
+    words
+}
+
+// Every enum option, its spellings joined as a case pattern, with its choices
+fn enum_options(commands: &[(String, &Command)]) -> Vec<(String, &'static [&'static str])> {
+    let mut found: Vec<(String, &'static [&'static str])> = Vec::new();
+    for &(_, cmd) in commands.iter() {
+        for opt in options(cmd) {
+            if let OptType::Enum(choices) = opt.opt_type {
+                let pattern = spellings(opt).join("|");
+                if !found.iter().any(|&(ref p, _)| *p == pattern) {
+                    found.push((pattern, choices));
+                }
+            }
+        }
+    }
+    found
+}
+
+// The case pattern matching the path of every command but the root
+fn path_pattern(commands: &[(String, &Command)]) -> String {
+    commands.iter()
+            .filter(|&&(ref path, _)| !path.is_empty())
+            .map(|&(ref path, _)| format!("\"{}\"", path))
+            .collect::<Vec<_>>()
+            .join("|")
+}
+
+fn bash(root: &Command, commands: &[(String, &Command)]) -> String {
+    let mut out = String::new();
+    out.push_str(&format!("# bash completion for {0}, generated by '{0} completion bash'\n",
+                          PROGRAM));
+    out.push_str(&format!("_{}() {{\n", PROGRAM));
+    out.push_str("    local cur prev cmdpath word i opts subcmds files\n");
+    out.push_str("    cur=\"${COMP_WORDS[COMP_CWORD]}\"\n");
+    out.push_str("    prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n");
+    out.push_str("    cmdpath=\"\"\n");
+    out.push_str("    for ((i = 1; i < COMP_CWORD; i++)); do\n");
+    out.push_str("        word=\"${COMP_WORDS[i]}\"\n");
+    out.push_str("        case \"$cmdpath $word\" in\n");
+    out.push_str(&format!("            {}) cmdpath=\"$cmdpath $word\" ;;\n",
+                          path_pattern(commands)));
+    out.push_str("        esac\n");
+    out.push_str("    done\n\n");
+
+    out.push_str("    case \"$prev\" in\n");
+    for (pattern, choices) in enum_options(commands) {
+        out.push_str(&format!("        {})\n", pattern));
+        out.push_str(&format!("            COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n",
+                              choices.join(" ")));
+        out.push_str("            return ;;\n");
+    }
+    let string_options = value_options(commands, Value::Files);
+    if !string_options.is_empty() {
+        out.push_str(&format!("        {})\n", string_options.join("|")));
+        out.push_str("            COMPREPLY=($(compgen -f -- \"$cur\"))\n");
+        out.push_str("            return ;;\n");
+    }
+    let int_options = value_options(commands, Value::Nothing);
+    if !int_options.is_empty() {
+        out.push_str(&format!("        {})\n", int_options.join("|")));
+        out.push_str("            return ;;\n");
+    }
+    out.push_str("    esac\n\n");
+
+    out.push_str("    case \"$cmdpath\" in\n");
+    for &(ref path, cmd) in commands.iter() {
+        out.push_str(&format!("        \"{}\")\n", path));
+        out.push_str(&format!("            opts=\"{}\"\n",
+                              option_words(root, path, cmd).join(" ")));
+        out.push_str(&format!("            subcmds=\"{}\"\n", subcommand_names(cmd).join(" ")));
+        out.push_str(&format!("            files={} ;;\n", if takes_files(cmd) { 1 } else { 0 }));
+    }
+    out.push_str("    esac\n\n");
+
+    out.push_str("    if [[ \"$cur\" == -* ]]; then\n");
+    out.push_str("        COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))\n");
+    out.push_str("    else\n");
+    out.push_str("        COMPREPLY=($(compgen -W \"$subcmds\" -- \"$cur\"))\n");
+    out.push_str("        if [ \"$files\" = 1 ]; then\n");
+    out.push_str("            COMPREPLY+=($(compgen -f -- \"$cur\"))\n");
+    out.push_str("        fi\n");
+    out.push_str("    fi\n");
+    out.push_str("}\n");
+    out.push_str(&format!("complete -o filenames -F _{0} {0}\n", PROGRAM));
+    out
+}
+
+fn zsh(root: &Command, commands: &[(String, &Command)]) -> String {
+    let mut out = String::new();
+    out.push_str(&format!("#compdef {}\n", PROGRAM));
+    out.push_str(&format!("# zsh completion for {0}, generated by '{0} completion zsh'\n",
+                          PROGRAM));
+    out.push_str(&format!("_{}() {{\n", PROGRAM));
+    // `path` is taken: zsh ties it to $PATH
+    out.push_str("    local cmdpath=\"\" word i files=0\n");
+    out.push_str("    local -a opts subcmds\n");
+    out.push_str("    for ((i = 2; i < CURRENT; i++)); do\n");
+    out.push_str("        word=\"${words[i]}\"\n");
+    out.push_str("        case \"$cmdpath $word\" in\n");
+    out.push_str(&format!("            ({}) cmdpath=\"$cmdpath $word\" ;;\n",
+                          path_pattern(commands)));
+    out.push_str("        esac\n");
+    out.push_str("    done\n\n");
+
+    out.push_str("    case \"${words[CURRENT-1]}\" in\n");
+    for (pattern, choices) in enum_options(commands) {
+        out.push_str(&format!("        ({})\n", pattern));
+        out.push_str(&format!("            compadd -- {}\n", choices.join(" ")));
+        out.push_str("            return ;;\n");
+    }
+    let string_options = value_options(commands, Value::Files);
+    if !string_options.is_empty() {
+        out.push_str(&format!("        ({})\n", string_options.join("|")));
+        out.push_str("            _files\n");
+        out.push_str("            return ;;\n");
+    }
+    let int_options = value_options(commands, Value::Nothing);
+    if !int_options.is_empty() {
+        out.push_str(&format!("        ({})\n", int_options.join("|")));
+        out.push_str("            return ;;\n");
+    }
+    out.push_str("    esac\n\n");
+
+    out.push_str("    case \"$cmdpath\" in\n");
+    for &(ref path, cmd) in commands.iter() {
+        out.push_str(&format!("        (\"{}\")\n", path));
+        out.push_str(&format!("            opts=({})\n", option_words(root, path, cmd).join(" ")));
+        out.push_str(&format!("            subcmds=({})\n", subcommand_names(cmd).join(" ")));
+        out.push_str(&format!("            files={} ;;\n", if takes_files(cmd) { 1 } else { 0 }));
+    }
+    out.push_str("    esac\n\n");
+
+    out.push_str("    if [[ \"${words[CURRENT]}\" == -* ]]; then\n");
+    out.push_str("        compadd -- $opts\n");
+    out.push_str("    else\n");
+    out.push_str("        compadd -- $subcmds\n");
+    out.push_str("        (( files )) && _files\n");
+    out.push_str("    fi\n");
+    out.push_str("}\n");
+    out.push_str(&format!("_{} \"$@\"\n", PROGRAM));
+    out
+}
+
+// The words that could complete `partial` as the word after `words` on an ipfs
+// command line, for prompts like `ipfs shell`'s, found the way the scripts
+// find them: the subcommands and options of the command the words lead to,
+// an enum option's choices for its value, and files where the scripts offer
+// them.
+pub fn candidates(root: &Command, words: &[String], partial: &str) -> Vec<String> {
+    let mut path = String::new();
+    let mut cmd = root;
+    for word in words.iter() {
+        if let Some(subcmd) = cmd.get_visible_subcommands()
+                                 .into_iter()
+                                 .find(|subcmd| subcmd.get_name() == word) {
+            path.push_str(" ");
+            path.push_str(word);
+            cmd = subcmd;
+        }
+    }
+
+    let before = words.last().map_or("", |word| &word[..]);
+    let value_of_option = options(root)
+                              .into_iter()
+                              .chain(options(cmd))
+                              .find(|opt| spellings(opt).iter().any(|s| s == before))
+                              .map(|opt| opt.opt_type);
+    let mut candidates = match value_of_option {
+        Some(OptType::Enum(choices)) => choices.iter().map(|c| c.to_string()).collect(),
+        Some(OptType::String) | Some(OptType::Repeated) => files(partial),
+        Some(OptType::Int) => vec![],
+        Some(OptType::Bool) | None => {
+            if partial.starts_with("-") {
+                option_words(root, &path, cmd)
+            } else {
+                let mut candidates: Vec<String> = subcommand_names(cmd)
+                                                      .into_iter()
+                                                      .map(|name| name.to_string())
+                                                      .collect();
+                if takes_files(cmd) {
+                    candidates.extend(files(partial));
+                }
+                candidates
+            }
+        }
+    };
+    candidates.retain(|c| c.starts_with(partial));
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+// The paths of the files in the directory `partial` names, if any, whose
+// names start like its last component. Directories end with a slash, and
+// hidden files are only offered once a dot is typed.
+fn files(partial: &str) -> Vec<String> {
+    let (dir, prefix) = match partial.rfind('/') {
+        Some(i) => (&partial[..i + 1], &partial[i + 1..]),
+        None => ("", partial),
+    };
+    let entries = match fs::read_dir(if dir.is_empty() { "." } else { dir }) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries.filter_map(|entry| entry.ok())
+           .filter_map(|entry| {
+               let name = match entry.file_name().into_string() {
+                   Ok(name) => name,
+                   Err(_) => return None,
+               };
+               if !name.starts_with(prefix) || (name.starts_with(".") && !prefix.starts_with(".")) {
+                   return None;
+               }
+               let slash = if entry.path().is_dir() { "/" } else { "" };
+               Some(format!("{}{}{}", dir, name, slash))
+           })
+           .collect()
+}
+
+// Quotes a string for fish
+fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace("\\", "\\\\").replace("'", "\\'"))
+}
+
+fn fish(commands: &[(String, &Command)]) -> String {
+    let mut out = String::new();
+    out.push_str(&format!("# fish completion for {0}, generated by '{0} completion fish'\n",
+                          PROGRAM));
+    // succeeds if the command being typed is the one at the path given
+    out.push_str(&format!("function __{}_at\n", PROGRAM));
+    out.push_str("    set -l cmdpath \"\"\n");
+    out.push_str("    for word in (commandline -opc)[2..-1]\n");
+    out.push_str("        switch \"$cmdpath $word\"\n");
+    let patterns: Vec<String> = commands.iter()
+                                        .filter(|&&(ref path, _)| !path.is_empty())
+                                        .map(|&(ref path, _)| fish_quote(path))
+                                        .collect();
+    out.push_str(&format!("            case {}\n", patterns.join(" ")));
+    out.push_str("                set cmdpath \"$cmdpath $word\"\n");
+    out.push_str("        end\n");
+    out.push_str("    end\n");
+    out.push_str("    test \"$cmdpath\" = \"$argv[1]\"\n");
+    out.push_str("end\n\n");
+    out.push_str(&format!("complete -c {} -f\n", PROGRAM));
+
+    for &(ref path, cmd) in commands.iter() {
+        let path_condition = format!(" -n {}",
+                                     fish_quote(&format!("__{}_at {}",
+                                                         PROGRAM,
+                                                         fish_quote(path))));
+        // the root's options are global, so they apply everywhere
+        let condition = if path.is_empty() {
+            String::new()
+        } else {
+            path_condition.clone()
+        };
+
+        for subcmd in cmd.get_visible_subcommands() {
+            out.push_str(&format!("complete -c {}{} -a {} -d {}\n",
+                                  PROGRAM,
+                                  path_condition,
+                                  subcmd.get_name(),
+                                  fish_quote(subcmd.get_help_text().tagline)));
+        }
+        for opt in options(cmd) {
+            let mut line = format!("complete -c {}{}", PROGRAM, condition);
+            for name in opt.get_names() {
+                if name.len() == 1 {
+                    line.push_str(&format!(" -s {}", name));
+                } else {
+                    line.push_str(&format!(" -l {}", name));
+                }
+            }
+            match opt.opt_type {
+                OptType::Bool => {}
+                OptType::String | OptType::Repeated => line.push_str(" -rF"),
+                OptType::Int => line.push_str(" -r"),
+                OptType::Enum(choices) => {
+                    line.push_str(&format!(" -r -a {}", fish_quote(&choices.join(" "))))
+                }
+            }
+            line.push_str(&format!(" -d {}\n", fish_quote(opt.get_description())));
+            out.push_str(&line);
+        }
+        if takes_files(cmd) {
+            out.push_str(&format!("complete -c {}{} -F\n", PROGRAM, path_condition));
+        }
+    }
+    out
+}
@@ -0,0 +1,328 @@
+// Renders help pages from a command's definition: its help text, arguments,
+// options and subcommands. Every command gets a help page this way, without
+// having to write out its usage or option list by hand.
+
+use super::{Argument, Command, Opt, OptType};
+
+// Options that ask for the help page instead of running the command. -h gives
+// the short page, --help the full one.
+pub const SHORT_HELP_OPTION: &'static str = "h";
+pub const LONG_HELP_OPTION: &'static str = "help";
+
+const INDENT: &'static str = "    ";
+
+// `path` is the command path, e.g. "repo compress", which is empty for the
+// root command
+pub fn render(cmd: &Command, path: &str, long: bool) -> String {
+    let help_text = cmd.get_help_text();
+    let mut out = String::new();
+
+    out.push_str("USAGE\n");
+    out.push_str(&format!("{}{}\n", INDENT, usage(cmd, path)));
+    if !help_text.tagline.is_empty() {
+        out.push_str(&format!("\n{}{}\n", INDENT, help_text.tagline));
+    }
+
+    let short_desc = help_text.short_desc.trim();
+    if long && !short_desc.is_empty() {
+        out.push_str("\nDESCRIPTION\n");
+        out.push_str(&indent(short_desc));
+    }
+
+    let arguments: Vec<&Argument> = cmd.get_arguments().collect();
+    if !arguments.is_empty() {
+        out.push_str("\nARGUMENTS\n");
+        let rows = arguments.iter()
+                            .map(|arg| (argument_usage(arg), arg.description().to_string()))
+                            .collect();
+        out.push_str(&table(rows));
+    }
+
+    let mut options: Vec<&Opt> = Vec::new();
+    for (_, opt) in cmd.get_options() {
+        if !options.iter().any(|o| o.get_name() == opt.get_name()) {
+            options.push(opt);
+        }
+    }
+    if !options.is_empty() {
+        out.push_str("\nOPTIONS\n");
+        let rows = options.iter()
+                          .map(|opt| (option_usage(opt), option_description(opt)))
+                          .collect();
+        out.push_str(&table(rows));
+    }
+
+    let subcommands = cmd.get_visible_subcommands();
+    if !subcommands.is_empty() {
+        out.push_str("\nSUBCOMMANDS\n");
+        let rows = subcommands.iter()
+                              .map(|subcmd| (join_path(path, subcmd.get_name()), tagline(*subcmd)))
+                              .collect();
+        out.push_str(&table(rows));
+    }
+
+    if !long {
+        out.push_str(&format!("\nUse '{} --help' for more information.\n",
+                              join_path("ipfs", path)));
+        return out;
+    }
+    if !path.is_empty() {
+        out.push_str("\nThe global options listed by 'ipfs --help' can also be given anywhere \
+                      on the command line.\n");
+    }
+    if !subcommands.is_empty() {
+        out.push_str(&format!("\nUse '{} <subcmd> --help' for more information about each \
+                               command.\n",
+                              join_path("ipfs", path)));
+    }
+    out
+}
+
+// The tagline a command is listed with, naming its aliases if it has any
+fn tagline(cmd: &Command) -> String {
+    let tagline = cmd.get_help_text().tagline;
+    let aliases = cmd.get_aliases();
+    if aliases.is_empty() {
+        tagline.to_string()
+    } else {
+        format!("{} (alias: {})", tagline, aliases.join(", "))
+    }
+}
+
+// `cmd` and every command under it that isn't hidden, parents first, with
+// their paths
+pub fn all_commands<'a>(cmd: &'a Command, path: &str) -> Vec<(String, &'a Command)> {
+    let mut commands = vec![(path.to_string(), cmd)];
+    for subcmd in cmd.get_visible_subcommands() {
+        commands.extend(all_commands(subcmd, &join_path(path, subcmd.get_name())));
+    }
+    commands
+}
+
+fn join_path(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{} {}", path, name)
+    }
+}
+
+// The synopsis from the help text if it has one, else a usage line built from
+// the command's arguments
+pub fn usage(cmd: &Command, path: &str) -> String {
+    let synopsis = cmd.get_help_text().synopsis.trim();
+    if !synopsis.is_empty() {
+        return synopsis.to_string();
+    }
+
+    let mut usage = join_path("ipfs", path);
+    if cmd.get_options().next().is_some() {
+        usage.push_str(" [<options>]");
+    }
+    if !cmd.get_subcommands().is_empty() && cmd.num_args() == 0 {
+        usage.push_str(" <subcmd>");
+    }
+    for arg in cmd.get_arguments() {
+        usage.push(' ');
+        usage.push_str(&argument_usage(arg));
+    }
+    usage
+}
+
+fn argument_usage(arg: &Argument) -> String {
+    let mut usage = format!("<{}>", arg.name());
+    if arg.is_variadic() {
+        usage.push_str("...");
+    }
+    if !arg.is_required() {
+        usage = format!("[{}]", usage);
+    }
+    usage
+}
+
+fn option_usage(opt: &Opt) -> String {
+    let names: Vec<String> = opt.get_names()
+                                .map(|name| {
+                                    if name.len() == 1 {
+                                        format!("-{}", name)
+                                    } else {
+                                        format!("--{}", name)
+                                    }
+                                })
+                                .collect();
+    let value = match opt.opt_type {
+        OptType::Bool => String::new(),
+        OptType::String => " <string>".to_string(),
+        OptType::Int => " <int>".to_string(),
+        OptType::Repeated => " <string>...".to_string(),
+        OptType::Enum(choices) => format!(" <{}>", choices.join("|")),
+    };
+    format!("{}{}", names.join(", "), value)
+}
+
+fn option_description(opt: &Opt) -> String {
+    let mut desc = opt.get_description().to_string();
+    if opt.is_required() {
+        desc.push_str(" (required)");
+    }
+    if let OptType::Repeated = opt.opt_type {
+        desc.push_str(" (can be given several times)");
+    }
+    if let Some(default) = opt.get_default() {
+        desc.push_str(&format!(" (default: {})", default));
+    }
+    desc
+}
+
+fn indent(text: &str) -> String {
+    let mut out = String::new();
+    for line in text.lines() {
+        if line.is_empty() {
+            out.push('\n');
+        } else {
+            out.push_str(&format!("{}{}\n", INDENT, line));
+        }
+    }
+    out
+}
+
+// Lays out (term, description) rows with the descriptions in one column
+fn table(rows: Vec<(String, String)>) -> String {
+    let width = rows.iter().map(|&(ref term, _)| term.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    for (term, desc) in rows {
+        out.push_str(&format!("{}{:<width$}  {}\n", INDENT, term, desc, width = width));
+    }
+    out
+}
+
+// The name of a command's man page, e.g. ipfs-repo-compress
+pub fn man_name(path: &str) -> String {
+    if path.is_empty() {
+        "ipfs".to_string()
+    } else {
+        format!("ipfs-{}", path.replace(" ", "-"))
+    }
+}
+
+// Renders the same sections as the help page as a man page, in roff with the
+// man macros
+pub fn render_man(cmd: &Command, path: &str) -> String {
+    let help_text = cmd.get_help_text();
+    let name = man_name(path);
+    let mut out = String::new();
+
+    out.push_str(&format!(".TH \"{}\" \"1\" \"\" \"\" \"ipfs manual\"\n", name.to_uppercase()));
+    out.push_str(".SH NAME\n");
+    if help_text.tagline.is_empty() {
+        out.push_str(&format!("{}\n", roff_escape(&name)));
+    } else {
+        out.push_str(&format!("{} \\- {}\n", roff_escape(&name), roff_escape(help_text.tagline)));
+    }
+
+    out.push_str(".SH SYNOPSIS\n");
+    out.push_str(&format!(".B {}\n", roff_escape(&usage(cmd, path))));
+
+    let short_desc = help_text.short_desc.trim();
+    if !short_desc.is_empty() {
+        out.push_str(".SH DESCRIPTION\n");
+        out.push_str(&roff_paragraphs(short_desc));
+    }
+
+    let arguments: Vec<&Argument> = cmd.get_arguments().collect();
+    if !arguments.is_empty() {
+        out.push_str(".SH ARGUMENTS\n");
+        for arg in arguments {
+            out.push_str(&format!(".TP\n.B {}\n{}\n",
+                                  roff_escape(&argument_usage(arg)),
+                                  roff_line(arg.description())));
+        }
+    }
+
+    let mut options: Vec<&Opt> = Vec::new();
+    for (_, opt) in cmd.get_options() {
+        if !options.iter().any(|o| o.get_name() == opt.get_name()) {
+            options.push(opt);
+        }
+    }
+    if !options.is_empty() {
+        out.push_str(".SH OPTIONS\n");
+        for opt in options {
+            out.push_str(&format!(".TP\n.B {}\n{}\n",
+                                  roff_escape(&option_usage(opt)),
+                                  roff_line(&option_description(opt))));
+        }
+        if !path.is_empty() {
+            out.push_str(".PP\nThe global options listed in \\fBipfs\\fR(1) can also be given \
+                          anywhere on the command line.\n");
+        }
+    }
+
+    let subcommands = cmd.get_visible_subcommands();
+    if !subcommands.is_empty() {
+        out.push_str(".SH SUBCOMMANDS\n");
+        for subcmd in subcommands.iter() {
+            let subpath = join_path(path, subcmd.get_name());
+            out.push_str(&format!(".TP\n.BR {} (1)\n{}\n",
+                                  roff_escape(&man_name(&subpath)),
+                                  roff_line(&tagline(*subcmd))));
+        }
+    }
+
+    if !path.is_empty() {
+        let parent = match path.rfind(' ') {
+            Some(i) => &path[..i],
+            None => "",
+        };
+        out.push_str(".SH SEE ALSO\n");
+        out.push_str(&format!(".BR {} (1)\n", roff_escape(&man_name(parent))));
+    }
+    out
+}
+
+fn roff_escape(s: &str) -> String {
+    s.replace("\\", "\\e").replace("-", "\\-")
+}
+
+// Escapes text for a line of its own, where a leading . or ' would make it a
+// request
+fn roff_line(s: &str) -> String {
+    let escaped = roff_escape(s);
+    if escaped.starts_with('.') || escaped.starts_with('\'') {
+        format!("\\&{}", escaped)
+    } else {
+        escaped
+    }
+}
+
+// Turns a help text's description into roff paragraphs. Indented lines, like
+// examples, are kept as they are.
+fn roff_paragraphs(text: &str) -> String {
+    let mut out = String::new();
+    let mut literal = false;
+    for line in text.lines() {
+        if line.is_empty() {
+            if literal {
+                out.push_str(".fi\n.RE\n");
+                literal = false;
+            }
+            out.push_str(".PP\n");
+        } else if line.starts_with(INDENT) {
+            if !literal {
+                out.push_str(".RS\n.nf\n");
+                literal = true;
+            }
+            out.push_str(&format!("{}\n", roff_line(&line[INDENT.len()..])));
+        } else {
+            if literal {
+                out.push_str(".fi\n.RE\n");
+                literal = false;
+            }
+            out.push_str(&format!("{}\n", roff_line(line)));
+        }
+    }
+    if literal {
+        out.push_str(".fi\n.RE\n");
+    }
+    out
+}
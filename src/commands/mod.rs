@@ -1,8 +1,21 @@
+use trace;
+
 use std::collections::{hash_map, HashMap};
+use std::fmt;
+use std::io::Write;
 use std::slice;
 
+pub mod builder;
 pub mod cli;
+pub mod completion;
+pub mod help;
+pub mod progress;
 pub mod request;
+pub mod response;
+// built in every build, though only the tests and tools outside the front-end
+// run commands through it
+#[allow(dead_code)]
+pub mod testing;
 
 pub struct HelpText {
     pub tagline: &'static str, // used in <cmd usage>
@@ -17,23 +30,144 @@ pub struct HelpText {
      * */
 }
 
-pub type RunFn = fn(&request::Request) -> Result<(), String>;
+// The ways a command can fail. Each kind has the exit code the CLI exits with,
+// so scripts can tell a mistake in the command line from a missing object or
+// a failure inside the command.
+#[derive(Clone)]
+pub enum CommandError {
+    // the request can't be carried out as given: bad arguments or options, or
+    // something the repo's current state doesn't allow
+    Client(String),
+    // an object, command or repo named by the request doesn't exist
+    NotFound(String),
+    // anything else that went wrong while running the command
+    Internal(String),
+}
+
+impl CommandError {
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            CommandError::Internal(_) => 1,
+            CommandError::Client(_) => 2,
+            CommandError::NotFound(_) => 3,
+        }
+    }
+
+    // the kind of error, as the JSON encoding names it
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            CommandError::Client(_) => "client",
+            CommandError::NotFound(_) => "not_found",
+            CommandError::Internal(_) => "internal",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match *self {
+            CommandError::Client(ref msg) |
+            CommandError::NotFound(ref msg) |
+            CommandError::Internal(ref msg) => msg,
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+// Errors from the rest of the crate are plain strings, and are taken to be
+// internal unless the command says otherwise
+impl From<String> for CommandError {
+    fn from(msg: String) -> Self {
+        CommandError::Internal(msg)
+    }
+}
+
+pub type RunFn = fn(&request::Request) -> Result<(), CommandError>;
 
 pub type ArgName = &'static str;
 pub type OptName = &'static str;
 pub type CommandName = &'static str;
 
-pub trait Command {
-    fn run(&self, &request::Request) -> Result<(), String>;
+// The dispatcher calls `pre_run`, `validate`, `run` and, if that succeeded,
+// `post_run`, stopping at the first error.
+//
+// Commands are shared as an Arc<Command>, and hold nothing but their
+// definition, so one tree can serve requests on several threads at once, as
+// the daemon's does. Requests are Send, so each can run on a thread of its own.
+pub trait Command: Send + Sync {
+    fn run(&self, &request::Request) -> Result<(), CommandError>;
+    // checks the request against the node before `run` is called
+    fn validate(&self, &request::Request) -> Result<(), CommandError>;
+    // setup shared by several commands, e.g. checking there is a repo
+    fn pre_run(&self, &request::Request) -> Result<(), CommandError> {
+        Ok(())
+    }
+    // teardown shared by several commands, before the output is finished. The
+    // response is borrowed from the request, so items have to be emitted into
+    // it directly rather than through the request.
+    fn post_run(&self, &request::Request, &mut response::Response) -> Result<(), CommandError> {
+        Ok(())
+    }
     fn get_name(&self) -> CommandName;
     fn get_help_text(&self) -> &HelpText;
     fn get_options(&self) -> CommandOptions;
-    fn get_subcommand(&self, &str) -> Option<&Command>;
+    fn get_subcommand(&self, &str) -> Option<&Command>; // by name or alias
+    fn get_subcommands(&self) -> Vec<&Command>; // sorted by name
+    // the subcommands that help and completion list, i.e. all but the hidden
+    fn get_visible_subcommands(&self) -> Vec<&Command> {
+        self.get_subcommands().into_iter().filter(|cmd| !cmd.is_hidden()).collect()
+    }
+    fn get_aliases(&self) -> &[CommandName]; // other names the parent finds it by
+    // whether the command is left out of help and completion, e.g. because
+    // it's experimental or deprecated. It can still be run.
+    fn is_hidden(&self) -> bool;
     fn num_args(&self) -> usize;
     fn get_arguments(&self) -> slice::Iter<Argument>; // TODO: wrap in iterator?
+    fn is_mutating(&self) -> bool; // whether running the command may modify the repo
+    // whether the command only modifies the config, so it can still undo a
+    // datastore.read_only set there
+    fn edits_config(&self) -> bool {
+        false
+    }
 
 }
 
+// Runs the command a request is for, as every front-end does: prints its
+// help instead if -h or --help was given, checks that the request can be
+// carried out, then runs each of the command's steps. `path` is the names of
+// the command and its parents, e.g. "repo compress".
+pub fn dispatch(req: &request::Request, path: &str) -> Result<(), CommandError> {
+    if req.option_bool(help::LONG_HELP_OPTION) {
+        return write_help(req, path, true);
+    }
+    if req.option_bool(help::SHORT_HELP_OPTION) {
+        return write_help(req, path, false);
+    }
+
+    let cmd = req.command;
+    try!(req.encoding().map_err(CommandError::Client));
+    if req.context.refuses(cmd) {
+        return Err(CommandError::Client(format!("The repo is read-only: `{}` would modify it",
+                                                cmd.get_name())));
+    }
+    let _span = trace::span(&format!("command {}", cmd.get_name()));
+    try!(cmd.pre_run(req));
+    try!(cmd.validate(req));
+    try!(cmd.run(req));
+    try!(cmd.post_run(req, &mut req.response_mut()));
+    req.finish_output();
+    Ok(())
+}
+
+fn write_help(req: &request::Request, path: &str, long: bool) -> Result<(), CommandError> {
+    let help = help::render(req.command, path, long);
+    try!(write!(req.raw_output(), "{}", help).map_err(|e| format!("Error writing help: {}", e)));
+    Ok(())
+}
+
 // iterator over (name, command) pairs. Each command can have multiple names.
 pub struct CommandOptions<'a> {
     opt_iter: slice::Iter<'a, Opt>,
@@ -102,6 +236,11 @@ pub enum OptType {
     Bool,
     String,
     Int,
+    // a string option that can be given several times; the values are
+    // collected in the order given
+    Repeated,
+    // a string option whose value has to be one of these
+    Enum(&'static [&'static str]),
 }
 
 // represents an option for a command
@@ -110,6 +249,8 @@ pub struct Opt {
     pub names: Vec<OptName>,
     pub opt_type: OptType,
     description: &'static str,
+    required: bool, // if set, the command can't run without the option
+    default: Option<request::Opt>, // the value used if the option isn't given
 }
 
 impl Opt {
@@ -118,6 +259,25 @@ impl Opt {
         Self::new(names, OptType::Bool, desc)
     }
 
+    pub fn new_string(names: Vec<OptName>, desc: &'static str) -> Self {
+        Self::new(names, OptType::String, desc)
+    }
+
+    pub fn new_int(names: Vec<OptName>, desc: &'static str) -> Self {
+        Self::new(names, OptType::Int, desc)
+    }
+
+    pub fn new_repeated(names: Vec<OptName>, desc: &'static str) -> Self {
+        Self::new(names, OptType::Repeated, desc)
+    }
+
+    pub fn new_enum(names: Vec<OptName>,
+                    choices: &'static [&'static str],
+                    desc: &'static str)
+                    -> Self {
+        Self::new(names, OptType::Enum(choices), desc)
+    }
+
     fn new(mut names: Vec<OptName>, opt_type: OptType, desc: &'static str) -> Self {
         let canonical = names[0];
         names.sort_by(|a, b| a.len().cmp(&b.len()));
@@ -126,9 +286,39 @@ impl Opt {
             names: names,
             opt_type: opt_type,
             description: desc,
+            required: false,
+            default: None,
         }
     }
 
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    // Panics if the value's type doesn't match the option's type
+    pub fn with_default(mut self, value: request::Opt) -> Self {
+        match (self.opt_type, &value) {
+            (OptType::Bool, &request::Opt::Bool(_)) |
+            (OptType::String, &request::Opt::String(_)) |
+            (OptType::Int, &request::Opt::Int(_)) |
+            (OptType::Repeated, &request::Opt::Strings(_)) => {}
+            (OptType::Enum(choices), &request::Opt::String(ref s))
+                if choices.contains(&&s[..]) => {}
+            _ => panic!("Default {:?} doesn't match the type of option {}", value, self.name),
+        }
+        self.default = Some(value);
+        self
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.required
+    }
+
+    pub fn get_default(&self) -> Option<&request::Opt> {
+        self.default.as_ref()
+    }
+
     pub fn get_name(&self) -> OptName {
         self.name
     }
@@ -136,6 +326,10 @@ impl Opt {
     pub fn get_names(&self) -> slice::Iter<OptName> {
         self.names.iter()
     }
+
+    pub fn get_description(&self) -> &'static str {
+        self.description
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -184,6 +378,10 @@ impl Argument {
         }
     }
 
+    pub fn is_required(&self) -> bool {
+        self.required
+    }
+
     pub fn is_variadic(&self) -> bool {
         self.variadic
     }
@@ -195,4 +393,15 @@ impl Argument {
     pub fn arg_type(&self) -> ArgumentType {
         self.ty
     }
+
+    pub fn is_file(&self) -> bool {
+        match self.ty {
+            ArgumentType::File => true,
+            ArgumentType::String => false,
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        self.description
+    }
 }
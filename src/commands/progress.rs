@@ -0,0 +1,99 @@
+// Progress reports from long-running commands. A run function sends events
+// with `Request::progress` while it works, and if --progress was given, the
+// front-end that ran it shows them as they arrive: the command line as a bar
+// on stderr, the HTTP API as lines streamed ahead of the output.
+
+use rustc_serialize::json::{Json, ToJson};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::iter;
+use std::sync::mpsc::Receiver;
+use std::thread::{self, JoinHandle};
+
+// the option that asks for progress to be shown
+pub const PROGRESS_OPTION: &'static str = "progress";
+
+// how many characters the bar itself takes, between the brackets
+const BAR_WIDTH: usize = 30;
+
+// How far a command has got: `current` out of `total` units of work, e.g.
+// bytes, or just `current` if the total isn't known in advance
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub current: u64,
+    pub total: Option<u64>,
+    pub message: String,
+}
+
+impl Event {
+    pub fn from_json(json: &Json) -> Option<Self> {
+        let current = match json.find("current").and_then(|current| current.as_u64()) {
+            Some(current) => current,
+            None => return None,
+        };
+        Some(Event {
+            current: current,
+            total: json.find("total").and_then(|total| total.as_u64()),
+            message: json.find("message")
+                         .and_then(|message| message.as_string())
+                         .unwrap_or("")
+                         .to_string(),
+        })
+    }
+
+    // The event as one line of a progress bar, e.g.
+    // "[=======>        ]  45% added foo.txt", or "1250 walked" without a total
+    pub fn bar(&self) -> String {
+        let total = match self.total {
+            Some(total) if total > 0 => total,
+            Some(_) => return format!("[{}] 100% {}", chars('=', BAR_WIDTH), self.message),
+            None => return format!("{} {}", self.current, self.message),
+        };
+        let current = if self.current > total { total } else { self.current };
+        let filled = (current * BAR_WIDTH as u64 / total) as usize;
+        let mut bar = chars('=', filled);
+        if filled < BAR_WIDTH {
+            bar.push('>');
+            bar.push_str(&chars(' ', BAR_WIDTH - filled - 1));
+        }
+        format!("[{}] {:3}% {}", bar, current * 100 / total, self.message)
+    }
+}
+
+fn chars(c: char, n: usize) -> String {
+    iter::repeat(c).take(n).collect()
+}
+
+impl ToJson for Event {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert("current".to_string(), self.current.to_json());
+        obj.insert("total".to_string(), self.total.to_json());
+        obj.insert("message".to_string(), self.message.to_json());
+        Json::Object(obj)
+    }
+}
+
+// Draws the events on stderr from another thread, until the sending end is
+// dropped
+pub fn show_on_stderr(events: Receiver<Event>) -> JoinHandle<()> {
+    thread::spawn(move || draw(events, &mut io::stderr()))
+}
+
+// Draws a bar for each event as it's received, until the sending end is
+// dropped. The bar is redrawn in place, and finished off with a newline so
+// later output starts on a line of its own.
+pub fn draw(events: Receiver<Event>, out: &mut Write) {
+    let mut longest: usize = 0;
+    for event in events.iter() {
+        let bar = event.bar();
+        // pad over whatever is left of a longer bar drawn before
+        let padding = longest.saturating_sub(bar.len());
+        longest = cmp::max(longest, bar.len());
+        let _ = write!(out, "\r{}{}", bar, chars(' ', padding));
+    }
+    if longest > 0 {
+        let _ = writeln!(out, "");
+    }
+}
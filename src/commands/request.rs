@@ -1,50 +1,96 @@
-use super::Command;
+use super::{Command, CommandError};
+use super::progress;
+use super::response::{self, Encoding, Item, Response};
 use blockstore::{self, Blockstore};
+use clock::{Clock, SystemClock};
 use config;
 use core::IpfsNode;
+use health::Health;
 use util;
 
+use rustc_serialize::json::Json;
+use std::cell::{Ref, RefCell, RefMut};
 use std::collections::HashMap;
 use std::collections::hash_map;
+use std::fs::File;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
 use std::fmt;
 
 // An option submitted for a request.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Opt {
     String(String),
     Bool(bool),
     Int(i32),
+    Strings(Vec<String>), // the values of a repeated option
 }
 
 impl Opt {
     pub fn parse_string(s: String, opt_type: super::OptType) -> Result<Self, String> {
         match opt_type {
             super::OptType::String => Ok(Opt::String(s)),
+            super::OptType::Repeated => Ok(Opt::Strings(vec![s])),
+            super::OptType::Enum(choices) => {
+                if choices.contains(&&s[..]) {
+                    Ok(Opt::String(s))
+                } else {
+                    Err(format!("expected one of {}, got {:?}", choices.join(", "), s))
+                }
+            }
             super::OptType::Bool => {
                 if s == "true" {
                     Ok(Opt::Bool(true))
                 } else if s == "false" {
                     Ok(Opt::Bool(false))
                 } else {
-                    Err("Expected boolean value for boolean option.".to_string())
+                    Err(format!("expected true or false, got {:?}", s))
                 }
             }
             super::OptType::Int => {
                 let x = try!(s.parse::<i32>()
-                              .map_err(|e| format!("{}", e)));
+                              .map_err(|_| format!("expected an integer, got {:?}", s)));
                 Ok(Opt::Int(x))
             }
         }
     }
 }
 
+impl fmt::Display for Opt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Opt::String(ref s) => write!(f, "{}", s),
+            Opt::Bool(b) => write!(f, "{}", b),
+            Opt::Int(n) => write!(f, "{}", n),
+            Opt::Strings(ref v) => write!(f, "{}", v.join(", ")),
+        }
+    }
+}
+
+// The file argument that stands for stdin
+pub const STDIN_ARG: &'static str = "-";
+
+#[derive(Clone)]
+enum FileSource {
+    Path(PathBuf),
+    Stdin,
+    CapturedStdin(Vec<u8>), // what stands in for stdin, see `Request::set_stdin`
+    Data(Vec<u8>), // e.g. the body of an API request
+}
+
+#[derive(Clone)]
 pub struct FileArg {
-    path: PathBuf,
+    source: FileSource,
 }
 
 impl FileArg {
     pub fn new(s: String) -> Result<Self, String> {
+        if s == STDIN_ARG {
+            return Ok(Self::stdin());
+        }
+
         let path = PathBuf::from(s);
         match util::file_exists(&path) {
             Ok(true) => {}
@@ -54,23 +100,62 @@ impl FileArg {
             }
         }
 
-        Ok(FileArg { path: path })
+        Ok(FileArg { source: FileSource::Path(path) })
+    }
+
+    pub fn stdin() -> Self {
+        FileArg { source: FileSource::Stdin }
+    }
+
+    fn captured_stdin(data: Vec<u8>) -> Self {
+        FileArg { source: FileSource::CapturedStdin(data) }
+    }
+
+    // A file whose content was received rather than read from disk
+    pub fn from_data(data: Vec<u8>) -> Self {
+        FileArg { source: FileSource::Data(data) }
+    }
+
+    pub fn is_stdin(&self) -> bool {
+        match self.source {
+            FileSource::Stdin | FileSource::CapturedStdin(_) => true,
+            FileSource::Path(_) | FileSource::Data(_) => false,
+        }
+    }
+
+    // The file's path, unless it is stdin or received data
+    pub fn path(&self) -> Option<&Path> {
+        match self.source {
+            FileSource::Path(ref path) => Some(path),
+            FileSource::Stdin | FileSource::CapturedStdin(_) | FileSource::Data(_) => None,
+        }
     }
-}
 
-impl AsRef<Path> for FileArg {
-    fn as_ref(&self) -> &Path {
-        self.path.as_ref()
+    pub fn open(&self) -> Result<Box<io::Read>, String> {
+        match self.source {
+            FileSource::Path(ref path) => {
+                let file = try!(File::open(path)
+                                    .map_err(|e| format!("Error opening file {:?}: {}", path, e)));
+                Ok(Box::new(file))
+            }
+            FileSource::Stdin => Ok(Box::new(io::stdin())),
+            FileSource::CapturedStdin(ref data) |
+            FileSource::Data(ref data) => Ok(Box::new(io::Cursor::new(data.clone()))),
+        }
     }
 }
 
 impl fmt::Debug for FileArg {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        self.path.fmt(f)
+        match self.source {
+            FileSource::Path(ref path) => path.fmt(f),
+            FileSource::Stdin | FileSource::CapturedStdin(_) => write!(f, "<stdin>"),
+            FileSource::Data(ref data) => write!(f, "<{} bytes of data>", data.len()),
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Arg {
     Strings(Vec<String>),
     Files(Vec<FileArg>),
@@ -104,37 +189,93 @@ impl Arg {
 
 pub type NodeConstructor = fn(PathBuf) -> Result<IpfsNode, String>;
 
+// What the front-end knows about the environment a command runs in. It is set
+// up once per process, and commands run from `shell` or `batch` get a copy.
+#[derive(Clone)]
 pub struct Context<'a> {
-    pub repo_dir: PathBuf,
-    pub node: Option<&'a IpfsNode>,
+    pub repo_dir: PathBuf, // from --repo-dir, $IPFS_PATH or the home dir
+    pub node: Option<&'a IpfsNode>, // None if there is no repo at repo_dir
+    pub read_only: bool, // if set, commands that modify the repo are refused
+    // whether read_only was asked for, e.g. with --read-only, rather than only
+    // set in the config
+    pub read_only_requested: bool,
+    pub online: bool, // if unset, commands must not reach out to the network
+    // what commands tell the time by, the system's unless a test sets another
+    pub clock: Arc<Clock>,
+    // the health of the daemon's services, for commands the daemon runs
+    pub health: Option<Health>,
 }
 
 impl<'a> Context<'a> {
-    // takes a path to the repo directory
-    pub fn new(path: PathBuf, node: Option<&'a IpfsNode>) -> Self {
+    // Takes a path to the repo directory. The repo is read-only if that's
+    // requested or its config says so.
+    pub fn new(path: PathBuf, node: Option<&'a IpfsNode>, read_only: bool, online: bool) -> Self {
         Context {
             repo_dir: path,
             node: node,
+            read_only: read_only || node.map_or(false, |n| n.config.read_only()),
+            read_only_requested: read_only,
+            online: online,
+            clock: Arc::new(SystemClock::new()),
+            health: None,
         }
     }
 
-    pub fn get_node(&self) -> Result<&IpfsNode, String> {
+    // Whether `cmd` can't run because the repo is read-only. Commands that
+    // only edit the config can still run if it's just the config that says
+    // so, or nothing could make the repo writable again.
+    pub fn refuses(&self, cmd: &Command) -> bool {
+        let read_only = if cmd.edits_config() {
+            self.read_only_requested
+        } else {
+            self.read_only
+        };
+        read_only && cmd.is_mutating()
+    }
+
+    // The repo's config, as loaded when the node was constructed
+    pub fn get_config(&self) -> Result<&config::Config, CommandError> {
+        self.get_node().map(|node| &node.config)
+    }
+
+    pub fn get_node(&self) -> Result<&IpfsNode, CommandError> {
         match self.node {
             None => {
-                Err(format!("No ipfs repo found at {:?}. Please run `ipfs init`",
-                            self.repo_dir))
+                Err(CommandError::NotFound(format!("No ipfs repo found at {:?}. Please run \
+                                                    `ipfs init`",
+                                                   self.repo_dir)))
             }
             Some(node) => Ok(self.node.unwrap()),
         }
     }
 }
 
+// How much output the global -q/-Q options ask for
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Verbosity {
+    Normal,
+    Quiet, // -q: only print the essential part of each item
+    Quieter, // -Q: only print the essential part of the last item
+}
+
+// What a request reads as stdin
+#[derive(Clone)]
+enum Stdin {
+    Process, // the process's own
+    Piped(Vec<u8>), // this, as if it were piped in
+    Terminal, // nothing, as if stdin were a terminal
+}
+
 // a request for a command to be executed
 pub struct Request<'a, 'b> {
     pub command: &'a Command,
-    arguments: HashMap<super::ArgName, Arg>,
-    options: HashMap<super::OptName, Opt>,
+    // shared with the request's clones
+    arguments: Arc<HashMap<super::ArgName, Arg>>,
+    options: Arc<HashMap<super::OptName, Opt>>,
     pub context: Context<'b>,
+    response: RefCell<Response>,
+    progress: Option<Sender<progress::Event>>, // set if the front-end shows progress
+    stdin: Stdin,
 }
 
 impl<'a, 'b> Request<'a, 'b> {
@@ -143,14 +284,47 @@ impl<'a, 'b> Request<'a, 'b> {
                opts: Vec<(super::OptName, Opt)>,
                context: Context<'b>)
                -> Self {
-        Request {
+        let mut req = Request {
             command: cmd,
-            arguments: args.into_iter().collect(),
-            options: opts.into_iter().collect(),
+            arguments: Arc::new(args.into_iter().collect()),
+            options: Arc::new(opts.into_iter().collect()),
             context: context,
+            response: RefCell::new(Response::new(Encoding::Text,
+                                                 Verbosity::Normal,
+                                                 Box::new(io::stdout()))),
+            progress: None,
+            stdin: Stdin::Process,
+        };
+        req.set_output(Box::new(io::stdout()));
+        req
+    }
+
+    // Has the command read `data` instead of the process's stdin, as if it
+    // were piped in, or nothing, as if stdin were a terminal. Files given as
+    // `-` are read from it too.
+    pub fn set_stdin(&mut self, data: Option<Vec<u8>>) {
+        let stdin = FileArg::captured_stdin(data.clone().unwrap_or(Vec::new()));
+        self.stdin = match data {
+            Some(data) => Stdin::Piped(data),
+            None => Stdin::Terminal,
+        };
+        for arg in Arc::make_mut(&mut self.arguments).values_mut() {
+            if let Arg::Files(ref mut files) = *arg {
+                for file in files.iter_mut().filter(|file| file.is_stdin()) {
+                    *file = stdin.clone();
+                }
+            }
         }
     }
 
+    // Sends the command's output to `out` instead of stdout
+    pub fn set_output(&mut self, out: Box<io::Write + Send>) {
+        // an unknown encoding is reported by `encoding` before the command runs
+        let encoding = self.encoding().unwrap_or(Encoding::Text);
+        let verbosity = self.verbosity();
+        self.response = RefCell::new(Response::new(encoding, verbosity, out));
+    }
+
     pub fn args(&self) -> hash_map::Iter<super::ArgName, Arg> {
         self.arguments.iter()
     }
@@ -163,6 +337,25 @@ impl<'a, 'b> Request<'a, 'b> {
         self.arguments.get(&name).map(|arg| arg.get_file())
     }
 
+    // The files given for a file argument, or stdin if none were given and
+    // stdin is piped in rather than a terminal. A front-end that has no stdin
+    // to offer gives the argument with no files.
+    pub fn file_args_or_stdin(&self, name: super::ArgName) -> Vec<FileArg> {
+        match self.file_arg(name) {
+            Some(files) => files.to_vec(),
+            None => self.piped_stdin().into_iter().collect(),
+        }
+    }
+
+    // Stdin, unless it's a terminal
+    pub fn piped_stdin(&self) -> Option<FileArg> {
+        match self.stdin {
+            Stdin::Process if util::stdin_is_piped() => Some(FileArg::stdin()),
+            Stdin::Piped(ref data) => Some(FileArg::captured_stdin(data.clone())),
+            _ => None,
+        }
+    }
+
     pub fn options(&self) -> hash_map::Iter<super::OptName, Opt> {
         self.options.iter()
     }
@@ -170,4 +363,147 @@ impl<'a, 'b> Request<'a, 'b> {
     pub fn option(&self, name: super::OptName) -> Option<&Opt> {
         self.options.get(&name)
     }
+
+    // The typed accessors below return the option's value if it was given. The
+    // parser has already converted it according to the option's declared
+    // OptType, so a value of another type means the name belongs to an option
+    // of a different type, which is a bug in the command.
+    pub fn option_bool(&self, name: super::OptName) -> bool {
+        match self.options.get(&name) {
+            None => false,
+            Some(&Opt::Bool(b)) => b,
+            Some(other) => panic!("Option {} is not a boolean option: {:?}", name, other),
+        }
+    }
+
+    pub fn option_int(&self, name: super::OptName) -> Option<i32> {
+        match self.options.get(&name) {
+            None => None,
+            Some(&Opt::Int(n)) => Some(n),
+            Some(other) => panic!("Option {} is not an integer option: {:?}", name, other),
+        }
+    }
+
+    pub fn option_str(&self, name: super::OptName) -> Option<&str> {
+        match self.options.get(&name) {
+            None => None,
+            Some(&Opt::String(ref s)) => Some(s),
+            Some(other) => panic!("Option {} is not a string option: {:?}", name, other),
+        }
+    }
+
+    // The values given for a repeated option, if any
+    pub fn option_strs(&self, name: super::OptName) -> &[String] {
+        match self.options.get(&name) {
+            None => &[],
+            Some(&Opt::Strings(ref v)) => v,
+            Some(other) => panic!("Option {} is not a repeated option: {:?}", name, other),
+        }
+    }
+
+    pub fn verbosity(&self) -> Verbosity {
+        if self.option_bool("Q") {
+            Verbosity::Quieter
+        } else if self.option_bool("q") {
+            Verbosity::Quiet
+        } else {
+            Verbosity::Normal
+        }
+    }
+
+    pub fn encoding(&self) -> Result<Encoding, String> {
+        Encoding::by_name(self.option_str("encoding").unwrap_or(response::DEFAULT_ENCODING))
+    }
+
+    // Emits one item of a command's output. `line` is how it's printed as text,
+    // and `essential` the part of it that scripts care about (e.g. the hash),
+    // which is all that's printed with -q. With -Q, only the last item's
+    // essential part is printed, by `finish_output`. Encodings other than text
+    // get the essential part as the item's value; see `emit_value`.
+    pub fn emit(&self, line: &str, essential: &str) {
+        self.emit_value(line, essential, Json::String(essential.to_string()));
+    }
+
+    // Like `emit`, with the item as structured data for encodings like JSON
+    pub fn emit_value(&self, line: &str, essential: &str, value: Json) {
+        self.response.borrow_mut().emit(Item {
+            line: line.to_string(),
+            essential: essential.to_string(),
+            value: value,
+        });
+    }
+
+    // The items emitted so far
+    pub fn response(&self) -> Ref<Response> {
+        self.response.borrow()
+    }
+
+    // Nothing can be emitted through the request while this is held
+    pub fn response_mut(&self) -> RefMut<Response> {
+        self.response.borrow_mut()
+    }
+
+    // Where output that isn't made of items is written, e.g. by `cat`. It goes
+    // wherever the items go, so it is captured along with them.
+    pub fn raw_output(&self) -> RefMut<Box<io::Write + Send>> {
+        RefMut::map(self.response.borrow_mut(), |response| response.output())
+    }
+
+    // Sends the events reported with `progress` to `events`, for the front-end
+    // to show, if --progress was given
+    pub fn set_progress(&mut self, events: Sender<progress::Event>) {
+        if self.option_bool(progress::PROGRESS_OPTION) {
+            self.progress = Some(events);
+        }
+    }
+
+    // Drops the sending end given to `set_progress`, so whatever shows the
+    // events sees that there are no more
+    pub fn end_progress(&mut self) {
+        self.progress = None;
+    }
+
+    // Reports that the command has done `current` of `total` units of work,
+    // if the total is known. Nothing is reported unless the front-end asked
+    // for progress, so commands can call this whenever they like.
+    pub fn progress(&self, current: u64, total: Option<u64>, message: &str) {
+        if let Some(ref events) = self.progress {
+            // a front-end that stopped listening isn't worth failing over
+            let _ = events.send(progress::Event {
+                current: current,
+                total: total,
+                message: message.to_string(),
+            });
+        }
+    }
+
+    // Called once the command has run successfully
+    pub fn finish_output(&self) {
+        self.response.borrow_mut().finish();
+    }
+
+    pub fn into_response(self) -> Response {
+        self.response.into_inner()
+    }
+}
+
+// A clone has the same command line and context, but output of its own, sent
+// to stdout until `set_output` says otherwise, so that it can be run
+// separately from the original. The arguments and options aren't copied.
+impl<'a, 'b> Clone for Request<'a, 'b> {
+    fn clone(&self) -> Self {
+        let mut req = Request {
+            command: self.command,
+            arguments: self.arguments.clone(),
+            options: self.options.clone(),
+            context: self.context.clone(),
+            response: RefCell::new(Response::new(Encoding::Text,
+                                                 Verbosity::Normal,
+                                                 Box::new(io::stdout()))),
+            progress: self.progress.clone(),
+            stdin: self.stdin.clone(),
+        };
+        req.set_output(Box::new(io::stdout()));
+        req
+    }
 }
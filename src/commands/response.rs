@@ -0,0 +1,227 @@
+// A command's output. Run functions emit items into the request's Response
+// rather than printing them, and the encoder picked with the global
+// --encoding option turns them into text or JSON. The items are also kept,
+// so the output of a run can be inspected without capturing stdout.
+//
+// With --encoding json, every line of the output is a JSON value of its own:
+// one per item, as it's emitted, and an error object if the command fails,
+// so scripts can read the output line by line even while it streams.
+
+use super::CommandError;
+use super::request::Verbosity;
+
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+pub const DEFAULT_ENCODING: &'static str = "text";
+
+// the names `Encoding::by_name` accepts
+pub const ENCODINGS: &'static [&'static str] = &["text", "json"];
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Encoding {
+    Text,
+    Json,
+}
+
+impl Encoding {
+    pub fn by_name(name: &str) -> Result<Self, String> {
+        match name {
+            "text" => Ok(Encoding::Text),
+            "json" => Ok(Encoding::Json),
+            _ => Err(format!("Unknown encoding {}, expected text or json", name)),
+        }
+    }
+
+    // The encoding a command line asks for with --encoding, for errors that
+    // are printed before it's parsed, or because it can't be. Anything but a
+    // known encoding is text.
+    pub fn requested(args: &[String]) -> Self {
+        let mut requested = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                break;
+            }
+            if arg.starts_with("--encoding=") {
+                requested = Some(arg["--encoding=".len()..].to_string());
+            } else if arg == "--encoding" {
+                requested = args.next().cloned();
+            }
+        }
+        requested.and_then(|name| Encoding::by_name(&name).ok()).unwrap_or(Encoding::Text)
+    }
+
+    // How an error that ends the output is printed: as its message, or as a
+    // JSON object like {"error":{"type":"client","message":"...","code":2}}
+    pub fn error_line(&self, e: &CommandError) -> String {
+        match *self {
+            Encoding::Text => format!("{}", e),
+            Encoding::Json => {
+                let mut error = BTreeMap::new();
+                error.insert("type".to_string(), e.kind().to_json());
+                error.insert("message".to_string(), e.message().to_json());
+                error.insert("code".to_string(), e.exit_code().to_json());
+                let mut obj = BTreeMap::new();
+                obj.insert("error".to_string(), Json::Object(error));
+                format!("{}", Json::Object(obj))
+            }
+        }
+    }
+}
+
+// One item of output. `essential` is the part scripts care about (e.g. the
+// hash), which is all that's kept with -q, and `value` is the item as
+// structured data for machine-readable encodings.
+pub struct Item {
+    pub line: String,
+    pub essential: String,
+    pub value: Json,
+}
+
+pub trait Encoder {
+    fn item(&mut self, out: &mut Write, item: &Item) -> io::Result<()>;
+    // called once the command has run successfully
+    fn finish(&mut self, out: &mut Write) -> io::Result<()>;
+}
+
+// Prints each item as it's emitted, except with -Q, where only the last
+// item's essential part is printed at the end
+pub struct TextEncoder {
+    verbosity: Verbosity,
+    last_essential: Option<String>,
+}
+
+impl Encoder for TextEncoder {
+    fn item(&mut self, out: &mut Write, item: &Item) -> io::Result<()> {
+        match self.verbosity {
+            Verbosity::Normal => writeln!(out, "{}", item.line),
+            Verbosity::Quiet => writeln!(out, "{}", item.essential),
+            Verbosity::Quieter => {
+                self.last_essential = Some(item.essential.clone());
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(&mut self, out: &mut Write) -> io::Result<()> {
+        match self.last_essential.take() {
+            Some(essential) => writeln!(out, "{}", essential),
+            None => Ok(()),
+        }
+    }
+}
+
+// Prints each item as it's emitted, as one line of JSON: its value, or its
+// essential part as a string with -q. With -Q, only the last essential part
+// is printed at the end.
+pub struct JsonEncoder {
+    verbosity: Verbosity,
+    last_essential: Option<String>,
+}
+
+impl Encoder for JsonEncoder {
+    fn item(&mut self, out: &mut Write, item: &Item) -> io::Result<()> {
+        match self.verbosity {
+            Verbosity::Normal => writeln!(out, "{}", item.value),
+            Verbosity::Quiet => writeln!(out, "{}", item.essential.to_json()),
+            Verbosity::Quieter => {
+                self.last_essential = Some(item.essential.clone());
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(&mut self, out: &mut Write) -> io::Result<()> {
+        match self.last_essential.take() {
+            Some(essential) => writeln!(out, "{}", essential.to_json()),
+            None => Ok(()),
+        }
+    }
+}
+
+// Send, like everything else a Request holds, so that a request can be
+// handed to the thread that runs it
+pub struct Response {
+    items: Vec<Item>,
+    encoder: Box<Encoder + Send>,
+    out: Box<Write + Send>,
+}
+
+impl Response {
+    pub fn new(encoding: Encoding, verbosity: Verbosity, out: Box<Write + Send>) -> Self {
+        let encoder: Box<Encoder + Send> = match encoding {
+            Encoding::Text => {
+                Box::new(TextEncoder {
+                    verbosity: verbosity,
+                    last_essential: None,
+                })
+            }
+            Encoding::Json => {
+                Box::new(JsonEncoder {
+                    verbosity: verbosity,
+                    last_essential: None,
+                })
+            }
+        };
+        Response {
+            items: Vec::new(),
+            encoder: encoder,
+            out: out,
+        }
+    }
+
+    pub fn emit(&mut self, item: Item) {
+        // output that can't be written (e.g. a closed pipe) isn't worth
+        // failing the command over
+        let _ = self.encoder.item(&mut *self.out, &item);
+        self.items.push(item);
+    }
+
+    pub fn finish(&mut self) {
+        let _ = self.encoder.finish(&mut *self.out);
+        let _ = self.out.flush();
+    }
+
+    pub fn items(&self) -> &[Item] {
+        &self.items
+    }
+
+    // Where output that isn't made of items, like a file's content or the
+    // help text, is written
+    pub fn output(&mut self) -> &mut Box<Write + Send> {
+        &mut self.out
+    }
+}
+
+// A Write whose contents can still be read once it has been boxed up as a
+// command's output, from whichever thread the command runs in
+#[derive(Clone)]
+pub struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn new() -> Self {
+        SharedBuffer(Arc::new(Mutex::new(Vec::new())))
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        self.lock().clone()
+    }
+
+    // a writer that panicked leaves what it wrote, which is still worth having
+    fn lock(&self) -> ::std::sync::MutexGuard<Vec<u8>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+// Runs a command line in-process, as the command line front-end would, and
+// captures everything that comes of it, so commands can be exercised without
+// spawning the binary:
+//
+//     let outcome = testing::execute(&*root, &["block", "put"], Some(b"data"), context);
+//     assert_eq!(outcome.exit_code, 0);
+//     assert_eq!(outcome.response.items().len(), 1);
+//
+// The command goes through parsing, validation and dispatch just as it does
+// from the command line, but isn't sent to a daemon, and isn't recorded in
+// the audit log or stats. ipfs_commands/tests.rs runs the command tree
+// through it.
+
+use super::{cli, dispatch, Command, CommandError};
+use super::progress;
+use super::request::{Context, Request, Verbosity};
+use super::response::{Encoding, Response, SharedBuffer};
+use crashreport;
+
+use std::io::Write;
+use std::sync::mpsc;
+
+pub struct Outcome {
+    pub response: Response, // holds the items the command emitted
+    // what would have been printed, ending with the error as the front-end
+    // prints it
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>, // the progress bars drawn with --progress
+    pub exit_code: i32,
+}
+
+// Runs `args` against `root` in `context`. The command reads `stdin` as if it
+// were piped in, or no stdin at all if it's None.
+pub fn execute(root: &Command, args: &[&str], stdin: Option<&[u8]>, context: Context) -> Outcome {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    let encoding = Encoding::requested(&args[..]);
+    let output = SharedBuffer::new();
+    let path = crashreport::command_path(root, &args[..]);
+    let (cmd, cmd_args, opts) = match cli::parse(args.into_iter(), root) {
+        Ok(parse) => parse,
+        Err(e) => {
+            let response = Response::new(Encoding::Text,
+                                         Verbosity::Normal,
+                                         Box::new(output.clone()));
+            return failed(response,
+                          output.contents(),
+                          Vec::new(),
+                          CommandError::Client(e),
+                          encoding);
+        }
+    };
+
+    let mut req = Request::new(cmd, cmd_args, opts, context);
+    req.set_output(Box::new(output.clone()));
+    req.set_stdin(stdin.map(|data| data.to_vec()));
+    let (events, received) = mpsc::channel();
+    req.set_progress(events);
+
+    let result = dispatch(&req, &path);
+    // dropping the request drops the sending end of the progress events
+    let response = req.into_response();
+    let mut stderr = Vec::new();
+    progress::draw(received, &mut stderr);
+
+    match result {
+        Ok(()) => {
+            Outcome {
+                response: response,
+                stdout: output.contents(),
+                stderr: stderr,
+                exit_code: 0,
+            }
+        }
+        Err(e) => failed(response, output.contents(), stderr, e, encoding),
+    }
+}
+
+fn failed(response: Response,
+          mut stdout: Vec<u8>,
+          stderr: Vec<u8>,
+          e: CommandError,
+          encoding: Encoding)
+          -> Outcome {
+    let _ = writeln!(stdout, "{}", encoding.error_line(&e));
+    Outcome {
+        response: response,
+        stdout: stdout,
+        stderr: stderr,
+        exit_code: e.exit_code(),
+    }
+}
@@ -1,11 +1,12 @@
 use crypto;
+use hash;
 use util;
 
 use rustc_serialize::Decodable;
 use rustc_serialize::base64::{self, ToBase64};
 use rustc_serialize::json::{self, Json};
 use rust_multihash::Multihash;
-use std::io::Read;
+use std::io::{self, Read, Write};
 use std::path::PathBuf;
 
 pub const DEFAULT_REPO_ROOT: &'static str = "~/";
@@ -22,15 +23,87 @@ pub struct Identity {
     pub private_key: String,
 }
 
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct Encryption {
+    // base64-encoded salt used when deriving the key from the repo passphrase
+    pub salt: String,
+    // base64-encoded sealed known value, used to reject a wrong key early
+    pub key_check: String,
+    // if set, the key is read (base64-encoded) from this command's stdout
+    // instead of being derived from the passphrase
+    pub key_command: Option<String>,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct Datastore {
+    // zstd-compress block data when it is written to the blockstore
+    pub compress_blocks: bool,
+    // encrypt block data at rest. None means blocks are stored in plaintext
+    pub encryption: Option<Encryption>,
+    // maximum number of bytes the blockstore may grow to
+    pub storage_max: Option<u64>,
+    // whether blocks written by an import that was aborted for exceeding a
+    // size limit are removed (the default), or left in the blockstore
+    pub cleanup_aborted_imports: Option<bool>,
+    // refuse to run any command that would modify the repo
+    pub read_only: Option<bool>,
+    // store blocks that are similar to ones already stored as deltas
+    pub delta_blocks: Option<bool>,
+}
+
+impl Default for Datastore {
+    fn default() -> Self {
+        Datastore {
+            compress_blocks: false,
+            encryption: None,
+            storage_max: None,
+            cleanup_aborted_imports: None,
+            read_only: None,
+            delta_blocks: None,
+        }
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct Import {
+    // name of the hash function objects are addressed with, e.g. "sha2-512"
+    pub hash: Option<String>,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct Network {
+    // "host:port" of the HTTP proxy outbound HTTP requests go through
+    pub http_proxy: Option<String>,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct Reports {
+    // crash reports are only sent anywhere if this is explicitly set to true
+    pub submit: bool,
+    pub endpoint: Option<String>,
+}
+
 #[derive(RustcEncodable, RustcDecodable)]
 pub struct Config {
     pub identity: Identity,
+    // optional so that configs written before this section existed still decode
+    pub datastore: Option<Datastore>,
+    pub reports: Option<Reports>,
+    pub import: Option<Import>,
+    pub network: Option<Network>,
 }
 
 impl Config {
+    // Keys the schema doesn't know are likely typos, so they're warned about,
+    // and the values of known keys are checked before decoding so that an
+    // error can name the key at fault.
     pub fn from_reader<R: Read>(reader: &mut R) -> Result<Config, String> {
         let json = try!(Json::from_reader(reader)
                             .map_err(|e| format!("Error parsing Json: {}", e)));
+        for key in unknown_keys(&json) {
+            let _ = writeln!(io::stderr(), "warning: unknown config key {}", key);
+        }
+        try!(check_values(&json));
         let mut decoder = json::Decoder::new(json);
         Decodable::decode(&mut decoder)
             .map_err(|e| format!("Error decoding Config from reader: {}", e))
@@ -39,6 +112,116 @@ impl Config {
     pub fn to_json_string(&self) -> json::EncodeResult<String> {
         json::encode(self)
     }
+
+    pub fn compress_blocks(&self) -> bool {
+        self.datastore.as_ref().map_or(false, |ds| ds.compress_blocks)
+    }
+
+    pub fn set_compress_blocks(&mut self, compress: bool) {
+        self.datastore_mut().compress_blocks = compress;
+    }
+
+    // The endpoint crash reports should be submitted to, if the user opted in
+    pub fn report_endpoint(&self) -> Option<&str> {
+        match self.reports {
+            Some(Reports { submit: true, endpoint: Some(ref endpoint) }) => Some(endpoint),
+            _ => None,
+        }
+    }
+
+    pub fn http_proxy(&self) -> Option<&str> {
+        self.network
+            .as_ref()
+            .and_then(|network| network.http_proxy.as_ref())
+            .map(|proxy| &proxy[..])
+    }
+
+    pub fn encryption(&self) -> Option<&Encryption> {
+        self.datastore.as_ref().and_then(|ds| ds.encryption.as_ref())
+    }
+
+    pub fn set_encryption(&mut self, encryption: Encryption) {
+        self.datastore_mut().encryption = Some(encryption);
+    }
+
+    pub fn storage_max(&self) -> Option<u64> {
+        self.datastore.as_ref().and_then(|ds| ds.storage_max)
+    }
+
+    pub fn import_hash(&self) -> &str {
+        self.import
+            .as_ref()
+            .and_then(|import| import.hash.as_ref())
+            .map_or(hash::DEFAULT_HASH, |name| &name[..])
+    }
+
+    pub fn cleanup_aborted_imports(&self) -> bool {
+        self.datastore.as_ref().and_then(|ds| ds.cleanup_aborted_imports).unwrap_or(true)
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.datastore.as_ref().and_then(|ds| ds.read_only).unwrap_or(false)
+    }
+
+    pub fn delta_blocks(&self) -> bool {
+        self.datastore.as_ref().and_then(|ds| ds.delta_blocks).unwrap_or(false)
+    }
+
+    // Sets one of the KEYS to a value from `parse_value`. Null unsets keys
+    // that are optional.
+    pub fn set(&mut self, path: &str, value: &Json) -> Result<(), String> {
+        let key = try!(key(path).ok_or_else(|| format!("Unknown config key {}", path)));
+        try!(check_value(key, value));
+        let string = value.as_string().map(|s| s.to_string());
+        match path {
+            "datastore.compress_blocks" => {
+                self.datastore_mut().compress_blocks = value.as_boolean().unwrap_or(false)
+            }
+            "datastore.storage_max" => self.datastore_mut().storage_max = value.as_u64(),
+            "datastore.cleanup_aborted_imports" => {
+                self.datastore_mut().cleanup_aborted_imports = value.as_boolean()
+            }
+            "datastore.read_only" => self.datastore_mut().read_only = value.as_boolean(),
+            "datastore.delta_blocks" => self.datastore_mut().delta_blocks = value.as_boolean(),
+            "import.hash" => self.import_mut().hash = string,
+            "network.http_proxy" => self.network_mut().http_proxy = string,
+            "reports.submit" => self.reports_mut().submit = value.as_boolean().unwrap_or(false),
+            "reports.endpoint" => self.reports_mut().endpoint = string,
+            _ => return Err(format!("Config key {} can't be set", path)),
+        }
+        Ok(())
+    }
+
+    fn datastore_mut(&mut self) -> &mut Datastore {
+        if self.datastore.is_none() {
+            self.datastore = Some(Datastore::default());
+        }
+        self.datastore.as_mut().unwrap()
+    }
+
+    fn import_mut(&mut self) -> &mut Import {
+        if self.import.is_none() {
+            self.import = Some(Import { hash: None });
+        }
+        self.import.as_mut().unwrap()
+    }
+
+    fn network_mut(&mut self) -> &mut Network {
+        if self.network.is_none() {
+            self.network = Some(Network { http_proxy: None });
+        }
+        self.network.as_mut().unwrap()
+    }
+
+    fn reports_mut(&mut self) -> &mut Reports {
+        if self.reports.is_none() {
+            self.reports = Some(Reports {
+                submit: false,
+                endpoint: None,
+            });
+        }
+        self.reports.as_mut().unwrap()
+    }
 }
 
 pub fn repo_path_to_config_file(mut repo_path: PathBuf) -> PathBuf {
@@ -56,5 +239,266 @@ pub fn init(num_key_pair_bits: usize) -> Config {
             peer_id: util::hash(&pub_bytes[..]),
             private_key: priv_b64_string,
         },
+        datastore: Some(Datastore::default()),
+        reports: None,
+        import: None,
+        network: None,
+    }
+}
+
+// The kind of value a settable config key holds
+#[derive(Copy, Clone)]
+pub enum ValueType {
+    Bool,
+    String,
+    // a number of bytes, which can be given with a suffix like 100M
+    Size,
+    // the name of a hash function known to the hash module
+    HashName,
+    // a proxy address as util::http_client takes it, e.g. "proxy.local:3128"
+    HostPort,
+    // an http or https URL, e.g. "https://reports.example.com/submit"
+    Url,
+}
+
+impl ValueType {
+    fn describe(&self) -> &'static str {
+        match *self {
+            ValueType::Bool => "true or false",
+            ValueType::String => "a string",
+            ValueType::Size => "a number of bytes",
+            ValueType::HashName => "the name of a hash function",
+            ValueType::HostPort => "a host:port address",
+            ValueType::Url => "an http or https URL",
+        }
+    }
+
+    // Checks a string value of this type, e.g. that a Url has a host
+    fn check(&self, value: &str) -> Result<(), String> {
+        match *self {
+            ValueType::HashName => hash::by_name(value).map(|_| ()),
+            ValueType::HostPort => util::parse_proxy(value).map(|_| ()),
+            ValueType::Url => check_url(value),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn check_url(url: &str) -> Result<(), String> {
+    let rest = if url.starts_with("http://") {
+        &url["http://".len()..]
+    } else if url.starts_with("https://") {
+        &url["https://".len()..]
+    } else {
+        return Err(format!("{} is not an http or https URL", url));
+    };
+    let authority = rest.split(|c| c == '/' || c == '?' || c == '#').next().unwrap_or("");
+    util::split_host_port(authority)
+        .map(|_| ())
+        .map_err(|e| format!("{} in URL {}", e, url))
+}
+
+// A key that can be set in the config, by its dotted path, e.g.
+// "datastore.storage_max"
+pub struct Key {
+    pub path: &'static str,
+    pub ty: ValueType,
+    pub description: &'static str,
+}
+
+pub const KEYS: &'static [Key] = &[
+    Key {
+        path: "datastore.compress_blocks",
+        ty: ValueType::Bool,
+        description: "zstd-compress blocks as they're written (see `ipfs repo compress`)",
+    },
+    Key {
+        path: "datastore.storage_max",
+        ty: ValueType::Size,
+        description: "the most bytes the blockstore may grow to",
+    },
+    Key {
+        path: "datastore.cleanup_aborted_imports",
+        ty: ValueType::Bool,
+        description: "remove the blocks written by an import that went over a size limit",
+    },
+    Key {
+        path: "datastore.read_only",
+        ty: ValueType::Bool,
+        description: "refuse to run commands that would modify the repo",
+    },
+    Key {
+        path: "datastore.delta_blocks",
+        ty: ValueType::Bool,
+        description: "store blocks similar to stored ones as deltas against them",
+    },
+    Key {
+        path: "import.hash",
+        ty: ValueType::HashName,
+        description: "the hash function objects are added with",
+    },
+    Key {
+        path: "network.http_proxy",
+        ty: ValueType::HostPort,
+        description: "host:port of the proxy outbound HTTP requests go through",
+    },
+    Key {
+        path: "reports.submit",
+        ty: ValueType::Bool,
+        description: "send crash reports to reports.endpoint",
+    },
+    Key {
+        path: "reports.endpoint",
+        ty: ValueType::Url,
+        description: "the URL crash reports are sent to",
+    },
+];
+
+// Keys whose values are managed by ipfs commands rather than set by hand
+const MANAGED_KEYS: &'static [&'static str] = &["identity.peer_id",
+                                                 "identity.private_key",
+                                                 "datastore.encryption.salt",
+                                                 "datastore.encryption.key_check",
+                                                 "datastore.encryption.key_command"];
+
+pub fn key(path: &str) -> Option<&'static Key> {
+    KEYS.iter().find(|key| key.path == path)
+}
+
+fn is_known_leaf(path: &str) -> bool {
+    key(path).is_some() || MANAGED_KEYS.contains(&path)
+}
+
+fn is_known_section(path: &str) -> bool {
+    let prefix = format!("{}.", path);
+    KEYS.iter().any(|key| key.path.starts_with(&prefix)) ||
+    MANAGED_KEYS.iter().any(|key| key.starts_with(&prefix))
+}
+
+// Whether `path` names a key or a section of keys the schema knows
+pub fn is_known_path(path: &str) -> bool {
+    is_known_leaf(path) || is_known_section(path)
+}
+
+// The dotted paths of the keys in a config that the schema doesn't know
+pub fn unknown_keys(json: &Json) -> Vec<String> {
+    let mut unknown = Vec::new();
+    collect_unknown_keys(json, "", &mut unknown);
+    unknown
+}
+
+fn collect_unknown_keys(json: &Json, prefix: &str, unknown: &mut Vec<String>) {
+    if let Json::Object(ref obj) = *json {
+        for (name, value) in obj.iter() {
+            let path = if prefix.is_empty() {
+                name.clone()
+            } else {
+                format!("{}.{}", prefix, name)
+            };
+            if is_known_section(&path) {
+                collect_unknown_keys(value, &path, unknown);
+            } else if !is_known_leaf(&path) {
+                unknown.push(path);
+            }
+        }
+    }
+}
+
+// The value at a dotted path, if the config has one
+pub fn lookup<'a>(json: &'a Json, path: &str) -> Option<&'a Json> {
+    path.split('.').fold(Some(json), |json, name| json.and_then(|json| json.find(name)))
+}
+
+fn check_value(key: &Key, value: &Json) -> Result<(), String> {
+    let ok = match (key.ty, value) {
+        (_, &Json::Null) => true,
+        (ValueType::Bool, &Json::Boolean(_)) => true,
+        (ValueType::String, &Json::String(_)) => true,
+        (ValueType::Size, &Json::U64(_)) => true,
+        (ValueType::HashName, &Json::String(ref s)) |
+        (ValueType::HostPort, &Json::String(ref s)) |
+        (ValueType::Url, &Json::String(ref s)) => key.ty.check(s).is_ok(),
+        _ => false,
+    };
+    if ok {
+        Ok(())
+    } else {
+        Err(format!("Invalid value for config key {}: expected {}, got {}",
+                    key.path,
+                    key.ty.describe(),
+                    value))
+    }
+}
+
+fn check_values(json: &Json) -> Result<(), String> {
+    for key in KEYS.iter() {
+        if let Some(value) = lookup(json, key.path) {
+            try!(check_value(key, value));
+        }
+    }
+    Ok(())
+}
+
+// Parses a value given on the command line for the key at `path`, checking
+// it against the key's type
+pub fn parse_value(path: &str, value: &str) -> Result<Json, String> {
+    let key = try!(key(path).ok_or_else(|| format!("Unknown config key {}", path)));
+    let invalid = |e: String| format!("Invalid value for config key {}: {}", path, e);
+    let json = match key.ty {
+        ValueType::Bool => {
+            match value {
+                "true" => Json::Boolean(true),
+                "false" => Json::Boolean(false),
+                _ => return Err(invalid(format!("expected true or false, got {:?}", value))),
+            }
+        }
+        ValueType::String => Json::String(value.to_string()),
+        ValueType::Size => Json::U64(try!(util::parse_size(value).map_err(&invalid))),
+        ValueType::HashName | ValueType::HostPort | ValueType::Url => {
+            try!(key.ty.check(value).map_err(&invalid));
+            Json::String(value.to_string())
+        }
+    };
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_value, key, parse_value};
+    use rustc_serialize::json::Json;
+
+    #[test]
+    fn proxies_are_host_port_addresses() {
+        let proxies = ["proxy.local:3128", "http://proxy.local:3128/", "proxy.local", "[::1]:8080"];
+        for proxy in proxies.iter() {
+            assert_eq!(parse_value("network.http_proxy", proxy),
+                       Ok(Json::String(proxy.to_string())));
+        }
+        for proxy in &["proxy.local:port", "proxy.local:99999", ":3128", "a b:3128"] {
+            assert!(parse_value("network.http_proxy", proxy).is_err(), "{}", proxy);
+        }
+    }
+
+    #[test]
+    fn report_endpoints_are_http_urls() {
+        for url in &["https://reports.example.com/submit", "http://localhost:8000?x=1"] {
+            assert_eq!(parse_value("reports.endpoint", url), Ok(Json::String(url.to_string())));
+        }
+        let urls = ["reports.example.com", "ftp://reports.example.com", "https://", "https://a:b/"];
+        for url in urls.iter() {
+            assert!(parse_value("reports.endpoint", url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn values_read_from_the_config_file_are_checked_too() {
+        let proxy = key("network.http_proxy").unwrap();
+        let endpoint = key("reports.endpoint").unwrap();
+        assert!(check_value(proxy, &Json::String("proxy.local:3128".to_string())).is_ok());
+        assert!(check_value(proxy, &Json::String("proxy.local:x".to_string())).is_err());
+        assert!(check_value(proxy, &Json::U64(3128)).is_err());
+        assert!(check_value(endpoint, &Json::String("https://example.com".to_string())).is_ok());
+        assert!(check_value(endpoint, &Json::String("example.com".to_string())).is_err());
+        assert!(check_value(endpoint, &Json::Null).is_ok());
     }
 }
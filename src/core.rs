@@ -1,6 +1,7 @@
 use blockstore::Blockstore;
 use config::Config;
 use merkledag::DagService;
+use merkledag::sizes::SizeIndex;
 
 use std::sync::Arc;
 
@@ -11,12 +12,12 @@ pub struct IpfsNode {
 }
 
 impl IpfsNode {
-    pub fn new(blockstore: Blockstore, cfg: Config) -> Self {
+    pub fn new(blockstore: Blockstore, sizes: SizeIndex, cfg: Config) -> Self {
         let bs = Arc::new(blockstore);
         IpfsNode {
             config: cfg,
             blockstore: bs.clone(),
-            dagservice: Arc::new(DagService::new(bs)),
+            dagservice: Arc::new(DagService::new(bs, sizes)),
         }
     }
 }
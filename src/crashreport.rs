@@ -0,0 +1,272 @@
+use clock::Clock;
+use commands::Command;
+use util;
+
+use backtrace::Backtrace;
+use hyper::header::ContentType;
+use rustc_serialize::json;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::panic;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const REPORTS_DIR: &'static str = "reports";
+const REPORT_PREFIX: &'static str = "crash-";
+const REPORT_EXT: &'static str = ".json";
+const SUBMIT_TIMEOUT_SECS: u64 = 10;
+
+// A crash report only records where the crash happened, never the panic
+// message or command arguments, since those may contain user data.
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct CrashReport {
+    pub version: String,
+    pub command: String,
+    pub location: String,
+    pub time: u64, // seconds since the unix epoch
+    pub backtrace: String,
+}
+
+impl CrashReport {
+    // A report of a crash at `location` that happened just now
+    fn new(command: String, location: String, clock: &Clock) -> Self {
+        CrashReport {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            command: command,
+            location: location,
+            time: clock.unix_time(),
+            backtrace: format!("{:?}", Backtrace::new()),
+        }
+    }
+}
+
+// Installs a panic hook which saves a crash report to the repo, unless
+// `repo_path` is None, e.g. because the repo is read-only, and submits it to
+// `endpoint` if one is given, through `proxy` if set. Reports are timestamped
+// by `clock`. The default panic output is kept, and what happened to the
+// report is said on stderr too, so it doesn't end up in the command's output.
+pub fn install_hook(repo_path: Option<PathBuf>,
+                    command: String,
+                    endpoint: Option<String>,
+                    proxy: Option<String>,
+                    clock: Arc<Clock>) {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+
+        let location = info.location()
+                           .map(|loc| format!("{}:{}", loc.file(), loc.line()))
+                           .unwrap_or(String::new());
+        let report = CrashReport::new(command.clone(), location, &*clock);
+
+        match repo_path.clone().map(|repo_path| save(repo_path, &report)) {
+            None => {}
+            Some(Err(e)) => {
+                let _ = writeln!(io::stderr(), "Could not save crash report: {}", e);
+            }
+            Some(Ok(id)) => {
+                let _ = writeln!(io::stderr(),
+                                 "Saved crash report {}. Use `ipfs diag reports ls` to list \
+                                  reports.",
+                                 id);
+            }
+        }
+
+        if let Some(ref endpoint) = endpoint {
+            if let Err(e) = submit(endpoint, proxy.as_ref().map(|p| &p[..]), &report) {
+                let _ = writeln!(io::stderr(), "Could not submit crash report: {}", e);
+            }
+        }
+    }));
+}
+
+// The space-separated names of the commands in `args`, e.g. "repo compress",
+// stopping at the first token that isn't a subcommand
+pub fn command_path(root: &Command, args: &[String]) -> String {
+    let mut names = Vec::new();
+    let mut cmd = root;
+    for arg in args {
+        if arg.starts_with("-") {
+            continue;
+        }
+        match cmd.get_subcommand(arg) {
+            None => break,
+            Some(subcmd) => {
+                names.push(subcmd.get_name());
+                cmd = subcmd;
+            }
+        }
+    }
+    names.join(" ")
+}
+
+fn reports_dir(mut repo_path: PathBuf) -> PathBuf {
+    repo_path.push(REPORTS_DIR);
+    repo_path
+}
+
+fn report_file(repo_path: PathBuf, id: &str) -> PathBuf {
+    let mut path = reports_dir(repo_path);
+    path.push(id.to_string() + REPORT_EXT);
+    path
+}
+
+// Whether `id` is one `save` could have given: crash-<time>, or
+// crash-<time>-<n> for a report saved in the same second as others. No other
+// id is looked up, so one can't name a file outside the reports dir.
+fn is_report_id(id: &str) -> bool {
+    if !id.starts_with(REPORT_PREFIX) {
+        return false;
+    }
+    let parts: Vec<&str> = id[REPORT_PREFIX.len()..].split('-').collect();
+    parts.len() <= 2 &&
+    parts.iter().all(|part| !part.is_empty() && part.chars().all(|c| c.is_digit(10)))
+}
+
+// Saves the report and returns its id
+pub fn save(repo_path: PathBuf, report: &CrashReport) -> Result<String, String> {
+    try!(util::ensure_dir_writable(reports_dir(repo_path.clone()))
+             .map_err(|e| format!("Error checking writability of reports dir: {}", e)));
+
+    let encoded = try!(json::encode(report)
+                           .map_err(|e| format!("Error encoding crash report: {}", e)));
+    // a report never replaces another, even one saved in the same second
+    let mut n = 0;
+    let (id, mut file) = loop {
+        let id = if n == 0 {
+            format!("{}{}", REPORT_PREFIX, report.time)
+        } else {
+            format!("{}{}-{}", REPORT_PREFIX, report.time, n)
+        };
+        match OpenOptions::new().write(true).create_new(true).open(report_file(repo_path.clone(),
+                                                                               &id)) {
+            Ok(file) => break (id, file),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(format!("Error creating crash report file: {}", e)),
+        }
+    };
+    try!(file.write_all(encoded.as_bytes())
+             .map_err(|e| format!("Error writing crash report file: {}", e)));
+    Ok(id)
+}
+
+// Lists the saved reports along with their ids, oldest first
+pub fn list(repo_path: PathBuf) -> Result<Vec<(String, CrashReport)>, String> {
+    let dir = reports_dir(repo_path);
+    if !try!(util::file_exists(&dir).map_err(|e| format!("{}", e))) {
+        return Ok(vec![]);
+    }
+
+    let entries = try!(fs::read_dir(&dir).map_err(|e| {
+        format!("Error reading reports directory {:?}: {}", dir, e)
+    }));
+
+    let mut reports = Vec::new();
+    for entry in entries {
+        let path = try!(entry.map_err(|e| format!("{}", e))).path();
+        let id = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if name.ends_with(REPORT_EXT) &&
+                          is_report_id(&name[..name.len() - REPORT_EXT.len()]) => {
+                name[..name.len() - REPORT_EXT.len()].to_string()
+            }
+            _ => continue,
+        };
+
+        let mut s = String::new();
+        try!(File::open(&path)
+                 .and_then(|mut f| f.read_to_string(&mut s))
+                 .map_err(|e| format!("Error reading crash report {:?}: {}", path, e)));
+        let report: CrashReport = try!(json::decode(&s).map_err(|e| {
+            format!("Error decoding crash report {:?}: {}", path, e)
+        }));
+        reports.push((id, report));
+    }
+
+    // of the reports saved in the same second, those numbered higher came later
+    reports.sort_by(|a, b| (a.1.time, a.0.len(), &a.0).cmp(&(b.1.time, b.0.len(), &b.0)));
+    Ok(reports)
+}
+
+pub fn remove(repo_path: PathBuf, id: &str) -> Result<(), String> {
+    if !is_report_id(id) {
+        return Err(format!("No crash report with id {}", id));
+    }
+    let path = report_file(repo_path, id);
+    if !try!(util::file_exists(&path).map_err(|e| format!("{}", e))) {
+        return Err(format!("No crash report with id {}", id));
+    }
+    fs::remove_file(&path).map_err(|e| format!("Error removing crash report {}: {}", id, e))
+}
+
+// POSTs the report as JSON
+fn submit(endpoint: &str, proxy: Option<&str>, report: &CrashReport) -> Result<(), String> {
+    let body = try!(json::encode(report)
+                        .map_err(|e| format!("Error encoding crash report: {}", e)));
+
+    let mut client = try!(util::http_client(proxy));
+    let timeout = Some(Duration::from_secs(SUBMIT_TIMEOUT_SECS));
+    client.set_read_timeout(timeout);
+    client.set_write_timeout(timeout);
+
+    let res = try!(client.post(endpoint)
+                         .header(ContentType::json())
+                         .body(&body[..])
+                         .send()
+                         .map_err(|e| format!("Error sending crash report: {}", e)));
+    if res.status.is_success() {
+        Ok(())
+    } else {
+        Err(format!("Report endpoint responded with status {}", res.status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::MockClock;
+
+    use std::env;
+    use std::fs;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn report(time: u64) -> CrashReport {
+        CrashReport {
+            version: "0.1.0".to_string(),
+            command: "add".to_string(),
+            location: "src/main.rs:1".to_string(),
+            time: time,
+            backtrace: String::new(),
+        }
+    }
+
+    #[test]
+    fn reports_saved_in_the_same_second_are_all_kept() {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+        let repo = env::temp_dir().join(format!("crashreport-test-{}", nanos));
+        let ids: Vec<String> = (0..3).map(|_| save(repo.clone(), &report(7)).unwrap()).collect();
+        assert_eq!(ids, vec!["crash-7", "crash-7-1", "crash-7-2"]);
+        assert!(ids.iter().all(|id| report_file(repo.clone(), id).is_file()));
+        fs::remove_dir_all(&repo).unwrap();
+    }
+
+    #[test]
+    fn reports_are_timestamped_by_the_clock() {
+        let report = CrashReport::new("add".to_string(),
+                                      "src/main.rs:1".to_string(),
+                                      &MockClock::new(1500000000));
+        assert_eq!(report.time, 1500000000);
+    }
+
+    #[test]
+    fn only_report_ids_are_removed() {
+        assert!(is_report_id("crash-1500000000"));
+        assert!(is_report_id("crash-1500000000-2"));
+        assert!(!is_report_id("crash-"));
+        assert!(!is_report_id("crash-1-2-3"));
+        assert!(!is_report_id("../config"));
+        assert!(!is_report_id("crash-1/../../config"));
+        let err = remove(PathBuf::from("/nonexistent"), "../../etc/passwd").unwrap_err();
+        assert_eq!(err, "No crash report with id ../../etc/passwd");
+    }
+}
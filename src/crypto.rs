@@ -1,7 +1,218 @@
+use config;
+use util;
+
 use openssl::crypto::pkey;
+use openssl::crypto::rand;
+use rust_crypto::aead::{AeadDecryptor, AeadEncryptor};
+use rust_crypto::aes::KeySize;
+use rust_crypto::aes_gcm::AesGcm;
+use rust_crypto::hmac::Hmac;
+use rust_crypto::pbkdf2;
+use rust_crypto::sha2::Sha256;
+use rustc_serialize::base64::{self, FromBase64, ToBase64};
+use std::env;
+
+pub const ENV_NAME_REPO_PASSPHRASE: &'static str = "IPFS_REPO_PASSPHRASE";
+pub const ENV_NAME_NEW_REPO_PASSPHRASE: &'static str = "IPFS_NEW_REPO_PASSPHRASE";
+
+const KEY_LEN: usize = 32; // AES-256
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const PBKDF2_ROUNDS: u32 = 100000;
+const KEY_CHECK_DATA: &'static [u8] = b"ipfs repo key check";
 
 pub fn gen_key_pair(num_bits: usize) -> pkey::PKey {
     let mut key_pair = pkey::PKey::new();
     key_pair.gen(num_bits);
     key_pair
 }
+
+// Signs `data` with the node's private key, returning the signature and the
+// public key it can be checked with
+pub fn sign(identity: &config::Identity, data: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let priv_bytes = try!(identity.private_key
+                                  .from_base64()
+                                  .map_err(|e| format!("Error decoding private key: {}", e)));
+    let mut key_pair = pkey::PKey::new();
+    key_pair.load_priv(&priv_bytes[..]);
+    Ok((key_pair.sign(data), key_pair.save_pub()))
+}
+
+pub fn verify(pub_key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    let mut key = pkey::PKey::new();
+    key.load_pub(pub_key);
+    key.verify(data, signature)
+}
+
+// AES-256-GCM encryption of individual datastore values
+pub struct BlockCipher {
+    key: Vec<u8>,
+}
+
+impl BlockCipher {
+    pub fn new(key: Vec<u8>) -> Result<Self, String> {
+        if key.len() != KEY_LEN {
+            return Err(format!("Repo key must be {} bytes long, but got {} bytes",
+                               KEY_LEN,
+                               key.len()));
+        }
+        Ok(BlockCipher { key: key })
+    }
+
+    pub fn from_passphrase(passphrase: &str, salt: &[u8]) -> Self {
+        let mut mac = Hmac::new(Sha256::new(), passphrase.as_bytes());
+        let mut key = vec![0; KEY_LEN];
+        pbkdf2::pbkdf2(&mut mac, salt, PBKDF2_ROUNDS, &mut key[..]);
+        BlockCipher { key: key }
+    }
+
+    // Output is laid out as <nonce><ciphertext><tag>. `aad` is authenticated
+    // but not stored, so the same `aad` must be passed to `open`.
+    pub fn seal(&self, data: &[u8], aad: &[u8]) -> Vec<u8> {
+        let nonce = rand::rand_bytes(NONCE_LEN);
+        let mut ciphertext = vec![0; data.len()];
+        let mut tag = vec![0; TAG_LEN];
+
+        let mut gcm = AesGcm::new(KeySize::KeySize256, &self.key[..], &nonce[..], aad);
+        gcm.encrypt(data, &mut ciphertext[..], &mut tag[..]);
+
+        let mut sealed = nonce;
+        sealed.extend_from_slice(&ciphertext[..]);
+        sealed.extend_from_slice(&tag[..]);
+        sealed
+    }
+
+    pub fn open(&self, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+        if sealed.len() < NONCE_LEN + TAG_LEN {
+            return Err("Encrypted value is truncated".to_string());
+        }
+
+        let (nonce, rest) = sealed.split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+        let mut data = vec![0; ciphertext.len()];
+
+        let mut gcm = AesGcm::new(KeySize::KeySize256, &self.key[..], nonce, aad);
+        if !gcm.decrypt(ciphertext, &mut data[..], tag) {
+            return Err("Encrypted value failed authentication (wrong key or corrupted data)"
+                           .to_string());
+        }
+        Ok(data)
+    }
+}
+
+// A random salt for deriving a new key from a passphrase
+pub fn new_salt() -> Vec<u8> {
+    rand::rand_bytes(SALT_LEN)
+}
+
+// Produces a new key from either the key command, if given, or the passphrase
+// stored in the environment variable `passphrase_var`, along with the
+// config section needed to load it again later. The same inputs always
+// produce the same key, so an interrupted re-encryption can be rerun.
+pub fn new_repo_cipher(key_command: Option<String>,
+                       passphrase_var: &str,
+                       salt: Vec<u8>)
+                       -> Result<(BlockCipher, config::Encryption), String> {
+    let cipher = match key_command {
+        Some(ref cmd) => try!(run_key_command(cmd)),
+        None => BlockCipher::from_passphrase(&try!(read_passphrase(passphrase_var)), &salt[..]),
+    };
+
+    let encryption = config::Encryption {
+        salt: salt.to_base64(base64::STANDARD),
+        key_check: cipher.seal(KEY_CHECK_DATA, &[]).to_base64(base64::STANDARD),
+        key_command: key_command,
+    };
+    Ok((cipher, encryption))
+}
+
+// Loads the key for an encrypted repo and checks it against the config
+pub fn load_repo_cipher(encryption: &config::Encryption) -> Result<BlockCipher, String> {
+    let cipher = match encryption.key_command {
+        Some(ref cmd) => try!(run_key_command(cmd)),
+        None => {
+            let salt = try!(encryption.salt
+                                      .from_base64()
+                                      .map_err(|e| format!("Error decoding repo key salt: {}", e)));
+            let passphrase = try!(read_passphrase(ENV_NAME_REPO_PASSPHRASE));
+            BlockCipher::from_passphrase(&passphrase, &salt[..])
+        }
+    };
+
+    let key_check = try!(encryption.key_check
+                                   .from_base64()
+                                   .map_err(|e| format!("Error decoding repo key check: {}", e)));
+    match cipher.open(&key_check[..], &[]) {
+        Ok(ref data) if &data[..] == KEY_CHECK_DATA => Ok(cipher),
+        _ => Err("Incorrect key for encrypted repo".to_string()),
+    }
+}
+
+fn read_passphrase(var: &str) -> Result<String, String> {
+    env::var(var).map_err(|_| {
+        format!("No repo passphrase given. Please set {}.", var)
+    })
+}
+
+// The external key hook: a shell command which prints the base64-encoded key
+fn run_key_command(cmd: &str) -> Result<BlockCipher, String> {
+    let output = try!(util::shell_command(cmd)
+                          .output()
+                          .map_err(|e| format!("Error running key command {:?}: {}", cmd, e)));
+    if !output.status.success() {
+        return Err(format!("Key command {:?} failed with {}", cmd, output.status));
+    }
+
+    let stdout = try!(String::from_utf8(output.stdout).map_err(|e| {
+        format!("Key command {:?} printed invalid UTF-8: {}", cmd, e)
+    }));
+    let key = try!(stdout.trim()
+                         .from_base64()
+                         .map_err(|e| format!("Error decoding key from key command: {}", e)));
+    BlockCipher::new(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockCipher, KEY_LEN};
+    use util;
+
+    fn cipher(byte: u8) -> BlockCipher {
+        BlockCipher::new(vec![byte; KEY_LEN]).unwrap()
+    }
+
+    #[test]
+    fn sealed_values_open_with_the_same_key_and_aad() {
+        let hash = util::hash(b"block").into_bytes();
+        let sealed = cipher(1).seal(b"block", &hash[..]);
+        assert!(&sealed[..] != b"block");
+        assert_eq!(cipher(1).open(&sealed[..], &hash[..]), Ok(b"block".to_vec()));
+        // a fresh nonce every time
+        assert!(cipher(1).seal(b"block", &hash[..]) != sealed);
+    }
+
+    #[test]
+    fn values_sealed_under_one_hash_do_not_open_under_another() {
+        let hash = util::hash(b"block").into_bytes();
+        let other = util::hash(b"other block").into_bytes();
+        let sealed = cipher(1).seal(b"block", &hash[..]);
+        assert!(cipher(1).open(&sealed[..], &other[..]).is_err());
+        assert!(cipher(2).open(&sealed[..], &hash[..]).is_err());
+
+        let mut tampered = sealed.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(cipher(1).open(&tampered[..], &hash[..]).is_err());
+        assert_eq!(cipher(1).open(&sealed[..10], &hash[..]),
+                   Err("Encrypted value is truncated".to_string()));
+    }
+
+    #[test]
+    fn keys_are_checked_and_depend_on_the_salt() {
+        assert!(BlockCipher::new(vec![0; KEY_LEN - 1]).is_err());
+        let a = BlockCipher::from_passphrase("passphrase", b"salt");
+        let b = BlockCipher::from_passphrase("passphrase", b"other salt");
+        assert!(b.open(&a.seal(b"block", &[])[..], &[]).is_err());
+    }
+}
@@ -0,0 +1,421 @@
+// Delta encoding of blocks against similar ones. A block that's a new version
+// of one already stored, like a chunk of a file that was edited and added
+// again, is mostly the same bytes, so it can be stored as the few changes
+// that turn the older block into it.
+//
+// Similar blocks are found by their features: feature i of a block is the
+// largest value of the i-th of NUM_FEATURES hash functions applied to a
+// rolling hash at every position in it. A small edit changes few of the
+// rolling hashes, so blocks that share most of their content are likely to
+// share features. A delta is a list of ops, each copying a range of the base
+// block or inserting bytes given literally.
+
+use rust_multihash::Multihash;
+use rustc_serialize::hex::{FromHex, ToHex};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub const NUM_FEATURES: usize = 4;
+
+// blocks smaller than this aren't worth looking for a base for
+pub const MIN_BLOCK_SIZE: usize = 512;
+
+// how long a run of bytes the base and the block have to share for it to be
+// copied rather than inserted
+const MATCH_LEN: usize = 16;
+
+const OP_INSERT: u8 = 0;
+const OP_COPY: u8 = 1;
+
+// The features of a block, for finding blocks similar to it
+pub fn features(data: &[u8]) -> Vec<u64> {
+    let gear = gear_table();
+    let mut features = vec![0; NUM_FEATURES];
+    let mut rolling: u64 = 0;
+    for &b in data {
+        // each byte is shifted out of the hash after 64 more bytes
+        rolling = (rolling << 1).wrapping_add(gear[b as usize]);
+        for (i, feature) in features.iter_mut().enumerate() {
+            let value = rolling.wrapping_mul(FEATURE_MULTIPLIERS[i])
+                               .wrapping_add(FEATURE_OFFSETS[i]);
+            if value > *feature {
+                *feature = value;
+            }
+        }
+    }
+    features
+}
+
+const FEATURE_MULTIPLIERS: [u64; NUM_FEATURES] = [0x9e3779b97f4a7c15,
+                                                  0xbf58476d1ce4e5b9,
+                                                  0x94d049bb133111eb,
+                                                  0xd6e8feb86659fd93];
+const FEATURE_OFFSETS: [u64; NUM_FEATURES] = [0x2545f4914f6cdd1d,
+                                              0x4cf5ad432745937f,
+                                              0x7c4a7c7b2c8e7a3d,
+                                              0x1b873593cc9e2d51];
+
+// a fixed pseudo-random value for each byte, from splitmix64, so that the
+// features of a block are the same in every run
+fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0;
+    for entry in table.iter_mut() {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        *entry = z ^ (z >> 31);
+    }
+    table
+}
+
+// The ops that turn `base` into `data`
+pub fn encode(base: &[u8], data: &[u8]) -> Vec<u8> {
+    // where each MATCH_LEN-byte run at a multiple of MATCH_LEN starts in the base
+    let mut runs: HashMap<&[u8], usize> = HashMap::new();
+    let mut offset = 0;
+    while offset + MATCH_LEN <= base.len() {
+        runs.entry(&base[offset..offset + MATCH_LEN]).or_insert(offset);
+        offset += MATCH_LEN;
+    }
+
+    let mut delta = Vec::new();
+    let mut literal_start = 0;
+    let mut i = 0;
+    while i + MATCH_LEN <= data.len() {
+        let start = match runs.get(&data[i..i + MATCH_LEN]) {
+            Some(&start) => start,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        // the match may start before the run and go on past it
+        let mut copy_start = start;
+        let mut data_start = i;
+        while data_start > literal_start && copy_start > 0 &&
+              data[data_start - 1] == base[copy_start - 1] {
+            data_start -= 1;
+            copy_start -= 1;
+        }
+        let mut len = i + MATCH_LEN - data_start;
+        while data_start + len < data.len() && copy_start + len < base.len() &&
+              data[data_start + len] == base[copy_start + len] {
+            len += 1;
+        }
+
+        push_insert(&mut delta, &data[literal_start..data_start]);
+        delta.push(OP_COPY);
+        push_varint(&mut delta, copy_start as u64);
+        push_varint(&mut delta, len as u64);
+        i = data_start + len;
+        literal_start = i;
+    }
+    push_insert(&mut delta, &data[literal_start..]);
+    delta
+}
+
+fn push_insert(delta: &mut Vec<u8>, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    delta.push(OP_INSERT);
+    push_varint(delta, bytes.len() as u64);
+    delta.extend_from_slice(bytes);
+}
+
+// Rebuilds the block a delta was made from, given its base
+pub fn apply(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    let mut pos = 0;
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        match op {
+            OP_INSERT => {
+                let len = try!(read_varint(delta, &mut pos)) as usize;
+                if len > delta.len() - pos {
+                    return Err("Delta inserts more bytes than it holds".to_string());
+                }
+                data.extend_from_slice(&delta[pos..pos + len]);
+                pos += len;
+            }
+            OP_COPY => {
+                let start = try!(read_varint(delta, &mut pos)) as usize;
+                let len = try!(read_varint(delta, &mut pos)) as usize;
+                if start > base.len() || len > base.len() - start {
+                    return Err("Delta copies past the end of its base block".to_string());
+                }
+                data.extend_from_slice(&base[start..start + len]);
+            }
+            _ => return Err(format!("Unknown delta op {}", op)),
+        }
+    }
+    Ok(data)
+}
+
+pub fn push_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+pub fn read_varint(data: &[u8], pos: &mut usize) -> Result<u64, String> {
+    let mut n: u64 = 0;
+    let mut shift = 0;
+    loop {
+        if *pos >= data.len() || shift > 63 {
+            return Err("Delta ends in the middle of a number".to_string());
+        }
+        let b = data[*pos];
+        *pos += 1;
+        n |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(n);
+        }
+        shift += 7;
+    }
+}
+
+pub const DELTA_INDEX_FILE: &'static str = "deltas.index";
+
+// Which blocks can serve as bases, by their features, and which blocks are
+// stored as deltas against which base. It's kept in <blockstore>/deltas.index
+// as one line per change, replayed when it's loaded:
+//
+//     base <hash> <feature>...   a block stored in full that deltas may use
+//     delta <hash> <base hash>   a block stored as a delta
+//     full <hash>                a block that was a delta, rewritten in full
+//     gone <hash>                a block that was removed
+pub struct DeltaIndex {
+    path: PathBuf,
+    state: Mutex<Option<IndexState>>,
+}
+
+#[derive(Default)]
+struct IndexState {
+    bases: HashMap<Multihash, Vec<u64>>, // the features of each base
+    by_feature: HashMap<(usize, u64), Vec<Multihash>>, // feature i's value, to its bases
+    deltas: HashMap<Multihash, Multihash>, // each delta block, to its base
+}
+
+impl IndexState {
+    fn apply(&mut self, change: &str) {
+        let fields: Vec<&str> = change.split_whitespace().collect();
+        let hash = match fields.get(1).and_then(|hex| hex.from_hex().ok()) {
+            Some(bytes) => Multihash::from_vec(bytes),
+            None => return,
+        };
+        match fields[0] {
+            "base" => {
+                let features: Vec<u64> = fields[2..]
+                                             .iter()
+                                             .filter_map(|f| u64::from_str_radix(f, 16).ok())
+                                             .collect();
+                // a line cut short by an interrupted write is skipped
+                if features.len() == NUM_FEATURES {
+                    self.add_base(hash, features);
+                }
+            }
+            "delta" => {
+                if let Some(bytes) = fields.get(2).and_then(|hex| hex.from_hex().ok()) {
+                    self.deltas.insert(hash, Multihash::from_vec(bytes));
+                }
+            }
+            "full" => {
+                self.deltas.remove(&hash);
+            }
+            "gone" => {
+                self.deltas.remove(&hash);
+                if let Some(features) = self.bases.remove(&hash) {
+                    for (i, feature) in features.into_iter().enumerate() {
+                        if let Some(bases) = self.by_feature.get_mut(&(i, feature)) {
+                            bases.retain(|base| *base != hash);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn add_base(&mut self, hash: Multihash, features: Vec<u64>) {
+        for (i, &feature) in features.iter().enumerate() {
+            self.by_feature.entry((i, feature)).or_insert(Vec::new()).push(hash.clone());
+        }
+        self.bases.insert(hash, features);
+    }
+}
+
+impl DeltaIndex {
+    pub fn new(path: PathBuf) -> Self {
+        DeltaIndex {
+            path: path,
+            state: Mutex::new(None),
+        }
+    }
+
+    fn load(&self) -> Result<IndexState, String> {
+        let mut state = IndexState::default();
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(_) => return Ok(state),
+        };
+        for line in BufReader::new(file).lines() {
+            let line = try!(line.map_err(|e| {
+                format!("Error reading delta index {:?}: {}", self.path, e)
+            }));
+            state.apply(&line);
+        }
+        Ok(state)
+    }
+
+    // Applies a change to the index and records it in the file
+    fn change(&self, change: String) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.is_none() {
+            *state = Some(try!(self.load()));
+        }
+        let mut file = try!(OpenOptions::new()
+                                .append(true)
+                                .create(true)
+                                .open(&self.path)
+                                .map_err(|e| {
+                                    format!("Error opening delta index {:?}: {}", self.path, e)
+                                }));
+        try!(writeln!(file, "{}", change).map_err(|e| {
+            format!("Error writing delta index {:?}: {}", self.path, e)
+        }));
+        state.as_mut().unwrap().apply(&change);
+        Ok(())
+    }
+
+    fn with_state<T, F>(&self, f: F) -> Result<T, String>
+        where F: FnOnce(&IndexState) -> T
+    {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.is_none() {
+            *state = Some(try!(self.load()));
+        }
+        Ok(f(state.as_ref().unwrap()))
+    }
+
+    // The bases sharing at least one of `features`, those sharing the most first
+    pub fn similar(&self, features: &[u64]) -> Result<Vec<Multihash>, String> {
+        self.with_state(|state| {
+            let mut shared: HashMap<&Multihash, usize> = HashMap::new();
+            for (i, &feature) in features.iter().enumerate() {
+                for base in state.by_feature.get(&(i, feature)).into_iter().flat_map(|b| b) {
+                    *shared.entry(base).or_insert(0) += 1;
+                }
+            }
+            let mut similar: Vec<(&Multihash, usize)> = shared.into_iter().collect();
+            similar.sort_by(|a, b| b.1.cmp(&a.1));
+            similar.into_iter().map(|(base, _)| base.clone()).collect()
+        })
+    }
+
+    // The blocks stored as deltas against `base`
+    pub fn deltas_of(&self, base: &Multihash) -> Result<Vec<Multihash>, String> {
+        self.with_state(|state| {
+            state.deltas
+                 .iter()
+                 .filter(|&(_, b)| b == base)
+                 .map(|(delta, _)| delta.clone())
+                 .collect()
+        })
+    }
+
+    pub fn add_base(&self, hash: &Multihash, features: &[u64]) -> Result<(), String> {
+        let features: Vec<String> = features.iter().map(|f| format!("{:x}", f)).collect();
+        self.change(format!("base {} {}", hash.to_hex(), features.join(" ")))
+    }
+
+    pub fn add_delta(&self, hash: &Multihash, base: &Multihash) -> Result<(), String> {
+        self.change(format!("delta {} {}", hash.to_hex(), base.to_hex()))
+    }
+
+    pub fn rewritten_in_full(&self, hash: &Multihash) -> Result<(), String> {
+        self.change(format!("full {}", hash.to_hex()))
+    }
+
+    pub fn removed(&self, hash: &Multihash) -> Result<(), String> {
+        self.change(format!("gone {}", hash.to_hex()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, encode, features, NUM_FEATURES};
+
+    // the same pseudo-random bytes every run
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    }
+
+    fn edited(data: &[u8]) -> Vec<u8> {
+        let mut edited = data.to_vec();
+        edited[1000..1010].copy_from_slice(b"0123456789");
+        edited.insert(3000, b'!');
+        edited
+    }
+
+    #[test]
+    fn deltas_rebuild_the_block() {
+        let base = noise(4096, 1);
+        let data = edited(&base[..]);
+        let delta = encode(&base[..], &data[..]);
+        assert!(delta.len() < data.len() / 10);
+        assert_eq!(apply(&base[..], &delta[..]), Ok(data));
+    }
+
+    #[test]
+    fn deltas_against_unrelated_blocks_rebuild_the_block() {
+        let base = noise(4096, 1);
+        let data = noise(1000, 2);
+        let delta = encode(&base[..], &data[..]);
+        assert_eq!(apply(&base[..], &delta[..]), Ok(data));
+        assert_eq!(apply(&base[..], &[][..]), Ok(vec![]));
+    }
+
+    #[test]
+    fn deltas_that_copy_past_the_base_are_rejected() {
+        let base = noise(4096, 1);
+        let delta = encode(&base[..], &base[..]);
+        assert!(apply(&base[..100], &delta[..]).is_err());
+        assert!(apply(&base[..], &delta[..delta.len() - 1]).is_err());
+        assert!(apply(&base[..], &[7]).is_err());
+    }
+
+    #[test]
+    fn similar_blocks_share_features() {
+        let base = noise(4096, 1);
+        assert_eq!(features(&base[..]), features(&base[..]));
+        let shared = features(&base[..])
+                         .iter()
+                         .zip(features(&edited(&base[..])[..]).iter())
+                         .filter(|&(a, b)| a == b)
+                         .count();
+        assert!(shared >= NUM_FEATURES / 2);
+        let unrelated = features(&base[..])
+                            .iter()
+                            .zip(features(&noise(4096, 2)[..]).iter())
+                            .filter(|&(a, b)| a == b)
+                            .count();
+        assert_eq!(unrelated, 0);
+    }
+}
@@ -0,0 +1,242 @@
+// Reed-Solomon erasure coding over GF(2^8). Data is split into k data shards
+// and m parity shards are computed from them, such that the data can be
+// recovered from any k of the k + m shards. Parity rows come from a Cauchy
+// matrix, since every square submatrix of one is invertible.
+
+// the field's reducing polynomial, x^8 + x^4 + x^3 + x^2 + 1
+const FIELD_POLY: u16 = 0x11d;
+
+// k + m can't exceed the number of field elements
+pub const MAX_SHARDS: usize = 256;
+
+struct Field {
+    exp: [u8; 512],
+    log: [u8; 256],
+}
+
+impl Field {
+    fn new() -> Self {
+        let mut field = Field {
+            exp: [0; 512],
+            log: [0; 256],
+        };
+        let mut x: u16 = 1;
+        for i in 0..255 {
+            field.exp[i] = x as u8;
+            field.log[x as usize] = i as u8;
+            x <<= 1;
+            if x & 0x100 != 0 {
+                x ^= FIELD_POLY;
+            }
+        }
+        for i in 255..512 {
+            field.exp[i] = field.exp[i - 255];
+        }
+        field
+    }
+
+    fn mul(&self, a: u8, b: u8) -> u8 {
+        if a == 0 || b == 0 {
+            return 0;
+        }
+        self.exp[self.log[a as usize] as usize + self.log[b as usize] as usize]
+    }
+
+    fn inv(&self, a: u8) -> u8 {
+        self.exp[255 - self.log[a as usize] as usize]
+    }
+}
+
+// Row `i` of the coding matrix: the unit row for data shards, and a row of the
+// Cauchy matrix 1 / (x_i + y_j) with x_i = i and y_j = j for parity shards
+fn coding_row(field: &Field, i: usize, k: usize) -> Vec<u8> {
+    (0..k)
+        .map(|j| {
+            if i < k {
+                if i == j { 1 } else { 0 }
+            } else {
+                field.inv(i as u8 ^ j as u8)
+            }
+        })
+        .collect()
+}
+
+fn check_counts(k: usize, m: usize) -> Result<(), String> {
+    if k == 0 {
+        return Err("At least one data shard is needed".to_string());
+    }
+    if k + m > MAX_SHARDS {
+        return Err(format!("At most {} shards are supported, {} were asked for",
+                           MAX_SHARDS,
+                           k + m));
+    }
+    Ok(())
+}
+
+// Splits `data` into `k` data shards, zero-padding the last, followed by `m`
+// parity shards. All shards have the same length.
+pub fn encode(data: &[u8], k: usize, m: usize) -> Result<Vec<Vec<u8>>, String> {
+    try!(check_counts(k, m));
+    let field = Field::new();
+    let shard_len = ::std::cmp::max(1, (data.len() + k - 1) / k);
+
+    let mut shards = Vec::with_capacity(k + m);
+    for j in 0..k {
+        let start = ::std::cmp::min(j * shard_len, data.len());
+        let end = ::std::cmp::min(start + shard_len, data.len());
+        let mut shard = data[start..end].to_vec();
+        shard.resize(shard_len, 0);
+        shards.push(shard);
+    }
+
+    for i in k..k + m {
+        let row = coding_row(&field, i, k);
+        let mut parity = vec![0; shard_len];
+        for (j, &coef) in row.iter().enumerate() {
+            for (p, &byte) in shards[j].iter().enumerate() {
+                parity[p] ^= field.mul(coef, byte);
+            }
+        }
+        shards.push(parity);
+    }
+    Ok(shards)
+}
+
+// Inverts a square matrix with Gauss-Jordan elimination
+fn invert(field: &Field, mut matrix: Vec<Vec<u8>>) -> Result<Vec<Vec<u8>>, String> {
+    let n = matrix.len();
+    let mut inverse: Vec<Vec<u8>> = (0..n).map(|i| coding_row(field, i, n)).collect();
+
+    for col in 0..n {
+        let pivot = match (col..n).find(|&row| matrix[row][col] != 0) {
+            Some(row) => row,
+            None => return Err("Shard coding matrix is singular".to_string()),
+        };
+        matrix.swap(col, pivot);
+        inverse.swap(col, pivot);
+
+        let scale = field.inv(matrix[col][col]);
+        for x in 0..n {
+            matrix[col][x] = field.mul(matrix[col][x], scale);
+            inverse[col][x] = field.mul(inverse[col][x], scale);
+        }
+
+        for row in 0..n {
+            let factor = matrix[row][col];
+            if row == col || factor == 0 {
+                continue;
+            }
+            for x in 0..n {
+                let (a, b) = (matrix[col][x], inverse[col][x]);
+                matrix[row][x] ^= field.mul(factor, a);
+                inverse[row][x] ^= field.mul(factor, b);
+            }
+        }
+    }
+    Ok(inverse)
+}
+
+// Recovers the `k` data shards from `shards`, which holds all k + m shards in
+// order with None for the ones that are missing
+pub fn reconstruct(shards: &[Option<Vec<u8>>], k: usize) -> Result<Vec<Vec<u8>>, String> {
+    try!(check_counts(k, shards.len().saturating_sub(k)));
+    let present: Vec<usize> = (0..shards.len()).filter(|&i| shards[i].is_some()).take(k).collect();
+    if present.len() < k {
+        return Err(format!("Only {} shards are available, {} are needed",
+                           present.len(),
+                           k));
+    }
+
+    // all data shards present: nothing to solve for
+    if present.iter().enumerate().all(|(j, &i)| i == j) {
+        return Ok(present.iter().map(|&i| shards[i].clone().unwrap()).collect());
+    }
+
+    let field = Field::new();
+    let matrix = present.iter().map(|&i| coding_row(&field, i, k)).collect();
+    let inverse = try!(invert(&field, matrix));
+
+    let shard_len = shards[present[0]].as_ref().unwrap().len();
+    let mut data = Vec::with_capacity(k);
+    for j in 0..k {
+        let mut shard = vec![0; shard_len];
+        for (r, &i) in present.iter().enumerate() {
+            let coef = inverse[j][r];
+            for (p, &byte) in shards[i].as_ref().unwrap().iter().enumerate() {
+                shard[p] ^= field.mul(coef, byte);
+            }
+        }
+        data.push(shard);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, reconstruct, Field, MAX_SHARDS};
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+    }
+
+    // Drops the shards at `missing`, so reconstruct has to do without them
+    fn without(shards: &[Vec<u8>], missing: &[usize]) -> Vec<Option<Vec<u8>>> {
+        shards.iter()
+              .enumerate()
+              .map(|(i, shard)| if missing.contains(&i) { None } else { Some(shard.clone()) })
+              .collect()
+    }
+
+    #[test]
+    fn the_field_is_closed_under_inverses() {
+        let field = Field::new();
+        for a in 1..256 {
+            assert_eq!(field.mul(a as u8, field.inv(a as u8)), 1);
+        }
+        assert_eq!(field.mul(0, 57), 0);
+        // x * x^7 = x^8, reduced by the field polynomial
+        assert_eq!(field.mul(2, 0x80), 0x1d);
+    }
+
+    #[test]
+    fn data_shards_hold_the_data_padded() {
+        let shards = encode(&data(10), 4, 2).unwrap();
+        assert_eq!(shards.len(), 6);
+        assert!(shards.iter().all(|shard| shard.len() == 3));
+        let mut joined = shards[..4].concat();
+        assert_eq!(joined.split_off(10), vec![0, 0]);
+        assert_eq!(joined, data(10));
+    }
+
+    #[test]
+    fn any_k_shards_recover_the_data() {
+        let (k, m) = (4, 3);
+        let shards = encode(&data(1000), k, m).unwrap();
+        let original = shards[..k].to_vec();
+
+        // every way of losing m shards or fewer
+        for lost in 0..1 << (k + m) {
+            let missing: Vec<usize> = (0..k + m).filter(|i| lost & (1 << i) != 0).collect();
+            if missing.len() > m {
+                continue;
+            }
+            let recovered = reconstruct(&without(&shards, &missing), k).unwrap();
+            assert_eq!(recovered, original, "losing shards {:?}", missing);
+        }
+    }
+
+    #[test]
+    fn more_than_m_missing_shards_are_an_error() {
+        let shards = encode(&data(100), 3, 2).unwrap();
+        assert_eq!(reconstruct(&without(&shards, &[0, 3, 4]), 3),
+                   Err("Only 2 shards are available, 3 are needed".to_string()));
+    }
+
+    #[test]
+    fn shard_counts_are_checked() {
+        assert!(encode(&data(10), 0, 2).is_err());
+        assert!(encode(&data(10), MAX_SHARDS - 1, 2).is_err());
+        assert!(encode(&data(10), MAX_SHARDS - 2, 2).is_ok());
+        assert_eq!(encode(&[], 2, 1).unwrap(), vec![vec![0], vec![0], vec![0]]);
+    }
+}
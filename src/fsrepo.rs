@@ -3,52 +3,98 @@ use util;
 
 use libc;
 
-use atomicwrites::{AtomicFile, DisallowOverwrite};
+use atomicwrites::{AtomicFile, AllowOverwrite, DisallowOverwrite, OverwriteBehavior};
+use rustc_serialize::base64::{self, FromBase64, ToBase64};
 use std::env;
 use std::fs::{self, File};
-use std::io::{self, Write};
-use std::os::unix::io::AsRawFd;
-use std::path::{Path, PathBuf};
+use std::io::{self, Read, Write};
+use std::path::{self, Path, PathBuf};
 
+// The layout of a repo:
+//
+//     config       the JSON config, see the config module
+//     version      the version of this layout, as a decimal number
+//     repo.lock    locked by a process while it sets up the repo, and by the
+//                  daemon while it runs
+//     api          the address of the running daemon's HTTP API
+//     datastore/
+//     blocks/      one file per block, see the blockstore module
+//     logs/        the audit and command logs
 const LOCK_FILE: &'static str = "repo.lock";
+const VERSION_FILE: &'static str = "version";
+const API_FILE: &'static str = "api";
+const PENDING_SALT_FILE: &'static str = "encryption.salt";
 const DATASTORE_DIR: &'static str = "datastore";
 const BLOCKSTORE_DIR: &'static str = "blocks";
-const LOGS_DIR: &'static str = "logs";
+pub const LOGS_DIR: &'static str = "logs";
 
-// TODO: make this work across multiple threads?
-pub fn is_locked(mut repo_path: PathBuf) -> Result<bool, String> {
-    repo_path.push(LOCK_FILE);
+// The version of the layout this ipfs writes. Repos initialized before the
+// version file existed have the same layout, so they count as this version.
+pub const REPO_VERSION: u32 = 1;
 
-    match util::file_exists(&repo_path) {
-        Ok(false) => return Ok(false),
-        _ => {}
-    }
+// The repo lock, held until this is dropped
+pub struct RepoLock {
+    _file: File,
+}
 
-    match lock(&repo_path) {
-        Err(e) => {
-            match e.kind() {
-                // TODO: is WouldBlock correct here?
-                io::ErrorKind::WouldBlock => Ok(true),
-                _ => Err(format!("{}", e)),
-            }
+// Takes the repo lock, failing if another process holds it
+pub fn lock_repo(repo_path: &Path) -> Result<RepoLock, String> {
+    let path = repo_path.join(LOCK_FILE);
+    match lock(&path) {
+        Ok(file) => Ok(RepoLock { _file: file }),
+        Err(ref e) if is_lock_held_error(e) => {
+            Err("Another process has locked the repo. Unable to continue.".to_string())
         }
-        Ok(_) => Ok(false),
+        Err(e) => Err(format!("Error locking repo {:?}: {}", path, e)),
     }
 }
 
 // TODO: make this work across multiple threads?
+#[cfg(unix)]
 pub fn lock(repo_path: &PathBuf) -> Result<File, io::Error> {
     let file = try!(File::create(repo_path));
     try!(fcntl(&file, 6)); // no F_SETLK in libc. :(
     Ok(file)
 }
 
+// Windows has no fcntl locks, but a file opened with no sharing allowed can't
+// be opened again (by any process) until it is closed.
+#[cfg(windows)]
+pub fn lock(repo_path: &PathBuf) -> Result<File, io::Error> {
+    use std::fs::OpenOptions;
+    use std::os::windows::fs::OpenOptionsExt;
+
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .share_mode(0)
+        .open(repo_path)
+}
+
+#[cfg(unix)]
+fn is_lock_held_error(e: &io::Error) -> bool {
+    // TODO: is WouldBlock correct here?
+    e.kind() == io::ErrorKind::WouldBlock
+}
+
+#[cfg(windows)]
+fn is_lock_held_error(e: &io::Error) -> bool {
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    e.raw_os_error() == Some(ERROR_SHARING_VIOLATION)
+}
+
+#[cfg(unix)]
 fn fcntl(file: &File, cmd: libc::c_int) -> Result<(), io::Error> {
-    // this only works on 64-bit linux probably
+    use std::os::unix::io::AsRawFd;
+
+    // this only works on 64-bit linux probably. The fields have to be laid out
+    // as they are in C, or the kernel reads the lock type from the wrong bytes
     #[derive(Debug)]
+    #[repr(C)]
     struct flock64 {
-        ty: libc::c_int,
-        whence: libc::c_int,
+        ty: libc::c_short,
+        whence: libc::c_short,
         start: libc::off_t,
         len: libc::off_t,
         pid: libc::pid_t,
@@ -57,14 +103,12 @@ fn fcntl(file: &File, cmd: libc::c_int) -> Result<(), io::Error> {
     let flock = flock64 {
         ty: 0x1, // there's no F_RDLCK / F_WRLCK / F_UNLCK in libc :(
                  // this is F_WRLCK, btw, I think
-        whence: libc::SEEK_SET,
+        whence: libc::SEEK_SET as libc::c_short,
         start: 0,
         len: 0,
         pid: unsafe { libc::getpid() },
     };
 
-    println!("{:?}", flock);
-
     let ret = unsafe { libc::fcntl(file.as_raw_fd(), cmd, &flock) };
     if ret < 0 {
         Err(io::Error::last_os_error())
@@ -73,14 +117,19 @@ fn fcntl(file: &File, cmd: libc::c_int) -> Result<(), io::Error> {
     }
 }
 
-// Use the environment variable to determine repo path if it exists,
-// else use the default path.
-pub fn best_known_path() -> Result<PathBuf, String> {
+// Use the path given with --repo-dir if there is one, else the environment
+// variable if it exists, else the default path.
+pub fn best_known_path(repo_dir: Option<&str>) -> Result<PathBuf, String> {
     // TODO: this should probably use var_os, but having to handle OsString
     // complicates things
-    let unexpanded_path = match env::var(config::ENV_NAME_REPO_DIR) {
-        Err(_) => config::DEFAULT_REPO_ROOT.to_string() + config::DEFAULT_REPO_PATH,
-        Ok(s) => s,
+    let unexpanded_path = match repo_dir {
+        Some(dir) => dir.to_string(),
+        None => {
+            match env::var(config::ENV_NAME_REPO_DIR) {
+                Err(_) => config::DEFAULT_REPO_ROOT.to_string() + config::DEFAULT_REPO_PATH,
+                Ok(s) => s,
+            }
+        }
     };
 
     expand_tilde(unexpanded_path)
@@ -92,7 +141,10 @@ fn expand_tilde(s: String) -> Result<PathBuf, ()> {
         match env::home_dir() {
             None => Err(()),
             Some(mut dir) => {
-                dir.push(&s[2..]);
+                // accept "~/" and, on Windows, "~\", but on unix a backslash
+                // is part of the file name
+                let rest = s[1..].trim_left_matches(path::is_separator);
+                dir.push(rest);
                 Ok(PathBuf::from(dir))
             }
         }
@@ -119,7 +171,7 @@ pub fn is_initialized(mut repo_path: PathBuf) -> Result<bool, String> {
     // TODO: why does the analogous function in go-ipfs only check the
     // datastore directory? what about blocks and log directories
     repo_path.push(DATASTORE_DIR);
-    let ds_exists = try!(util::file_exists(&config_path).map_err(|e| {
+    let ds_exists = try!(util::file_exists(&repo_path).map_err(|e| {
         format!("Error checking existence of datastore directory {:?}: {}",
                 repo_path,
                 e)
@@ -131,18 +183,60 @@ pub fn is_initialized(mut repo_path: PathBuf) -> Result<bool, String> {
     Ok(true)
 }
 
-pub fn remove<P: AsRef<Path>>(repo_path: P) -> Result<(), String> {
-    fs::remove_dir_all(repo_path).map_err(|e| format!("Error removing repo: {}", e))
+// The closest directory above `repo_path` that is itself an initialized repo,
+// if there is one
+pub fn enclosing_repo(repo_path: &Path) -> Result<Option<PathBuf>, String> {
+    let absolute = if repo_path.is_absolute() {
+        repo_path.to_path_buf()
+    } else {
+        let cwd = try!(env::current_dir()
+                           .map_err(|e| format!("Error getting current directory: {}", e)));
+        cwd.join(repo_path)
+    };
+
+    let mut dir = absolute.parent();
+    while let Some(d) = dir {
+        if try!(is_initialized(d.to_path_buf())) {
+            return Ok(Some(d.to_path_buf()));
+        }
+        dir = d.parent();
+    }
+    Ok(None)
 }
 
-pub fn init(mut repo_path: PathBuf, cfg: &config::Config) -> Result<(), String> {
-    // Don't initialize if already initialized.
-    if try!(is_initialized(repo_path.clone())) {
-        return Ok(());
+// Removes everything in the repo but the lock file, which the caller is
+// expected to hold
+pub fn remove<P: AsRef<Path>>(repo_path: P) -> Result<(), String> {
+    let entries = try!(fs::read_dir(&repo_path)
+                           .map_err(|e| format!("Error removing repo: {}", e)));
+    for entry in entries {
+        let entry = try!(entry.map_err(|e| format!("Error removing repo: {}", e)));
+        if entry.file_name() == LOCK_FILE {
+            continue;
+        }
+        let path = entry.path();
+        let result = if path.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+        try!(result.map_err(|e| format!("Error removing {:?}: {}", path, e)));
     }
+    Ok(())
+}
 
+// Creates whatever part of the layout is missing. An existing config is
+// never overwritten, so this can be rerun on a repo whose setup was cut short.
+pub fn init(repo_path: PathBuf, cfg: &config::Config) -> Result<(), String> {
     let config_path = config::repo_path_to_config_file(repo_path.clone());
-    try!(write_config_file(config_path, cfg));
+    let config_exists = try!(util::file_exists(&config_path).map_err(|e| {
+        format!("Error checking existence of config file {:?}: {}",
+                config_path,
+                e)
+    }));
+    if !config_exists {
+        try!(write_config_file(config_path, cfg));
+    }
 
     let mut datastore_path = repo_path.clone();
     datastore_path.push(DATASTORE_DIR);
@@ -160,19 +254,133 @@ pub fn init(mut repo_path: PathBuf, cfg: &config::Config) -> Result<(), String>
     try!(util::ensure_dir_writable(logs_path)
              .map_err(|e| format!("Error checking writability of logs dir: {}", e)));
 
+    let version_path = repo_path.join(VERSION_FILE);
+    let version_exists = try!(util::file_exists(&version_path).map_err(|e| {
+        format!("Error checking existence of version file {:?}: {}",
+                version_path,
+                e)
+    }));
+    if !version_exists {
+        let file = AtomicFile::new(&version_path, DisallowOverwrite);
+        try!(file.write(|f| write!(f, "{}\n", REPO_VERSION))
+                 .map_err(|e| format!("Error writing version file: {}", e)));
+    }
+
+    Ok(())
+}
+
+// The version of the repo's layout
+pub fn read_version(repo_path: PathBuf) -> Result<u32, String> {
+    let path = repo_path.join(VERSION_FILE);
+    if !try!(util::file_exists(&path).map_err(|e| format!("{}", e))) {
+        return Ok(REPO_VERSION);
+    }
+
+    let mut contents = String::new();
+    try!(File::open(&path)
+             .and_then(|mut f| f.read_to_string(&mut contents))
+             .map_err(|e| format!("Error reading version file {:?}: {}", path, e)));
+    contents.trim()
+            .parse()
+            .map_err(|_| format!("Invalid repo version {:?} in {:?}", contents.trim(), path))
+}
+
+// Fails if the repo has a layout from a newer ipfs, which this one can't know
+// how to read
+pub fn check_version(repo_path: PathBuf) -> Result<(), String> {
+    let version = try!(read_version(repo_path.clone()));
+    if version > REPO_VERSION {
+        return Err(format!("The repo at {:?} has version {}, but this ipfs only supports up \
+                            to version {}",
+                           repo_path,
+                           version,
+                           REPO_VERSION));
+    }
     Ok(())
 }
 
+// Records the address the daemon serves the API on, for clients to find it
+pub fn write_api_file(repo_path: &Path, addr: &str) -> Result<(), String> {
+    let file = AtomicFile::new(repo_path.join(API_FILE), AllowOverwrite);
+    file.write(|f| write!(f, "{}\n", addr))
+        .map_err(|e| format!("Error writing api file: {}", e))
+}
+
+// The address in the api file, or None if no daemon has written one. The
+// file is left behind by a daemon that was killed, so the address may be
+// stale.
+pub fn read_api_file(repo_path: &Path) -> Result<Option<String>, String> {
+    let path = repo_path.join(API_FILE);
+    if !try!(util::file_exists(&path).map_err(|e| format!("{}", e))) {
+        return Ok(None);
+    }
+
+    let mut contents = String::new();
+    try!(File::open(&path)
+             .and_then(|mut f| f.read_to_string(&mut contents))
+             .map_err(|e| format!("Error reading api file {:?}: {}", path, e)));
+    Ok(Some(contents.trim().to_string()))
+}
+
+pub fn remove_api_file(repo_path: &Path) -> Result<(), String> {
+    let path = repo_path.join(API_FILE);
+    fs::remove_file(&path).map_err(|e| format!("Error removing api file {:?}: {}", path, e))
+}
+
+// The salt of the key blocks are being re-encrypted under, if a re-encryption
+// is under way. It's kept until the config records the key, so that an
+// interrupted re-encryption is finished with the same key.
+pub fn read_pending_salt(repo_path: &Path) -> Result<Option<Vec<u8>>, String> {
+    let path = repo_path.join(PENDING_SALT_FILE);
+    if !try!(util::file_exists(&path).map_err(|e| format!("{}", e))) {
+        return Ok(None);
+    }
+
+    let mut contents = String::new();
+    try!(File::open(&path)
+             .and_then(|mut f| f.read_to_string(&mut contents))
+             .map_err(|e| format!("Error reading salt file {:?}: {}", path, e)));
+    contents.trim()
+            .from_base64()
+            .map(Some)
+            .map_err(|e| format!("Error decoding salt file {:?}: {}", path, e))
+}
+
+pub fn write_pending_salt(repo_path: &Path, salt: &[u8]) -> Result<(), String> {
+    let file = AtomicFile::new(repo_path.join(PENDING_SALT_FILE), AllowOverwrite);
+    file.write(|f| write!(f, "{}\n", salt.to_base64(base64::STANDARD)))
+        .map_err(|e| format!("Error writing salt file: {}", e))
+}
+
+pub fn remove_pending_salt(repo_path: &Path) -> Result<(), String> {
+    let path = repo_path.join(PENDING_SALT_FILE);
+    fs::remove_file(&path).map_err(|e| format!("Error removing salt file {:?}: {}", path, e))
+}
+
 // Caller should ensure the directory exists before calling
 fn write_config_file<P: AsRef<Path>>(file_path: P,
                                      cfg: &config::Config)
                                      -> Result<(), String> {
+    write_config(file_path, cfg, DisallowOverwrite)
+}
+
+// Replaces the config file of an already initialized repo
+pub fn update_config_file<P: AsRef<Path>>(file_path: P,
+                                          cfg: &config::Config)
+                                          -> Result<(), String> {
+    write_config(file_path, cfg, AllowOverwrite)
+}
+
+fn write_config<P: AsRef<Path>>(file_path: P,
+                                cfg: &config::Config,
+                                overwrite: OverwriteBehavior)
+                                -> Result<(), String> {
     let s = match cfg.to_json_string() {
         Err(e) => return Err(format!("Error encoding config as Json: {}", e)),
         Ok(s) => s,
     };
 
-    let file = AtomicFile::new(file_path, DisallowOverwrite);
+    let file = AtomicFile::new(file_path, overwrite);
     file.write(|f| f.write_all(s.as_bytes()))
         .map_err(|e| format!("Error writing config file: {}", e))
 }
@@ -183,3 +391,74 @@ pub fn read_config_file<P: AsRef<Path>>(file_path: P) -> Result<config::Config,
     }));
     config::Config::from_reader(&mut config_file)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{expand_tilde, is_lock_held_error, lock};
+
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn lock_path() -> PathBuf {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+        env::temp_dir().join(format!("fsrepo-lock-test-{}", nanos))
+    }
+
+    #[test]
+    fn paths_without_a_tilde_are_kept() {
+        assert_eq!(expand_tilde("/srv/ipfs".to_string()), Ok(PathBuf::from("/srv/ipfs")));
+        assert_eq!(expand_tilde("ipfs/~".to_string()), Ok(PathBuf::from("ipfs/~")));
+    }
+
+    #[test]
+    fn tildes_expand_to_the_home_dir() {
+        let home = env::home_dir().unwrap();
+        assert_eq!(expand_tilde("~".to_string()), Ok(home.clone()));
+        assert_eq!(expand_tilde("~/.ipfs".to_string()), Ok(home.join(".ipfs")));
+        assert_eq!(expand_tilde("~//.ipfs".to_string()), Ok(home.join(".ipfs")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn backslashes_after_a_tilde_are_kept_on_unix() {
+        let home = env::home_dir().unwrap();
+        assert_eq!(expand_tilde("~\\.ipfs".to_string()), Ok(home.join("\\.ipfs")));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn backslashes_after_a_tilde_are_separators_on_windows() {
+        let home = env::home_dir().unwrap();
+        assert_eq!(expand_tilde("~\\.ipfs".to_string()), Ok(home.join(".ipfs")));
+    }
+
+    // fcntl locks are per process, so one held by this process doesn't stop
+    // it from taking the lock again; only the error it would get is checked
+    #[cfg(unix)]
+    #[test]
+    fn locking_creates_the_lock_file() {
+        let path = lock_path();
+        let file = lock(&path).unwrap();
+        assert!(path.is_file());
+        drop(file);
+        fs::remove_file(&path).unwrap();
+
+        assert!(is_lock_held_error(&io::Error::new(io::ErrorKind::WouldBlock, "held")));
+        assert!(!is_lock_held_error(&io::Error::new(io::ErrorKind::NotFound, "gone")));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn a_held_lock_cant_be_taken_again() {
+        let path = lock_path();
+        let file = lock(&path).unwrap();
+        let err = lock(&path).unwrap_err();
+        assert!(is_lock_held_error(&err));
+        drop(file);
+        drop(lock(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+}
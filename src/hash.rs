@@ -0,0 +1,145 @@
+// The hash functions objects can be addressed with. Each entry carries its
+// multihash code, so hashing with it produces a complete multihash, and a
+// multihash using any other code is rejected with a clear error rather than
+// treated as a block we might have. blake3 is only supported in builds with
+// the `blake3` feature, e.g. `cargo build --features blake3`.
+
+use rust_crypto::blake2b::Blake2b;
+use rust_crypto::digest::Digest;
+use rust_crypto::sha2::{Sha256, Sha512};
+use rust_multihash::Multihash;
+
+pub struct HashFn {
+    pub name: &'static str,
+    pub code: u8,
+    new_digest: fn() -> Box<Digest>,
+}
+
+fn sha2_256() -> Box<Digest> {
+    Box::new(Sha256::new())
+}
+
+fn sha2_512() -> Box<Digest> {
+    Box::new(Sha512::new())
+}
+
+fn blake2b_512() -> Box<Digest> {
+    Box::new(Blake2b::new(64))
+}
+
+// blake3 has its own hasher, so it's put behind rust-crypto's Digest here
+#[cfg(feature = "blake3")]
+struct Blake3(::blake3::Hasher);
+
+#[cfg(feature = "blake3")]
+impl Digest for Blake3 {
+    fn input(&mut self, input: &[u8]) {
+        self.0.update(input);
+    }
+
+    fn result(&mut self, out: &mut [u8]) {
+        out.copy_from_slice(self.0.finalize().as_bytes());
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+
+    fn output_bits(&self) -> usize {
+        256
+    }
+
+    fn block_size(&self) -> usize {
+        64
+    }
+}
+
+#[cfg(feature = "blake3")]
+fn blake3_256() -> Box<Digest> {
+    Box::new(Blake3(::blake3::Hasher::new()))
+}
+
+pub const SHA2_256: HashFn = HashFn {
+    name: "sha2-256",
+    code: 0x12,
+    new_digest: sha2_256,
+};
+
+const REGISTRY: &'static [HashFn] = &[SHA2_256,
+                                      HashFn {
+                                          name: "sha2-512",
+                                          code: 0x13,
+                                          new_digest: sha2_512,
+                                      },
+                                      HashFn {
+                                          name: "blake2b",
+                                          code: 0x40,
+                                          new_digest: blake2b_512,
+                                      },
+                                      #[cfg(feature = "blake3")]
+                                      HashFn {
+                                          name: "blake3",
+                                          code: 0x1e,
+                                          new_digest: blake3_256,
+                                      }];
+
+pub const DEFAULT_HASH: &'static str = "sha2-256";
+
+impl HashFn {
+    pub fn multihash(&self, data: &[u8]) -> Multihash {
+        let mut digest = (self.new_digest)();
+        digest.input(data);
+
+        let len = digest.output_bytes();
+        let mut bytes = vec![0; 2 + len];
+        bytes[0] = self.code;
+        bytes[1] = len as u8;
+        digest.result(&mut bytes[2..]);
+        Multihash::from_vec(bytes)
+    }
+}
+
+pub fn by_name(name: &str) -> Result<&'static HashFn, String> {
+    REGISTRY.iter().find(|f| f.name == name).ok_or_else(|| {
+        format!("Unknown hash function '{}'. Supported hash functions are: {}",
+                name,
+                REGISTRY.iter().map(|f| f.name).collect::<Vec<_>>().join(", "))
+    })
+}
+
+pub fn by_code(code: u8) -> Result<&'static HashFn, String> {
+    REGISTRY.iter()
+            .find(|f| f.code == code)
+            .ok_or_else(|| format!("Unsupported hash 0x{:02x}", code))
+}
+
+// Parses a base58-encoded multihash given on the command line, refusing ones
+// made with a hash function we don't support
+pub fn parse_multihash(s: &str) -> Result<Multihash, String> {
+    let mh = try!(Multihash::from_base58_str(s));
+    if mh.is_empty() {
+        return Err(format!("Invalid multihash: {}", s));
+    }
+    try!(by_code(mh[0]).map_err(|e| format!("{} in {}", e, s)));
+    Ok(mh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{by_code, by_name};
+
+    #[cfg(not(feature = "blake3"))]
+    #[test]
+    fn blake3_is_unsupported_without_its_feature() {
+        assert_eq!(by_code(0x1e).err().unwrap(), "Unsupported hash 0x1e");
+        assert!(by_name("blake3").is_err());
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn blake3_hashes_are_multihashes_with_its_code() {
+        let hash = by_name("blake3").unwrap().multihash(b"data");
+        assert_eq!((hash[0], hash[1], hash.len()), (0x1e, 32, 34));
+        assert_eq!(by_code(0x1e).unwrap().name, "blake3");
+    }
+}
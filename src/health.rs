@@ -0,0 +1,56 @@
+// The health of the services a daemon runs, so that whatever watches the
+// daemon can ask `ipfs diag health`, or /api/v0/diag/health, whether it's
+// ready, rather than parse its logs. Each service sets its state as it changes.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum State {
+    Starting,
+    Ready,
+    Degraded, // running, but without some of what it does
+    Failed,
+}
+
+impl State {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            State::Starting => "starting",
+            State::Ready => "ready",
+            State::Degraded => "degraded",
+            State::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Service {
+    pub state: State,
+    pub message: String, // why the service is in its state, if it isn't ready
+}
+
+// The daemon's services by name, shared by everything that reports on them
+#[derive(Clone)]
+pub struct Health {
+    services: Arc<Mutex<BTreeMap<String, Service>>>,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Health { services: Arc::new(Mutex::new(BTreeMap::new())) }
+    }
+
+    pub fn set(&self, service: &str, state: State, message: &str) {
+        self.services.lock().unwrap().insert(service.to_string(),
+                                             Service {
+                                                 state: state,
+                                                 message: message.to_string(),
+                                             });
+    }
+
+    // The services and their states, by name
+    pub fn services(&self) -> BTreeMap<String, Service> {
+        self.services.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,267 @@
+// Ignore rules for adding directories, written like .gitignore lines:
+//
+//     target         any file or directory named `target`
+//     *.o            a glob, matched against the name at any depth
+//     build/         directories only
+//     /docs/out      a path from the directory the rules are for
+//     docs/**/*.html `**` matches any number of directories
+//     !keep.o        a negation: includes what an earlier rule left out
+//     # a comment
+//
+// As in git, the last rule to match a path decides whether it's ignored, and
+// nothing under an ignored directory can be included again, since the
+// directory isn't read.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Clone)]
+struct Rule {
+    pattern: Vec<char>,
+    negated: bool,
+    dir_only: bool,
+    // matched against the whole path from `base` rather than the name
+    anchored: bool,
+    // the path, from the directory being added, of the directory the rule
+    // came from, empty for rules that hold throughout
+    base: String,
+}
+
+#[derive(Clone)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    pub fn new() -> Self {
+        IgnoreRules { rules: Vec::new() }
+    }
+
+    // Adds the rule on `line`, which holds for paths under `base`. Blank
+    // lines and comments are skipped.
+    pub fn add(&mut self, line: &str, base: &str) {
+        let mut line = line.trim_right_matches(|c| c == '\r' || c == '\n');
+        // trailing spaces are dropped unless escaped
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line = &line[..line.len() - 1];
+        }
+        if line.is_empty() || line.starts_with('#') {
+            return;
+        }
+
+        let negated = line.starts_with('!');
+        if negated {
+            line = &line[1..];
+        } else if line.starts_with("\\!") || line.starts_with("\\#") {
+            line = &line[1..];
+        }
+        let dir_only = line.ends_with('/');
+        let line = line.trim_right_matches('/');
+        if line.is_empty() {
+            return;
+        }
+        // a slash anywhere but the end ties the pattern to `base`
+        let anchored = line.contains('/');
+        let line = line.trim_left_matches('/');
+
+        self.rules.push(Rule {
+            pattern: line.chars().collect(),
+            negated: negated,
+            dir_only: dir_only,
+            anchored: anchored,
+            base: base.to_string(),
+        });
+    }
+
+    // Adds the rules read from the file at `path`, holding for paths under `base`
+    pub fn add_file(&mut self, path: &Path, base: &str) -> Result<(), String> {
+        let mut text = String::new();
+        try!(File::open(path)
+                 .and_then(|mut file| file.read_to_string(&mut text))
+                 .map_err(|e| format!("Error reading ignore rules from {:?}: {}", path, e)));
+        for line in text.lines() {
+            self.add(line, base);
+        }
+        Ok(())
+    }
+
+    // Whether the rules leave out the file or directory at `path`, given
+    // with / between its components from the directory being added
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        let name: Vec<char> = path.rsplit('/').next().unwrap_or(path).chars().collect();
+        let mut ignored = false;
+        for rule in self.rules.iter() {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let from_base = if rule.base.is_empty() {
+                path
+            } else if path.starts_with(&rule.base[..]) &&
+                      path[rule.base.len()..].starts_with('/') {
+                &path[rule.base.len() + 1..]
+            } else {
+                continue;
+            };
+            let matched = if rule.anchored {
+                let from_base: Vec<char> = from_base.chars().collect();
+                glob_matches(&rule.pattern[..], &from_base[..])
+            } else {
+                glob_matches(&rule.pattern[..], &name[..])
+            };
+            if matched {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+}
+
+// Whether `text` matches the glob `pattern`, in which `*` and `?` don't match
+// a slash but `**/` matches any number of whole directories
+fn glob_matches(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(&'*') if pattern.get(1) == Some(&'*') => {
+            let rest = &pattern[2..];
+            if rest.is_empty() {
+                return true;
+            }
+            if rest[0] != '/' {
+                // as in git, other runs of asterisks act as one
+                return glob_matches(&pattern[1..], text);
+            }
+            let rest = &rest[1..];
+            glob_matches(rest, text) ||
+            (0..text.len()).any(|i| text[i] == '/' && glob_matches(rest, &text[i + 1..]))
+        }
+        Some(&'*') => {
+            let mut i = 0;
+            loop {
+                if glob_matches(&pattern[1..], &text[i..]) {
+                    return true;
+                }
+                if i == text.len() || text[i] == '/' {
+                    return false;
+                }
+                i += 1;
+            }
+        }
+        Some(&'?') => {
+            !text.is_empty() && text[0] != '/' && glob_matches(&pattern[1..], &text[1..])
+        }
+        Some(&'[') => {
+            match class_matches(&pattern[1..], text.first()) {
+                Some((true, len)) => glob_matches(&pattern[len + 1..], &text[1..]),
+                Some((false, _)) => false,
+                // without a closing ] it's a literal [
+                None => text.first() == Some(&'[') && glob_matches(&pattern[1..], &text[1..]),
+            }
+        }
+        Some(&'\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && glob_matches(&pattern[2..], &text[1..])
+        }
+        Some(c) => text.first() == Some(c) && glob_matches(&pattern[1..], &text[1..]),
+    }
+}
+
+// Whether `c` is in the class, like `a-z]` or `!.]`, at the start of
+// `pattern`, and how much of the pattern the class takes up, or None if the
+// class isn't closed
+fn class_matches(pattern: &[char], c: Option<&char>) -> Option<(bool, usize)> {
+    let negated = pattern.first() == Some(&'!') || pattern.first() == Some(&'^');
+    let mut i = if negated { 1 } else { 0 };
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match pattern.get(i) {
+            None => return None,
+            // a ] right at the start is part of the class
+            Some(&']') if !first => break,
+            Some(&start) => {
+                let end = if pattern.get(i + 1) == Some(&'-') &&
+                             pattern.get(i + 2).map_or(false, |&end| end != ']') {
+                    i += 2;
+                    pattern[i]
+                } else {
+                    start
+                };
+                if let Some(&c) = c {
+                    matched = matched || (c >= start && c <= end);
+                }
+                i += 1;
+            }
+        }
+        first = false;
+    }
+    Some((c.map_or(false, |&c| c != '/') && matched != negated, i + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IgnoreRules;
+
+    fn rules(lines: &[&str]) -> IgnoreRules {
+        let mut rules = IgnoreRules::new();
+        for line in lines {
+            rules.add(line, "");
+        }
+        rules
+    }
+
+    #[test]
+    fn names_match_at_any_depth() {
+        let rules = rules(&["target", "*.o"]);
+        assert!(rules.is_ignored("target", true));
+        assert!(rules.is_ignored("src/target", false));
+        assert!(rules.is_ignored("src/lib/main.o", false));
+        assert!(!rules.is_ignored("src/main.rs", false));
+        assert!(!rules.is_ignored("targets", true));
+    }
+
+    #[test]
+    fn slashes_anchor_patterns_and_mark_directories() {
+        let rules = rules(&["/out", "docs/*.html", "build/"]);
+        assert!(rules.is_ignored("out", true));
+        assert!(!rules.is_ignored("src/out", true));
+        assert!(rules.is_ignored("docs/index.html", false));
+        assert!(!rules.is_ignored("docs/api/index.html", false));
+        assert!(rules.is_ignored("src/build", true));
+        assert!(!rules.is_ignored("src/build", false));
+    }
+
+    #[test]
+    fn double_asterisks_match_any_number_of_directories() {
+        let rules = rules(&["docs/**/*.html", "**/cache", "logs/**"]);
+        assert!(rules.is_ignored("docs/index.html", false));
+        assert!(rules.is_ignored("docs/a/b/index.html", false));
+        assert!(rules.is_ignored("cache", true));
+        assert!(rules.is_ignored("a/b/cache", true));
+        assert!(rules.is_ignored("logs/a/b", false));
+        assert!(!rules.is_ignored("logs", true));
+    }
+
+    #[test]
+    fn the_last_matching_rule_decides() {
+        let rules = rules(&["# objects", "", "*.o", "!keep.o", "\\!bang", "[a-c]?.tmp"]);
+        assert!(rules.is_ignored("main.o", false));
+        assert!(!rules.is_ignored("keep.o", false));
+        assert!(rules.is_ignored("!bang", false));
+        assert!(rules.is_ignored("b1.tmp", false));
+        assert!(!rules.is_ignored("d1.tmp", false));
+        assert!(!rules.is_ignored("# objects", false));
+    }
+
+    #[test]
+    fn rules_hold_under_their_base() {
+        let mut rules = IgnoreRules::new();
+        rules.add("*.log", "src");
+        rules.add("/gen", "src");
+        assert!(rules.is_ignored("src/a.log", false));
+        assert!(rules.is_ignored("src/deep/a.log", false));
+        assert!(!rules.is_ignored("a.log", false));
+        assert!(!rules.is_ignored("srcs/a.log", false));
+        assert!(rules.is_ignored("src/gen", true));
+        assert!(!rules.is_ignored("src/deep/gen", true));
+    }
+}
@@ -1,3 +1,431 @@
+// Turns content into DAGs of unixfs nodes. A file is split into fixed-size
+// chunks, each stored as a leaf node, under as many layers of nodes linking
+// to their children as it takes to end up with one root. A file that fits in
+// one chunk is stored as a single node.
+
+use ignore::IgnoreRules;
+use merkledag::{Link, Node};
+use unixfs::FSNode;
+
+use rust_multihash::Multihash;
+use std::fs::{self, File};
 use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// the size of the chunks files are split into unless told otherwise
+pub const DEFAULT_CHUNK_SIZE: usize = 256 * 1024;
+
+// the most children a node of a file's DAG has, as in go-ipfs
+const LINKS_PER_NODE: usize = 174;
+
+// Reads content in chunks of a fixed size. Only the last chunk may be shorter.
+pub struct FixedChunker<R> {
+    reader: R,
+    size: usize,
+    done: bool,
+}
+
+impl<R: Read> FixedChunker<R> {
+    pub fn new(reader: R, size: usize) -> Self {
+        FixedChunker {
+            reader: reader,
+            size: size,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for FixedChunker<R> {
+    type Item = Result<Vec<u8>, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut chunk = Vec::with_capacity(self.size);
+        match Read::by_ref(&mut self.reader).take(self.size as u64).read_to_end(&mut chunk) {
+            Err(e) => {
+                self.done = true;
+                Some(Err(format!("Error reading content: {}", e)))
+            }
+            Ok(n) => {
+                if n < self.size {
+                    self.done = true;
+                }
+                if n == 0 {
+                    None
+                } else {
+                    Some(Ok(chunk))
+                }
+            }
+        }
+    }
+}
+
+// A node that has been stored, with what its parent needs to link to it
+struct Piece {
+    hash: Multihash,
+    filesize: u64, // the length of the file content under the node
+    cumulative: u64, // the encoded size of the node and everything under it
+}
+
+fn put_fs_node(fs_node: &FSNode,
+               links: Vec<Link>,
+               put: &mut FnMut(&Node) -> Result<Multihash, String>)
+               -> Result<(Multihash, u64), String> {
+    let mut data = Vec::new();
+    try!(fs_node.encode_to_writer(&mut data));
+    let node = Node::new(data, links);
+
+    let mut encoded = Vec::new();
+    try!(node.encode_to_writer(&mut encoded));
+    Ok((try!(put(&node)), encoded.len() as u64))
+}
+
+// Stores the content read from `reader` as a file, passing each node to `put`
+// children first. Returns the hash of the file's root.
+pub fn add_file<R: Read>(reader: R,
+                         chunk_size: usize,
+                         put: &mut FnMut(&Node) -> Result<Multihash, String>)
+                         -> Result<Multihash, String> {
+    file_piece(reader, chunk_size, put).map(|piece| piece.hash)
+}
+
+fn file_piece<R: Read>(reader: R,
+                       chunk_size: usize,
+                       put: &mut FnMut(&Node) -> Result<Multihash, String>)
+                       -> Result<Piece, String> {
+    let mut level = Vec::new();
+    for chunk in FixedChunker::new(reader, chunk_size) {
+        let chunk = try!(chunk);
+        let filesize = chunk.len() as u64;
+        let (hash, size) = try!(put_fs_node(&FSNode::file_from_bytes(chunk), vec![], put));
+        level.push(Piece {
+            hash: hash,
+            filesize: filesize,
+            cumulative: size,
+        });
+    }
+
+    if level.is_empty() {
+        let (hash, size) = try!(put_fs_node(&FSNode::file_from_bytes(vec![]), vec![], put));
+        return Ok(Piece {
+            hash: hash,
+            filesize: 0,
+            cumulative: size,
+        });
+    }
+
+    while level.len() > 1 {
+        let mut parents = Vec::with_capacity(level.len() / LINKS_PER_NODE + 1);
+        for children in level.chunks(LINKS_PER_NODE) {
+            let blocksizes: Vec<u64> = children.iter().map(|child| child.filesize).collect();
+            let links = children.iter()
+                                .map(|child| {
+                                    Link::new(String::new(), child.hash.clone(), child.cumulative)
+                                })
+                                .collect();
+            let (hash, size) = try!(put_fs_node(&FSNode::file_from_blocks(blocksizes.clone()),
+                                                links,
+                                                put));
+            parents.push(Piece {
+                hash: hash,
+                filesize: blocksizes.iter().sum(),
+                cumulative: size + children.iter().map(|child| child.cumulative).sum::<u64>(),
+            });
+        }
+        level = parents;
+    }
+    Ok(level.pop().unwrap())
+}
+
+// What adding a directory leaves out of it
+#[derive(Clone)]
+pub struct Filter {
+    pub exclude: Vec<String>, // entry names, left out at any depth
+    pub ignore: IgnoreRules,
+    // whether the rules in .gitignore files under the directory are followed,
+    // and .git directories left out, as git does
+    pub gitignore: bool,
+    // whether symlinks are added as what they lead to rather than left out
+    pub dereference_symlinks: bool,
+}
+
+// How a directory is told apart from others however it's reached
+#[cfg(unix)]
+type DirId = (u64, u64);
+
+#[cfg(unix)]
+fn dir_id(path: &Path) -> Result<DirId, String> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = try!(fs::metadata(path).map_err(|e| {
+        format!("Error reading metadata of {:?}: {}", path, e)
+    }));
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+type DirId = PathBuf;
+
+#[cfg(not(unix))]
+fn dir_id(path: &Path) -> Result<DirId, String> {
+    fs::canonicalize(path).map_err(|e| format!("Error resolving {:?}: {}", path, e))
+}
+
+// Where a walk of a directory being added has got to
+#[derive(Clone)]
+pub struct Walk {
+    rel_path: String, // from the directory being added, with / between components
+    rules: IgnoreRules, // the ignore rules that hold here
+    // the directories from the one being added down to here, when symlinks
+    // are followed, to tell one that leads back up them
+    ancestors: Vec<(DirId, PathBuf)>,
+}
+
+// An entry of a directory being added that the filter keeps
+pub struct Entry {
+    pub name: String,
+    pub path: PathBuf,
+    pub dir: Option<Walk>, // for a directory, the walk on inside it
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Filter {
+            exclude: Vec::new(),
+            ignore: IgnoreRules::new(),
+            gitignore: false,
+            dereference_symlinks: false,
+        }
+    }
+
+    // Starts a walk of the directory at `path`
+    pub fn start(&self, path: &Path) -> Result<Walk, String> {
+        let ancestors = if self.dereference_symlinks {
+            vec![(try!(dir_id(path)), path.to_path_buf())]
+        } else {
+            vec![]
+        };
+        Ok(Walk {
+            rel_path: String::new(),
+            rules: self.ignore.clone(),
+            ancestors: ancestors,
+        })
+    }
+
+    // The entries of the directory at `path`, which `walk` has got to, sorted
+    // by name, but for those the filter leaves out. Symlinks are left out
+    // too, unless they're followed, since they needn't stay inside the
+    // directory and could loop back up it; one that does is an error.
+    pub fn read_dir(&self, path: &Path, walk: &Walk) -> Result<Vec<Entry>, String> {
+        let mut rules = walk.rules.clone();
+        let gitignore = path.join(".gitignore");
+        if self.gitignore && gitignore.is_file() {
+            try!(rules.add_file(&gitignore, &walk.rel_path));
+        }
+
+        let mut dir_entries = try!(fs::read_dir(path)
+                                       .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+                                       .map_err(|e| {
+                                           format!("Error reading directory {:?}: {}", path, e)
+                                       }));
+        dir_entries.sort_by_key(|entry| entry.file_name());
+
+        let mut entries = Vec::with_capacity(dir_entries.len());
+        for entry in dir_entries {
+            let name = try!(entry.file_name()
+                                 .into_string()
+                                 .map_err(|name| format!("File name {:?} is not UTF-8", name)));
+            if self.exclude.contains(&name) || (self.gitignore && name == ".git") {
+                continue;
+            }
+            let entry_path = entry.path();
+            // unlike entry_path.is_dir(), this doesn't follow symlinks
+            let file_type = try!(entry.file_type().map_err(|e| {
+                format!("Error reading metadata of {:?}: {}", entry_path, e)
+            }));
+            let is_dir = if !file_type.is_symlink() {
+                file_type.is_dir()
+            } else if self.dereference_symlinks {
+                try!(fs::metadata(&entry_path).map_err(|e| {
+                    format!("Error following symlink {:?}: {}", entry_path, e)
+                })).is_dir()
+            } else {
+                continue;
+            };
+            let rel_path = if walk.rel_path.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", walk.rel_path, name)
+            };
+            if rules.is_ignored(&rel_path, is_dir) {
+                continue;
+            }
+
+            let dir = if is_dir {
+                let mut ancestors = walk.ancestors.clone();
+                if self.dereference_symlinks {
+                    let id = try!(dir_id(&entry_path));
+                    if let Some(&(_, ref ancestor)) = ancestors.iter().find(|a| a.0 == id) {
+                        return Err(format!("Symlink loop: {:?} leads back to {:?}, which it is \
+                                            under",
+                                           entry_path,
+                                           ancestor));
+                    }
+                    ancestors.push((id, entry_path.clone()));
+                }
+                Some(Walk {
+                    rel_path: rel_path,
+                    rules: rules.clone(),
+                    ancestors: ancestors,
+                })
+            } else {
+                None
+            };
+            entries.push(Entry {
+                name: name,
+                path: entry_path,
+                dir: dir,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+// Stores the directory at `path` and everything under it, as `add_file` does
+// for files, leaving out what `filter` does. Returns the hash and the path,
+// starting with `name`, of every file and directory stored, children before
+// their parents, so the last one is the directory itself.
+pub fn add_directory(path: &Path,
+                     name: &str,
+                     chunk_size: usize,
+                     filter: &Filter,
+                     put: &mut FnMut(&Node) -> Result<Multihash, String>)
+                     -> Result<Vec<(Multihash, String)>, String> {
+    let mut added = Vec::new();
+    let walk = try!(filter.start(path));
+    try!(directory_piece(path, name, &walk, chunk_size, filter, put, &mut added));
+    Ok(added)
+}
+
+fn directory_piece(path: &Path,
+                   name: &str,
+                   walk: &Walk,
+                   chunk_size: usize,
+                   filter: &Filter,
+                   put: &mut FnMut(&Node) -> Result<Multihash, String>,
+                   added: &mut Vec<(Multihash, String)>)
+                   -> Result<Piece, String> {
+    let entries = try!(filter.read_dir(path, walk));
+
+    let mut links = Vec::with_capacity(entries.len());
+    let mut children_size = 0;
+    for entry in entries {
+        let shown_name = format!("{}/{}", name, entry.name);
+        let piece = if let Some(ref walk) = entry.dir {
+            try!(directory_piece(&entry.path, &shown_name, walk, chunk_size, filter, put, added))
+        } else {
+            let file = try!(File::open(&entry.path).map_err(|e| {
+                format!("Error opening file {:?}: {}", entry.path, e)
+            }));
+            let piece = try!(file_piece(file, chunk_size, put));
+            added.push((piece.hash.clone(), shown_name));
+            piece
+        };
+
+        children_size += piece.cumulative;
+        links.push(Link::new(entry.name, piece.hash, piece.cumulative));
+    }
+
+    let (hash, size) = try!(put_fs_node(&FSNode::directory(), links, put));
+    added.push((hash.clone(), name.to_string()));
+    Ok(Piece {
+        hash: hash,
+        filesize: 0,
+        cumulative: size + children_size,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_directory, Filter};
+    use merkledag::Node;
+
+    use rust_multihash::Multihash;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::Path;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(path).unwrap().write_all(content.as_bytes()).unwrap();
+    }
+
+    // The paths add_directory stores from `dir` with `filter`, in order
+    fn added(dir: &Path, filter: &Filter) -> Vec<String> {
+        let mut put = |_: &Node| Ok(Multihash::from_vec(vec![0; 34]));
+        add_directory(dir, "d", 16, filter, &mut put)
+            .unwrap()
+            .into_iter()
+            .map(|(_, name)| name)
+            .collect()
+    }
+
+    #[test]
+    fn directories_leave_out_what_the_filter_ignores() {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+        let dir = env::temp_dir().join(format!("ipfs-importer-test-{}", nanos));
+        write(&dir.join(".git/HEAD"), "ref");
+        write(&dir.join(".gitignore"), "*.o\n!keep.o\n");
+        write(&dir.join("main.o"), "o");
+        write(&dir.join("keep.o"), "o");
+        write(&dir.join("src/.gitignore"), "/gen/\n");
+        write(&dir.join("src/gen/a.rs"), "a");
+        write(&dir.join("src/lib.rs"), "lib");
+        write(&dir.join("src/lib.o"), "o");
+        write(&dir.join("target/out"), "out");
+
+        let mut filter = Filter::new();
+        filter.exclude.push("target".to_string());
+        assert_eq!(added(&dir, &filter).len(), 12);
+
+        filter.gitignore = true;
+        assert_eq!(added(&dir, &filter),
+                   vec!["d/.gitignore", "d/keep.o", "d/src/.gitignore", "d/src/lib.rs", "d/src",
+                        "d"]);
+
+        filter.ignore.add("src/", "");
+        assert_eq!(added(&dir, &filter), vec!["d/.gitignore", "d/keep.o", "d"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_followed_until_they_loop() {
+        use std::os::unix::fs::symlink;
+
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+        let dir = env::temp_dir().join(format!("ipfs-importer-symlink-test-{}", nanos));
+        write(&dir.join("a/file"), "file");
+        symlink(dir.join("a"), dir.join("b")).unwrap();
+
+        let mut filter = Filter::new();
+        assert_eq!(added(&dir, &filter), vec!["d/a/file", "d/a", "d"]);
+        filter.dereference_symlinks = true;
+        assert_eq!(added(&dir, &filter),
+                   vec!["d/a/file", "d/a", "d/b/file", "d/b", "d"]);
+
+        symlink(&dir, dir.join("a/up")).unwrap();
+        let mut put = |_: &Node| Ok(Multihash::from_vec(vec![0; 34]));
+        let error = add_directory(&dir, "d", 16, &filter, &mut put).unwrap_err();
+        assert!(error.contains(&format!("{:?} leads back to {:?}", dir.join("a/up"), dir)),
+                "{}",
+                error);
 
-pub fn build_dag_from_reader<R: Read>(reader: &mut R, ds: 
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
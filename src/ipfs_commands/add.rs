@@ -1,59 +1,639 @@
-use commands::{self, HelpText, Command};
+use commands::{self, HelpText, Command, CommandError};
+use commands::builder::CommandBuilder;
+use blockstore::Blockstore;
 use commands::request;
+use core::IpfsNode;
+use hash::{self, HashFn};
+use ignore::IgnoreRules;
+use importer::{self, Filter, Walk};
 use merkledag::{DagService, Node};
-use unixfs::FSNode;
+use provenance::{self, Provenance};
+use util;
 
+use hyper::client::{self, RedirectPolicy};
+use hyper::header::ContentLength;
 use rust_multihash::Multihash;
-use std::fs::File;
-use std::io::Read;
-use std::path::Path;
-use std::sync::Arc;
+use rustc_serialize::json::{Json, ToJson};
+use std::cmp;
+use std::u64;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 const HELP_TEXT: HelpText = HelpText {
     tagline: "Add an object to ipfs.",
     synopsis: "",
-    short_desc: "Adds contents of <path> to ipfs.",
+    short_desc: r#"
+Adds contents of <path> to ipfs, and of each URL given with --from-url.
+Files are split into chunks of --chunk-size bytes, linked to from the
+file's root object. Directories are only added with -r, along with
+everything under them but symlinks, which could lead back up the tree, and:
+
+  - entries named with --exclude, e.g. --exclude .git --exclude target
+  - what .gitignore-style patterns given with --ignore match, e.g.
+    --ignore '*.o' --ignore /build/, or those in the --ignore-rules-path file
+  - with --gitignore, what the .gitignore files in the tree ignore, and .git
+
+Patterns are matched against paths from the directory being added. With
+--dereference-symlinks, symlinks are added as the files and directories they
+lead to, and one that leads back to a directory it's under is an error. With
+--progress, a bar on stderr shows how much of the files' content has been
+added.
+
+With --provenance, a record of where each object came from is kept along
+with it: its source path or URL, the time it was added and a signature by
+this node's key. 'ipfs provenance ls' lists the records of an object.
+"#,
 };
 
-fn run(req: &request::Request) -> Result<(), String> {
+// how many files are read, chunked and hashed at once
+const MAX_CONCURRENT_ADDS: usize = 4;
+
+// how many redirects a --from-url fetch follows unless --max-redirects is given
+const DEFAULT_MAX_REDIRECTS: i32 = 5;
+
+fn run(req: &request::Request) -> Result<(), CommandError> {
     let node = try!(req.context.get_node());
+    let url = req.option_str("from-url").map(|url| url.to_string());
+    // stdin is only read for lack of a path if there's no URL to add either
+    let file_args = match url {
+        None => req.file_args_or_stdin("path"),
+        Some(_) => req.file_arg("path").unwrap_or(&[]).to_vec(),
+    };
+    // content that isn't read from a path: stdin, or the body of an API request
+    let streams: Vec<request::FileArg> = file_args.iter()
+                                                  .filter(|f| f.path().is_none())
+                                                  .cloned()
+                                                  .collect();
+    let paths: Vec<PathBuf> = file_args.iter()
+                                       .filter_map(|f| f.path())
+                                       .map(|path| path.to_path_buf())
+                                       .collect();
+    if paths.is_empty() && streams.is_empty() && url.is_none() {
+        return Err(CommandError::Client("Nothing to add: give a <path> or --from-url".to_string()));
+    }
+    let max_redirects = req.option_int("max-redirects").unwrap_or(DEFAULT_MAX_REDIRECTS);
+    let recursive = req.option_bool("r");
+    let filter = try!(make_filter(req));
+    let chunk_size = req.option_int("chunk-size").unwrap_or(importer::DEFAULT_CHUNK_SIZE as i32) as
+                     usize;
+
+    let hash_fn = try!(hash::by_name(req.option_str("hash")
+                                        .unwrap_or(node.config.import_hash())));
+
+    let limit = try!(ImportLimit::new(req, node));
+
+    // abort before writing anything if the files are already known to be too large
+    let mut sizes = Vec::new();
+    for path in paths.iter() {
+        if !recursive && path.is_dir() {
+            return Err(CommandError::Client(format!("{:?} is a directory; use -r to add it",
+                                                    path)));
+        }
+        sizes.push(try!(content_size(path, &filter)));
+    }
+    let total_size = sizes.iter().fold(0, |total, size| total + size);
+    try!(limit.check(total_size));
+
+    let limit = Arc::new(Mutex::new(limit));
+    let worker_limit = limit.clone();
+    let blockstore = node.blockstore.clone();
+    let dagservice = node.dagservice.clone();
+
+    // a file that can't be added doesn't stop the others from being added
+    let mut failed = 0;
+    // the objects added along with their sources, for --provenance
+    let mut added = Vec::new();
+    // the hashes and names of the objects added, only emitted once the import
+    // is known to stand, so an aborted import doesn't report what it removed
+    let mut emitted = Vec::new();
+    // the bytes in the files done with so far, added or not
+    let mut done_size = 0;
+    util::run_concurrently(paths.clone(),
+                           MAX_CONCURRENT_ADDS,
+                           move |path| {
+                               let mut put = |dag_node: &Node| {
+                                   add_node(dag_node,
+                                            hash_fn,
+                                            &blockstore,
+                                            &dagservice,
+                                            &worker_limit)
+                               };
+                               let name = path.to_string_lossy().into_owned();
+                               if path.is_dir() {
+                                   importer::add_directory(&path,
+                                                           &name,
+                                                           chunk_size,
+                                                           &filter,
+                                                           &mut put)
+                               } else {
+                                   let file = try!(File::open(&path).map_err(|e| {
+                                       format!("Error opening file: {}", e)
+                                   }));
+                                   importer::add_file(file, chunk_size, &mut put)
+                                       .map(|hash| vec![(hash, name)])
+                               }
+                           },
+                           |i, result| {
+                               done_size += sizes[i];
+                               req.progress(done_size,
+                                            Some(total_size),
+                                            &format!("{}", paths[i].display()));
+                               match result {
+                                   Ok(entries) => {
+                                       for &(ref hash, ref name) in entries.iter() {
+                                           emitted.push((hash.clone(),
+                                                         format!("{:?}", name),
+                                                         name.clone()));
+                                       }
+                                       // the path's own object comes last
+                                       let root = entries.last().unwrap().0.clone();
+                                       added.push((root, source_of(&paths[i])));
+                                   }
+                                   Err(e) => {
+                                       failed += 1;
+                                       let _ = writeln!(io::stderr(),
+                                                        "error adding {:?}: {}",
+                                                        paths[i],
+                                                        e);
+                                   }
+                               }
+                           });
+
+    for stream in streams.iter() {
+        let result = stream.open().and_then(|reader| {
+            importer::add_file(reader, chunk_size, &mut |dag_node: &Node| {
+                add_node(dag_node, hash_fn, &node.blockstore, &node.dagservice, &limit)
+            })
+        });
+        let source = if stream.is_stdin() { "stdin" } else { "request body" };
+        match result {
+            Ok(hash) => {
+                emitted.push((hash.clone(),
+                              request::STDIN_ARG.to_string(),
+                              request::STDIN_ARG.to_string()));
+                added.push((hash, source.to_string()));
+            }
+            Err(e) => {
+                failed += 1;
+                let _ = writeln!(io::stderr(), "error adding {}: {}", source, e);
+            }
+        }
+    }
+
+    if let Some(ref url) = url {
+        // the remaining limit is taken after the files, so the fetch can stop
+        // reading as soon as it goes over
+        let remaining = limit.lock().unwrap().remaining();
+        let result = fetch_url(url, node.config.http_proxy(), max_redirects, remaining)
+                         .and_then(|mut body| {
+                             let result = importer::add_file(&mut body,
+                                                             chunk_size,
+                                                             &mut |dag_node: &Node| {
+                                                                 add_node(dag_node,
+                                                                          hash_fn,
+                                                                          &node.blockstore,
+                                                                          &node.dagservice,
+                                                                          &limit)
+                                                             });
+                             // the chunks read before it went over are
+                             // removed with the rest of the import
+                             if body.over_limit() {
+                                 limit.lock().unwrap().exceeded = true;
+                             }
+                             result
+                         });
+        match result {
+            Ok(hash) => {
+                emitted.push((hash.clone(), url.clone(), url.clone()));
+                added.push((hash, url.clone()));
+            }
+            Err(e) => {
+                failed += 1;
+                let _ = writeln!(io::stderr(), "error adding {}: {}", url, e);
+            }
+        }
+    }
 
-    for path in req.file_arg("path").unwrap() {
-        let hash = try!(add_file(path, node.dagservice.clone()));
-        println!("added {} {:?}", hash, path);
+    let total = paths.len() + streams.len() + if url.is_some() { 1 } else { 0 };
+    let mut limit = limit.lock().unwrap();
+    if limit.exceeded {
+        try!(limit.abort(node));
+        return Err(CommandError::Client("Import aborted: the size limit was exceeded".to_string()));
+    }
+    for &(ref hash, ref shown_name, ref name) in emitted.iter() {
+        emit_added(req, hash, shown_name, name);
+    }
+
+    // only recorded once the import is known to stand, so an aborted import
+    // leaves no records behind
+    let mut unrecorded = 0;
+    if req.option_bool("provenance") {
+        for &(ref hash, ref source) in added.iter() {
+            let record = Provenance::new(node, hash, source.clone(), &*req.context.clock);
+            let result = record.and_then(|record| {
+                provenance::record(node, req.context.repo_dir.clone(), hash, &record)
+            });
+            if let Err(e) = result {
+                unrecorded += 1;
+                let _ = writeln!(io::stderr(),
+                                 "error recording the provenance of {}: {}",
+                                 source,
+                                 e);
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(CommandError::Internal(format!("{} of {} files could not be added",
+                                                   failed,
+                                                   total)));
+    }
+    if unrecorded > 0 {
+        return Err(CommandError::Internal(format!("The provenance of {} of {} files could not \
+                                                   be recorded",
+                                                  unrecorded,
+                                                  added.len())));
     }
     Ok(())
 }
 
-ipfs_command!(AddCommand, run);
+fn validate(req: &request::Request) -> Result<(), CommandError> {
+    let config = try!(req.context.get_config());
 
+    try!(hash::by_name(req.option_str("hash").unwrap_or(config.import_hash()))
+             .map_err(CommandError::Client));
+    if let Some(s) = req.option_str("size-limit") {
+        try!(util::parse_size(s).map_err(|e| {
+            CommandError::Client(format!("Invalid --size-limit: {}", e))
+        }));
+    }
+    if let Some(url) = req.option_str("from-url") {
+        if !req.context.online {
+            return Err(CommandError::Client("--from-url needs the network, which --offline \
+                                             doesn't allow"
+                                                .to_string()));
+        }
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(CommandError::Client(format!("--from-url only supports http:// and \
+                                                     https:// URLs, not {}",
+                                                    url)));
+        }
+    }
+    if req.option_int("chunk-size").map_or(false, |n| n < 1) {
+        return Err(CommandError::Client("--chunk-size must be at least 1".to_string()));
+    }
+    if req.option_int("max-redirects").map_or(false, |n| n < 0) {
+        return Err(CommandError::Client("--max-redirects can't be negative".to_string()));
+    }
+    Ok(())
+}
+
+// The number of bytes an import may still write, given the --size-limit option
+// and the repo quota, along with the blocks written so far in case the import
+// has to be aborted. Blocks are counted at the size the blockstore stores them
+// at, compressed or encrypted as the repo's blocks are, as the quota is
+// measured by the blockstore.
+struct ImportLimit {
+    size_limit: Option<u64>,
+    size_remaining: Option<u64>, // under the size limit
+    quota_remaining: Option<u64>,
+    written: Vec<Multihash>,
+    exceeded: bool,
+}
+
+impl ImportLimit {
+    fn new(req: &request::Request, node: &IpfsNode) -> Result<Self, String> {
+        let size_limit = match req.option_str("size-limit") {
+            Some(s) => Some(try!(util::parse_size(s))),
+            None => None,
+        };
+
+        let quota_remaining = match node.config.storage_max() {
+            None => None,
+            Some(max) => {
+                let used = try!(node.blockstore.size_on_disk());
+                Some(max.saturating_sub(used))
+            }
+        };
+
+        Ok(ImportLimit {
+            size_limit: size_limit,
+            size_remaining: size_limit,
+            quota_remaining: quota_remaining,
+            written: Vec::new(),
+            exceeded: false,
+        })
+    }
+
+    // The bytes that may still be written under both bounds
+    fn remaining(&self) -> Option<u64> {
+        match (self.size_remaining, self.quota_remaining) {
+            (Some(a), Some(b)) => Some(cmp::min(a, b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    // Fails, naming the bound it would exceed, if `size` more bytes can't be
+    // written
+    fn check(&self, size: u64) -> Result<(), String> {
+        if self.size_remaining.map_or(false, |remaining| size > remaining) {
+            return Err(format!("Import aborted: it would exceed the size limit of {} bytes",
+                               self.size_limit.unwrap()));
+        }
+        match self.quota_remaining {
+            Some(remaining) if size > remaining => {
+                Err(format!("Import aborted: it would exceed the repo quota ({} bytes \
+                             remaining)",
+                            remaining))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Takes `size` bytes off what may still be written, before the block is
+    fn reserve(&mut self, size: u64) {
+        self.size_remaining = self.size_remaining.map(|remaining| remaining.saturating_sub(size));
+        self.quota_remaining = self.quota_remaining
+                                   .map(|remaining| remaining.saturating_sub(size));
+    }
 
-fn add_file<P: AsRef<Path>>(path: P, ds: Arc<DagService>) -> Result<Multihash, String> {
-    let mut file = try!(File::open(path).map_err(|e| {
-        format!("Error opening file: {}", e)
+    // Gives back what `reserve` took for a block that couldn't be written
+    fn release(&mut self, size: u64) {
+        self.size_remaining = self.size_remaining.map(|remaining| remaining + size);
+        self.quota_remaining = self.quota_remaining.map(|remaining| remaining + size);
+    }
+
+    fn record(&mut self, hash: Multihash) {
+        self.written.push(hash);
+    }
+
+    // Removes the blocks written by this import, unless the repo is configured
+    // to leave them in place. Concurrent adds of the same block both record
+    // it, so it may already be gone.
+    fn abort(&mut self, node: &IpfsNode) -> Result<(), String> {
+        if !node.config.cleanup_aborted_imports() {
+            return Ok(());
+        }
+        for hash in self.written.drain(..) {
+            if try!(node.blockstore.has(&hash)) {
+                try!(node.blockstore.delete(&hash));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn emit_added(req: &request::Request, hash: &Multihash, shown_name: &str, name: &str) {
+    let hash = format!("{}", hash);
+    let mut obj = BTreeMap::new();
+    obj.insert("hash".to_string(), hash.to_json());
+    obj.insert("name".to_string(), name.to_json());
+    req.emit_value(&format!("added {} {}", hash, shown_name), &hash, Json::Object(obj));
+}
+
+// The absolute path of a file, so its provenance doesn't depend on where
+// `ipfs add` was run from
+fn source_of(path: &Path) -> String {
+    let path = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+    format!("{}", path.display())
+}
+
+// What directories added with -r leave out, from --exclude, --ignore,
+// --ignore-rules-path and --gitignore
+fn make_filter(req: &request::Request) -> Result<Filter, CommandError> {
+    let mut ignore = IgnoreRules::new();
+    if let Some(path) = req.option_str("ignore-rules-path") {
+        try!(ignore.add_file(Path::new(path), "").map_err(CommandError::Client));
+    }
+    for pattern in req.option_strs("ignore") {
+        ignore.add(pattern, "");
+    }
+    Ok(Filter {
+        exclude: req.option_strs("exclude").to_vec(),
+        ignore: ignore,
+        gitignore: req.option_bool("gitignore"),
+        dereference_symlinks: req.option_bool("dereference-symlinks"),
+    })
+}
+
+// The size of the file at `path`, or of all the files under it that `filter`
+// keeps if it is a directory, as the importer leaves the others out
+fn content_size(path: &Path, filter: &Filter) -> Result<u64, String> {
+    let metadata = try!(fs::metadata(path).map_err(|e| {
+        format!("Error reading metadata of {:?}: {}", path, e)
     }));
-    let mut file_data = Vec::new();
-    file.read_to_end(&mut file_data);
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    directory_size(path, &try!(filter.start(path)), filter)
+}
 
-    let fs_node = FSNode::file_from_bytes(file_data);
+fn directory_size(path: &Path, walk: &Walk, filter: &Filter) -> Result<u64, String> {
+    let mut size = 0;
+    for entry in try!(filter.read_dir(path, walk)) {
+        size += if let Some(ref walk) = entry.dir {
+            try!(directory_size(&entry.path, walk, filter))
+        } else {
+            try!(fs::metadata(&entry.path).map_err(|e| {
+                format!("Error reading metadata of {:?}: {}", entry.path, e)
+            })).len()
+        };
+    }
+    Ok(size)
+}
 
-    let mut buf = Vec::new();
-    try!(fs_node.encode_to_writer(&mut buf));
+// Fetches `url`, returning its body to be read as it arrives. With
+// `max_size`, the body fails to read once more than that many bytes of it
+// have come in, so no more than that is taken from the connection.
+fn fetch_url(url: &str,
+             proxy: Option<&str>,
+             max_redirects: i32,
+             max_size: Option<u64>)
+             -> Result<UrlBody, String> {
+    let mut client = try!(util::http_client(proxy));
+    client.set_redirect_policy(if max_redirects == 0 {
+        RedirectPolicy::FollowNone
+    } else {
+        RedirectPolicy::FollowCount(max_redirects as u32)
+    });
+
+    let res = try!(client.get(url)
+                         .send()
+                         .map_err(|e| format!("Error fetching {}: {}", url, e)));
+    if !res.status.is_success() {
+        return Err(format!("Error fetching {}: server responded {}", url, res.status));
+    }
+
+    if let (Some(max), Some(&ContentLength(len))) = (max_size, res.headers.get()) {
+        if len > max {
+            return Err(format!("Response is {} bytes, more than the {} bytes allowed",
+                               len,
+                               max));
+        }
+    }
+
+    Ok(UrlBody {
+        res: res.take(max_size.map_or(u64::MAX, |max| max + 1)),
+        max_size: max_size,
+        read: 0,
+    })
+}
 
-    let dag_node = Node::from_data(buf);
-    ds.add(&dag_node)
+// The body of a --from-url response, which fails to read past `max_size`
+struct UrlBody {
+    res: io::Take<client::Response>,
+    max_size: Option<u64>,
+    read: u64,
+}
+
+impl UrlBody {
+    fn over_limit(&self) -> bool {
+        self.max_size.map_or(false, |max| self.read > max)
+    }
+}
+
+impl Read for UrlBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.res.read(buf));
+        self.read += n as u64;
+        if self.over_limit() {
+            return Err(io::Error::new(io::ErrorKind::Other,
+                                      format!("Response is more than the {} bytes allowed",
+                                              self.max_size.unwrap())));
+        }
+        Ok(n)
+    }
+}
+
+// A block's size is taken off the limit before it's written, so that
+// concurrent adds can't overshoot the limit between them, but the limit
+// isn't held locked during the write itself, which would serialize them.
+fn add_node(dag_node: &Node,
+            hash_fn: &HashFn,
+            blockstore: &Blockstore,
+            dagservice: &DagService,
+            limit: &Mutex<ImportLimit>)
+            -> Result<Multihash, String> {
+    let hash = dag_node.multihash_with(hash_fn);
+
+    // blocks that are already stored don't count against the limit
+    if try!(blockstore.has(&hash)) {
+        return Ok(hash);
+    }
+
+    let mut encoded = Vec::new();
+    try!(dag_node.encode_to_writer(&mut encoded));
+    let size = try!(blockstore.encoded_size(&hash, &encoded[..]));
+    {
+        let mut limit = limit.lock().unwrap();
+        if let Err(e) = limit.check(size) {
+            limit.exceeded = true;
+            return Err(e);
+        }
+        limit.reserve(size);
+    }
+    match dagservice.add_with(dag_node, hash_fn) {
+        Ok(hash) => {
+            limit.lock().unwrap().record(hash.clone());
+            Ok(hash)
+        }
+        Err(e) => {
+            limit.lock().unwrap().release(size);
+            Err(e)
+        }
+    }
 }
 
-pub fn make_command() -> Box<Command> {
+pub fn make_command() -> Arc<Command> {
+    let size_limit = commands::Opt::new_string(
+        vec!["size-limit"],
+        "Abort the import if it would write more than this many bytes (e.g. 100M)"
+    );
+
+    let from_url = commands::Opt::new_string(
+        vec!["from-url"],
+        "Also add the content fetched from this http(s) URL"
+    );
+
+    let max_redirects = commands::Opt::new_int(
+        vec!["max-redirects"],
+        "How many redirects to follow when fetching --from-url (0 for none)"
+    ).with_default(request::Opt::Int(DEFAULT_MAX_REDIRECTS));
+
+    let hash = commands::Opt::new_string(
+        vec!["hash"],
+        "The hash function to address objects with (sha2-256, sha2-512, blake2b, or blake3 \
+         in builds with the blake3 feature). \
+         Defaults to Import.Hash from the config, or sha2-256"
+    );
+
+    let provenance = commands::Opt::new_bool(
+        vec!["provenance"],
+        "Record the source, time and this node's signature for each added object"
+    );
+
+    let recursive = commands::Opt::new_bool(
+        vec!["r", "recursive"],
+        "Add directories along with everything under them"
+    );
+
+    let exclude = commands::Opt::new_repeated(
+        vec!["exclude"],
+        "Leave out files and directories with this name when adding a directory with -r"
+    );
+
+    let ignore = commands::Opt::new_repeated(
+        vec!["ignore"],
+        "Leave out what this .gitignore-style pattern matches when adding a directory with -r"
+    );
+
+    let ignore_rules_path = commands::Opt::new_string(
+        vec!["ignore-rules-path"],
+        "A file of .gitignore-style patterns to leave out when adding a directory with -r"
+    );
+
+    let gitignore = commands::Opt::new_bool(
+        vec!["gitignore"],
+        "Follow the .gitignore files under a directory added with -r, and leave out .git"
+    );
+
+    let dereference_symlinks = commands::Opt::new_bool(
+        vec!["dereference-symlinks"],
+        "Add what the symlinks under a directory added with -r lead to, instead of leaving \
+         them out"
+    );
+
+    let chunk_size = commands::Opt::new_int(
+        vec!["chunk-size"],
+        "The size in bytes of the chunks files are split into"
+    ).with_default(request::Opt::Int(importer::DEFAULT_CHUNK_SIZE as i32));
+
     let arg_path = commands::Argument::new_file(
         "path",
+        false,
         true,
-        true,
-        "The path(s) to a file to be added to IPFS"
+        "The path(s) to a file to be added to IPFS, or - for stdin, which is also read \
+         if no path is given and something is piped in"
     );
 
 
-    Box::new(AddCommand::new("add", vec![], vec![arg_path], HELP_TEXT, vec![]))
+    CommandBuilder::new("add", HELP_TEXT)
+        .option(recursive)
+        .option(exclude)
+        .option(ignore)
+        .option(ignore_rules_path)
+        .option(gitignore)
+        .option(dereference_symlinks)
+        .option(chunk_size)
+        .option(size_limit)
+        .option(from_url)
+        .option(max_redirects)
+        .option(hash)
+        .option(provenance)
+        .arg(arg_path)
+        .mutating()
+        .validate(validate)
+        .run(run)
 }
-
-// fn add_file<P: AsRef<Path>>(path: P)
@@ -0,0 +1,124 @@
+use commands::{HelpText, Command, Argument, Opt, CommandError};
+use commands::builder::CommandBuilder;
+use commands::request;
+use ipfs_commands::root;
+use util;
+
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader};
+use std::sync::Arc;
+
+const HELP_TEXT: HelpText = HelpText {
+    tagline: "Run a script of ipfs command lines",
+    synopsis: "",
+    short_desc: r#"
+'ipfs batch' reads one command line per line from <script>, or from stdin
+if no script is given, and runs them in order against the repo, which is
+opened only once. Lines are written the way they would be after 'ipfs' on
+the command line. Blank lines and lines starting with # are skipped.
+
+Every line is run even if earlier ones fail, unless --stop-on-error is
+given. At the end the failed lines are listed with their errors, as JSON
+objects with --encoding json.
+
+While a daemon serves the repo, each line is sent to it, as it would be
+from the command line. Otherwise the repo is locked until the script ends,
+so that no daemon can start and modify the repo under it.
+"#,
+};
+
+struct LineResult {
+    line_number: usize,
+    line: String,
+    result: Result<(), CommandError>,
+}
+
+impl ToJson for LineResult {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert("line".to_string(), self.line_number.to_json());
+        obj.insert("command".to_string(), self.line.to_json());
+        if let Err(ref e) = self.result {
+            obj.insert("error".to_string(), e.message().to_json());
+            obj.insert("exit_code".to_string(), e.exit_code().to_json());
+        }
+        Json::Object(obj)
+    }
+}
+
+fn run(req: &request::Request) -> Result<(), CommandError> {
+    let root = root::make_command();
+    let stop_on_error = req.option_bool("stop-on-error");
+
+    let reader = match req.file_args_or_stdin("script").first() {
+        Some(script) => try!(script.open()),
+        None => {
+            return Err(CommandError::Client("No script to run: give a <script> or pipe one in"
+                                                .to_string()));
+        }
+    };
+
+    let mut session = try!(::Session::start(&req.context.repo_dir, req.context.read_only));
+    let mut results = Vec::new();
+    for (i, line) in BufReader::new(reader).lines().enumerate() {
+        let line = try!(line.map_err(|e| format!("Error reading script: {}", e)));
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("#") {
+            continue;
+        }
+
+        let result = util::split_words(line).map_err(CommandError::Client).and_then(|mut words| {
+            if words.first().map_or(false, |w| w == "ipfs") {
+                words.remove(0);
+            }
+            let context = req.context.clone();
+            ::run_line(&*root, words, context, &mut session, &mut *req.raw_output())
+        });
+        let failed = result.is_err();
+        results.push(LineResult {
+            line_number: i + 1,
+            line: line.to_string(),
+            result: result,
+        });
+
+        if failed && stop_on_error {
+            break;
+        }
+    }
+
+    let failed: Vec<&LineResult> = results.iter().filter(|r| r.result.is_err()).collect();
+    for r in failed.iter() {
+        req.emit_value(&format!("line {}: {}: {}",
+                                r.line_number,
+                                r.line,
+                                r.result.as_ref().err().unwrap()),
+                       &r.line_number.to_string(),
+                       r.to_json());
+    }
+
+    if !failed.is_empty() {
+        // the failed lines are the output, so it's finished even though the
+        // batch fails
+        req.finish_output();
+        return Err(CommandError::Internal(format!("{} of {} command lines failed",
+                                                  failed.len(),
+                                                  results.len())));
+    }
+    Ok(())
+}
+
+pub fn make_command() -> Arc<Command> {
+    let arg_script = Argument::new_file("script",
+                                        false,
+                                        false,
+                                        "The file to read command lines from (default: stdin)");
+
+    let stop_on_error = Opt::new_bool(vec!["stop-on-error"],
+                                      "Stop at the first command line that fails");
+
+    CommandBuilder::new("batch", HELP_TEXT)
+        .option(stop_on_error)
+        .arg(arg_script)
+        .run(run)
+}
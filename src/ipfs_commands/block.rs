@@ -0,0 +1,168 @@
+use commands::{self, HelpText, Command, Argument, Opt, CommandError};
+use commands::builder::CommandBuilder;
+use commands::request;
+use hash;
+use ipfs_commands;
+
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+const BlockHelpText: HelpText = HelpText {
+    tagline: "Interact with raw blocks in the datastore",
+    synopsis: "",
+    short_desc: r#"
+'ipfs block' is a plumbing command used to manipulate raw ipfs blocks.
+Reads from stdin or writes to stdout, and <key> is a base58 encoded
+multihash.
+"#,
+};
+
+const PutHelpText: HelpText = HelpText {
+    tagline: "Store input as an ipfs block",
+    synopsis: "",
+    short_desc: r#"
+'ipfs block put' reads a block from <data>, or from stdin if no file is
+given, stores it as is and prints its key.
+"#,
+};
+
+const GetHelpText: HelpText = HelpText {
+    tagline: "Get a raw ipfs block",
+    synopsis: "",
+    short_desc: "Writes the data of the block named by <key> to stdout.",
+};
+
+const StatHelpText: HelpText = HelpText {
+    tagline: "Print information of a raw ipfs block",
+    synopsis: "",
+    short_desc: "Prints the key and the size in bytes of the block named by <key>.",
+};
+
+fn run_block(req: &request::Request) -> Result<(), CommandError> {
+    try!(write!(req.raw_output(), "{}", commands::help::render(req.command, "block", false))
+             .map_err(|e| format!("Error writing help: {}", e)));
+    Ok(())
+}
+
+pub fn make_command() -> Arc<Command> {
+    CommandBuilder::new("block", BlockHelpText)
+        .subcommand(make_put_command())
+        .subcommand(make_get_command())
+        .subcommand(make_stat_command())
+        .run(run_block)
+}
+
+fn run_put(req: &request::Request) -> Result<(), CommandError> {
+    let node = try!(req.context.get_node());
+    let file = match req.file_args_or_stdin("data").into_iter().next() {
+        None => {
+            return Err(CommandError::Client("Nothing to put: give a <data> file or pipe the \
+                                             data in"
+                                                .to_string()))
+        }
+        Some(file) => file,
+    };
+
+    let mut data = Vec::new();
+    try!(try!(file.open())
+             .read_to_end(&mut data)
+             .map_err(|e| format!("Error reading {:?}: {}", file, e)));
+
+    let hash_fn = try!(hash::by_name(req.option_str("hash").unwrap_or(node.config.import_hash())));
+    let mh = hash_fn.multihash(&data);
+
+    // as with add, the block counts at the size it's stored at, and not at
+    // all if it's already stored
+    if let Some(max) = node.config.storage_max() {
+        if !try!(node.blockstore.has(&mh)) {
+            let size = try!(node.blockstore.encoded_size(&mh, &data));
+            let used = try!(node.blockstore.size_on_disk());
+            if used + size > max {
+                return Err(CommandError::Client(format!("Putting a block of {} bytes would \
+                                                         take the repo over its storage_max \
+                                                         of {} bytes",
+                                                        size,
+                                                        max)));
+            }
+        }
+    }
+
+    try!(node.blockstore.put(&mh, &data));
+
+    let key = format!("{}", mh);
+    req.emit(&key, &key);
+    Ok(())
+}
+
+fn validate_put(req: &request::Request) -> Result<(), CommandError> {
+    let config = try!(req.context.get_config());
+    try!(hash::by_name(req.option_str("hash").unwrap_or(config.import_hash()))
+             .map_err(CommandError::Client));
+    Ok(())
+}
+
+fn make_put_command() -> Arc<Command> {
+    let arg_data = Argument::new_file("data",
+                                      false,
+                                      false,
+                                      "The file to read the block from, or - for stdin");
+
+    let hash = Opt::new_string(vec!["hash"],
+                               "The hash function to key the block with, instead of the \
+                                config's import.hash");
+
+    CommandBuilder::new("put", PutHelpText)
+        .option(hash)
+        .arg(arg_data)
+        .mutating()
+        .validate(validate_put)
+        .run(run_put)
+}
+
+fn key_arg() -> Argument {
+    Argument::new_string("key",
+                         true,
+                         false,
+                         "Key of the block (in base58-encoded multihash format)")
+}
+
+fn run_get(req: &request::Request) -> Result<(), CommandError> {
+    let node = try!(req.context.get_node());
+    let mh = try!(ipfs_commands::stored_hash(node, &req.string_arg("key").unwrap()[0]));
+    let block = try!(node.blockstore.get(&mh));
+
+    try!(req.raw_output()
+            .write_all(block.get_data())
+            .map_err(|e| format!("Error writing block: {}", e)));
+    Ok(())
+}
+
+fn make_get_command() -> Arc<Command> {
+    CommandBuilder::new("get", GetHelpText)
+        .arg(key_arg())
+        .run(run_get)
+}
+
+fn run_stat(req: &request::Request) -> Result<(), CommandError> {
+    let node = try!(req.context.get_node());
+    let mh = try!(ipfs_commands::stored_hash(node, &req.string_arg("key").unwrap()[0]));
+    let block = try!(node.blockstore.get(&mh));
+
+    let key = format!("{}", mh);
+    let size = block.get_data().len();
+    let mut obj = BTreeMap::new();
+    obj.insert("key".to_string(), key.to_json());
+    obj.insert("size".to_string(), size.to_json());
+    req.emit_value(&format!("Key: {}\nSize: {}", key, size),
+                   &format!("{}", size),
+                   Json::Object(obj));
+    Ok(())
+}
+
+fn make_stat_command() -> Arc<Command> {
+    CommandBuilder::new("stat", StatHelpText)
+        .arg(key_arg())
+        .run(run_stat)
+}
@@ -0,0 +1,53 @@
+use commands::{HelpText, Command, Argument, CommandError};
+use commands::builder::CommandBuilder;
+use commands::request;
+use ipfs_commands;
+use unixfs;
+
+use std::io::Write;
+use std::sync::Arc;
+
+const HELP_TEXT: HelpText = HelpText {
+    tagline: "Show ipfs object data",
+    synopsis: "",
+    short_desc: r#"
+Retrieves the file named by each <ipfs-path> and writes its content to
+stdout, one after the other. The chunks a large file was split into by
+'ipfs add' are reassembled in order.
+"#,
+};
+
+fn run(req: &request::Request) -> Result<(), CommandError> {
+    let node = try!(req.context.get_node());
+
+    // every path is checked before anything is written, so a bad one doesn't
+    // leave partial output behind
+    let mut roots = Vec::new();
+    for path in req.string_arg("ipfs-path").unwrap() {
+        let mh = try!(ipfs_commands::stored_hash(node, path));
+        let root = try!(node.dagservice.get(&mh));
+        let data = try!(unixfs::from_reader(&mut root.get_data()));
+        if data.get_Type() == unixfs::pb::Data_DataType::Directory {
+            return Err(CommandError::Client(format!("{} is a directory", mh)));
+        }
+        roots.push(root);
+    }
+
+    let mut out = req.raw_output();
+    for root in roots.iter() {
+        try!(unixfs::write_file(&node.dagservice, root, &mut *out));
+    }
+    try!(out.flush().map_err(|e| format!("Error writing output: {}", e)));
+    Ok(())
+}
+
+pub fn make_command() -> Arc<Command> {
+    let arg_path = Argument::new_string("ipfs-path",
+                                        true,
+                                        true,
+                                        "The path(s) to the IPFS object(s) to be outputted");
+
+    CommandBuilder::new("cat", HELP_TEXT)
+        .arg(arg_path)
+        .run(run)
+}
@@ -0,0 +1,38 @@
+use commands::{self, HelpText, Command, Argument, CommandError};
+use commands::builder::CommandBuilder;
+use commands::completion::Shell;
+use commands::request;
+use ipfs_commands::root;
+
+use std::io::Write;
+use std::sync::Arc;
+
+const HELP_TEXT: HelpText = HelpText {
+    tagline: "Print a shell completion script",
+    synopsis: "",
+    short_desc: r#"
+Prints a script that completes ipfs subcommands, options and file arguments
+for <shell>, which is bash, zsh or fish. For example:
+
+    ipfs completion bash > /etc/bash_completion.d/ipfs
+    ipfs completion zsh > "${fpath[1]}/_ipfs"
+    ipfs completion fish > ~/.config/fish/completions/ipfs.fish
+"#,
+};
+
+fn run(req: &request::Request) -> Result<(), CommandError> {
+    let shell = try!(Shell::by_name(&req.string_arg("shell").unwrap()[0])
+                         .map_err(CommandError::Client));
+    let script = commands::completion::script(&*root::make_command(), shell);
+    try!(write!(req.raw_output(), "{}", script)
+             .map_err(|e| format!("Error writing completion script: {}", e)));
+    Ok(())
+}
+
+pub fn make_command() -> Arc<Command> {
+    let arg_shell = Argument::new_string("shell", true, false, "bash, zsh or fish");
+
+    CommandBuilder::new("completion", HELP_TEXT)
+        .arg(arg_shell)
+        .run(run)
+}
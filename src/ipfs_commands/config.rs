@@ -0,0 +1,230 @@
+use commands::{self, HelpText, Command, Argument, CommandError};
+use commands::builder::CommandBuilder;
+use commands::request;
+use config;
+use fsrepo;
+use ipfs_commands;
+use util;
+
+use rustc_serialize::json::{Json, ToJson};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::sync::Arc;
+
+const ConfigHelpText: HelpText = HelpText {
+    tagline: "Manage configuration",
+    synopsis: "",
+    short_desc: r#"
+The config is the JSON file 'config' in the repo. Keys are named by their
+dotted path, e.g. datastore.storage_max. The keys that can be set are:
+
+    datastore.compress_blocks          true or false
+    datastore.storage_max              a number of bytes, e.g. 10G
+    datastore.cleanup_aborted_imports  true or false
+    datastore.read_only                true or false
+    datastore.delta_blocks             true or false
+    import.hash                        the name of a hash function
+    network.http_proxy                 host:port
+    reports.submit                     true or false
+    reports.endpoint                   an http or https URL
+
+The identity and encryption keys are managed by 'ipfs init' and
+'ipfs repo encrypt', and can't be set by hand. While datastore.read_only is
+set, 'ipfs config set' and 'ipfs config edit' still change the config, so
+the repo can be made writable again, unless --read-only is given too.
+"#,
+};
+
+const ShowHelpText: HelpText = HelpText {
+    tagline: "Print the config",
+    synopsis: "",
+    short_desc: "The private key is left out.",
+};
+
+const GetHelpText: HelpText = HelpText {
+    tagline: "Print the value of a config key",
+    synopsis: "",
+    short_desc: "",
+};
+
+const SetHelpText: HelpText = HelpText {
+    tagline: "Set the value of a config key",
+    synopsis: "",
+    short_desc: r#"
+The value is checked against the key's type before the config is written.
+Setting datastore.compress_blocks only affects blocks added afterwards; use
+'ipfs repo compress' or 'ipfs repo decompress' to rewrite existing blocks.
+"#,
+};
+
+const EditHelpText: HelpText = HelpText {
+    tagline: "Open the config in an editor",
+    synopsis: "",
+    short_desc: r#"
+Opens a copy of the config in $VISUAL or $EDITOR (vi if neither is set).
+Once the editor exits, the copy is checked, and only replaces the config if
+it is valid.
+"#,
+};
+
+// shown in place of the private key
+const REDACTED: &'static str = "<redacted>";
+
+fn run_config(req: &request::Request) -> Result<(), CommandError> {
+    try!(write!(req.raw_output(), "{}", commands::help::render(req.command, "config", false))
+             .map_err(|e| format!("Error writing help: {}", e)));
+    Ok(())
+}
+
+pub fn make_command() -> Arc<Command> {
+    CommandBuilder::new("config", ConfigHelpText)
+        .subcommand(make_show_command())
+        .subcommand(make_get_command())
+        .subcommand(make_set_command())
+        .subcommand(make_edit_command())
+        .run(run_config)
+}
+
+// The config as JSON, without the private key
+fn redacted_json(cfg: &config::Config) -> Result<Json, String> {
+    let encoded = try!(cfg.to_json_string()
+                          .map_err(|e| format!("Error encoding config as Json: {}", e)));
+    let mut json = try!(Json::from_str(&encoded)
+                            .map_err(|e| format!("Error parsing Json: {}", e)));
+    if let Json::Object(ref mut obj) = json {
+        if let Some(&mut Json::Object(ref mut identity)) = obj.get_mut("identity") {
+            identity.insert("private_key".to_string(), REDACTED.to_json());
+        }
+    }
+    Ok(json)
+}
+
+fn run_show(req: &request::Request) -> Result<(), CommandError> {
+    let json = try!(redacted_json(try!(req.context.get_config())));
+    let line = format!("{}", json.pretty());
+    req.emit_value(&line, &line, json);
+    Ok(())
+}
+
+fn make_show_command() -> Arc<Command> {
+    CommandBuilder::new("show", ShowHelpText).run(run_show)
+}
+
+fn run_get(req: &request::Request) -> Result<(), CommandError> {
+    let path = &req.string_arg("key").unwrap()[0];
+    if !config::is_known_path(path) {
+        return Err(CommandError::Client(format!("Unknown config key {}", path)));
+    }
+
+    let json = try!(redacted_json(try!(req.context.get_config())));
+    let value = match config::lookup(&json, path) {
+        None | Some(&Json::Null) => {
+            return Err(CommandError::NotFound(format!("Config key {} is not set", path)))
+        }
+        Some(value) => value.clone(),
+    };
+
+    // strings are printed bare, so they can be used in scripts as they are
+    let line = match value {
+        Json::String(ref s) => s.clone(),
+        Json::Object(_) => format!("{}", value.pretty()),
+        _ => format!("{}", value),
+    };
+    req.emit_value(&line, &line, value);
+    Ok(())
+}
+
+fn make_get_command() -> Arc<Command> {
+    let arg_key = Argument::new_string("key",
+                                       true,
+                                       false,
+                                       "The dotted path of the key, e.g. import.hash");
+
+    CommandBuilder::new("get", GetHelpText)
+        .arg(arg_key)
+        .run(run_get)
+}
+
+fn run_set(req: &request::Request) -> Result<(), CommandError> {
+    let path = &req.string_arg("key").unwrap()[0];
+    let value = &req.string_arg("value").unwrap()[0];
+    let parsed = try!(config::parse_value(path, value).map_err(CommandError::Client));
+
+    // the file is reread rather than taking the loaded config, so nothing but
+    // the key changes
+    let config_path = config::repo_path_to_config_file(req.context.repo_dir.clone());
+    let mut cfg = try!(fsrepo::read_config_file(&config_path));
+    try!(cfg.set(path, &parsed).map_err(CommandError::Client));
+    try!(fsrepo::update_config_file(&config_path, &cfg));
+
+    req.emit(&format!("{} = {}", path, parsed), &format!("{}", parsed));
+    Ok(())
+}
+
+fn validate_set(req: &request::Request) -> Result<(), CommandError> {
+    let path = &req.string_arg("key").unwrap()[0];
+    let value = &req.string_arg("value").unwrap()[0];
+    try!(config::parse_value(path, value).map_err(CommandError::Client));
+    Ok(())
+}
+
+fn make_set_command() -> Arc<Command> {
+    let arg_key = Argument::new_string("key",
+                                       true,
+                                       false,
+                                       "The dotted path of the key, e.g. import.hash");
+
+    let arg_value = Argument::new_string("value", true, false, "The value to set the key to");
+
+    CommandBuilder::new("set", SetHelpText)
+        .arg(arg_key)
+        .arg(arg_value)
+        .edits_config()
+        .pre_run(ipfs_commands::require_repo)
+        .validate(validate_set)
+        .run(run_set)
+}
+
+// Quotes a path for the shell command line the editor is run with
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace("'", "'\\''"))
+}
+
+fn run_edit(req: &request::Request) -> Result<(), CommandError> {
+    let config_path = config::repo_path_to_config_file(req.context.repo_dir.clone());
+    let edit_path = config_path.with_extension("edit");
+    try!(fs::copy(&config_path, &edit_path)
+             .map_err(|e| format!("Error copying config to {:?}: {}", edit_path, e)));
+
+    let editor = env::var("VISUAL").or_else(|_| env::var("EDITOR")).unwrap_or("vi".to_string());
+    let cmd = format!("{} {}", editor, shell_quote(&edit_path.to_string_lossy()));
+    let status = try!(util::shell_command(&cmd)
+                          .status()
+                          .map_err(|e| format!("Error running editor {:?}: {}", editor, e)));
+    if !status.success() {
+        let _ = fs::remove_file(&edit_path);
+        return Err(CommandError::Internal(format!("Editor {:?} failed with {}; the config was \
+                                                   left unchanged",
+                                                  editor,
+                                                  status)));
+    }
+
+    // an invalid copy is kept, so the changes aren't lost
+    let cfg = try!(fsrepo::read_config_file(&edit_path).map_err(|e| {
+        CommandError::Client(format!("The edited config is invalid, so it was not saved: {}\n\
+                                      Your changes are in {:?}.",
+                                     e,
+                                     edit_path))
+    }));
+    try!(fsrepo::update_config_file(&config_path, &cfg));
+    let _ = fs::remove_file(&edit_path);
+    Ok(())
+}
+
+fn make_edit_command() -> Arc<Command> {
+    CommandBuilder::new("edit", EditHelpText)
+        .edits_config()
+        .pre_run(ipfs_commands::require_repo)
+        .run(run_edit)
+}
@@ -0,0 +1,90 @@
+use api;
+use commands::{HelpText, Command, Opt, CommandError};
+use commands::builder::CommandBuilder;
+use commands::request;
+use fsrepo;
+use health::{self, Health};
+use ipfs_commands;
+
+use std::io::Write;
+use std::sync::Arc;
+
+const HELP_TEXT: HelpText = HelpText {
+    tagline: "Run a long-running daemon process",
+    synopsis: "",
+    short_desc: r#"
+'ipfs daemon' serves the HTTP API for the repo on --api until it is killed.
+Every command is an endpoint under /api/v0/, e.g. /api/v0/block/put, taking
+its options as query parameters, its arguments as 'arg' parameters and its
+file argument as the request body.
+
+While the daemon runs, it holds the repo lock and writes its address to the
+file 'api' in the repo. Other ipfs commands on the repo find it there and
+are sent to the daemon instead of running themselves, except for commands
+like 'ipfs shell' that need the local terminal.
+
+A command that may modify the repo can be given an id, with --request-id or
+over the API with the Idempotency-Key header, so that it can be retried
+safely: the daemon answers a command with the id of one it's already run
+with that one's result, rather than running it again.
+
+'ipfs diag health', or /api/v0/diag/health, reports whether each of the
+daemon's services is starting, ready, degraded or failed, and fails unless
+they're all ready or degraded, so it can be polled for readiness.
+
+A daemon for a read-only repo writes nothing to it, so it can serve a
+snapshot or a read-only mount: it neither takes the lock nor writes the
+'api' file, and other commands don't find it.
+"#,
+};
+
+fn run(req: &request::Request) -> Result<(), CommandError> {
+    let repo_dir = req.context.repo_dir.clone();
+    let read_only = req.context.read_only;
+    let _lock = if read_only {
+        None
+    } else {
+        Some(try!(fsrepo::lock_repo(&repo_dir).map_err(CommandError::Client)))
+    };
+
+    let health = Health::new();
+    health.set("api", health::State::Starting, "");
+    if read_only {
+        health.set("repo", health::State::Degraded, "read-only");
+    } else {
+        health.set("repo", health::State::Ready, "");
+    }
+    if req.context.online {
+        health.set("network", health::State::Ready, "");
+    } else {
+        health.set("network", health::State::Degraded, "offline");
+    }
+
+    let addr = req.option_str("api").unwrap_or(api::DEFAULT_API_ADDR);
+    let listening = try!(api::serve(addr,
+                                    repo_dir.clone(),
+                                    read_only,
+                                    req.context.online,
+                                    health.clone()));
+    if !read_only {
+        try!(fsrepo::write_api_file(&repo_dir, addr));
+    }
+    health.set("api", health::State::Ready, "");
+    req.emit(&format!("API server listening on {}", addr), addr);
+    let _ = req.raw_output().flush();
+
+    // dropping the Listening waits for the server threads, which only stop
+    // when the process is killed
+    drop(listening);
+    Ok(())
+}
+
+pub fn make_command() -> Arc<Command> {
+    let api = Opt::new_string(vec!["api"],
+                              "The address to serve the API on (default: 127.0.0.1:5001)");
+
+    CommandBuilder::new("daemon", HELP_TEXT)
+        .option(api)
+        .pre_run(ipfs_commands::require_repo)
+        .run(run)
+}
@@ -0,0 +1,133 @@
+use commands::{self, HelpText, Command, Argument, CommandError};
+use commands::builder::CommandBuilder;
+use commands::request;
+use ipfs_commands;
+use core::IpfsNode;
+use merkledag::Node;
+
+use rust_multihash::Multihash;
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::BTreeMap;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::sync::Arc;
+
+const DagHelpText: HelpText = HelpText {
+    tagline: "Interact with ipfs merkledags",
+    synopsis: "",
+    short_desc: "",
+};
+
+const StatHelpText: HelpText = HelpText {
+    tagline: "Print statistics about the DAGs under the given roots",
+    synopsis: "",
+    short_desc: r#"
+'ipfs dag stat' walks the DAG under each <root> and prints the number of
+distinct blocks and their total size. Given several roots, it also prints
+how many blocks are unique to a single root and how many are shared.
+With --progress, the number of blocks walked so far is shown on stderr.
+"#,
+};
+
+fn run_dag(req: &request::Request) -> Result<(), CommandError> {
+    try!(write!(req.raw_output(), "{}", commands::help::render(req.command, "dag", false))
+             .map_err(|e| format!("Error writing help: {}", e)));
+    Ok(())
+}
+
+pub fn make_command() -> Arc<Command> {
+    CommandBuilder::new("dag", DagHelpText)
+        .subcommand(make_stat_command())
+        .run(run_dag)
+}
+
+struct DagStat {
+    blocks: usize,
+    size: u64,
+}
+
+// Walks the DAG under `root`, visiting each distinct block once. `walked` counts
+// the blocks visited, across all roots, for progress reporting.
+fn walk(req: &request::Request,
+        node: &IpfsNode,
+        root: &Multihash,
+        seen: &mut HashSet<Multihash>,
+        walked: &mut u64)
+        -> Result<DagStat, String> {
+    let mut stat = DagStat {
+        blocks: 0,
+        size: 0,
+    };
+    let mut stack = vec![root.clone()];
+
+    while let Some(hash) = stack.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+
+        let block = try!(node.blockstore.get(&hash));
+        let dag_node = try!(Node::from_reader(&mut block.get_data()));
+        stat.blocks += 1;
+        stat.size += block.get_data().len() as u64;
+
+        for link in dag_node.get_links() {
+            stack.push(link.clone_hash());
+        }
+
+        *walked += 1;
+        req.progress(*walked, None, "blocks walked");
+    }
+    Ok(stat)
+}
+
+fn run_stat(req: &request::Request) -> Result<(), CommandError> {
+    let node = try!(req.context.get_node());
+
+    let mut roots = Vec::new();
+    for root in req.string_arg("root").unwrap() {
+        roots.push(try!(ipfs_commands::stored_hash(node, root)));
+    }
+
+    // how many of the roots each block is reachable from
+    let mut root_counts: HashMap<Multihash, usize> = HashMap::new();
+    let mut walked = 0;
+
+    for root in roots.iter() {
+        let mut seen = HashSet::new();
+        let stat = try!(walk(req, node, root, &mut seen, &mut walked));
+        for hash in seen {
+            *root_counts.entry(hash).or_insert(0) += 1;
+        }
+
+        let mut obj = BTreeMap::new();
+        obj.insert("root".to_string(), format!("{}", root).to_json());
+        obj.insert("blocks".to_string(), stat.blocks.to_json());
+        obj.insert("size".to_string(), stat.size.to_json());
+        req.emit_value(&format!("{}: {} blocks, {} bytes", root, stat.blocks, stat.size),
+                       &format!("{}", stat.size),
+                       Json::Object(obj));
+    }
+
+    if roots.len() > 1 {
+        let shared = root_counts.values().filter(|&&count| count > 1).count();
+        let unique = root_counts.len() - shared;
+        let summary = format!("{} unique blocks, {} shared between roots", unique, shared);
+        let mut obj = BTreeMap::new();
+        obj.insert("unique".to_string(), unique.to_json());
+        obj.insert("shared".to_string(), shared.to_json());
+        req.emit_value(&summary, &summary, Json::Object(obj));
+    }
+    Ok(())
+}
+
+fn make_stat_command() -> Arc<Command> {
+    let arg_root = Argument::new_string("root",
+                                        true,
+                                        true,
+                                        "The root(s) of the DAGs to walk (base58-encoded \
+                                         multihashes)");
+
+    CommandBuilder::new("stat", StatHelpText)
+        .arg(arg_root)
+        .run(run_stat)
+}
@@ -0,0 +1,176 @@
+use commands::{self, HelpText, Command, Argument, CommandError};
+use commands::builder::CommandBuilder;
+use commands::request;
+use crashreport;
+use health;
+use util;
+
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+// how many reports are removed at once
+const MAX_CONCURRENT_REMOVES: usize = 4;
+
+const DiagHelpText: HelpText = HelpText {
+    tagline: "Generate diagnostic reports",
+    synopsis: "",
+    short_desc: "",
+};
+
+const ReportsHelpText: HelpText = HelpText {
+    tagline: "Manage saved crash reports",
+    synopsis: "",
+    short_desc: r#"
+When ipfs crashes, it saves a report containing the version, the command
+being run and a backtrace to the repo. Reports are only submitted anywhere
+if the config sets reports.submit to true and gives a reports.endpoint.
+"#,
+};
+
+const HealthHelpText: HelpText = HelpText {
+    tagline: "Report the health of the daemon's services",
+    synopsis: "",
+    short_desc: r#"
+Lists each service of the daemon serving the repo with its state: starting,
+ready, degraded (running, but without some of what it does) or failed, and
+why, if it isn't ready. Fails unless every service is ready or degraded, so
+it can be polled, or /api/v0/diag/health probed, to tell when the daemon is
+ready.
+"#,
+};
+
+const LsHelpText: HelpText = HelpText {
+    tagline: "List saved crash reports",
+    synopsis: "",
+    short_desc: "",
+};
+
+const RmHelpText: HelpText = HelpText {
+    tagline: "Remove saved crash reports",
+    synopsis: "",
+    short_desc: "",
+};
+
+fn run_diag(req: &request::Request) -> Result<(), CommandError> {
+    try!(write!(req.raw_output(), "{}", commands::help::render(req.command, "diag", false))
+             .map_err(|e| format!("Error writing help: {}", e)));
+    Ok(())
+}
+
+pub fn make_command() -> Arc<Command> {
+    CommandBuilder::new("diag", DiagHelpText)
+        .subcommand(make_health_command())
+        .subcommand(make_reports_command())
+        .run(run_diag)
+}
+
+fn run_health(req: &request::Request) -> Result<(), CommandError> {
+    // only the daemon has services, so it's only set for the commands it runs
+    let health = match req.context.health {
+        Some(ref health) => health,
+        None => {
+            return Err(CommandError::NotFound("No daemon is running for the repo".to_string()))
+        }
+    };
+
+    let mut not_ready = Vec::new();
+    for (name, service) in health.services() {
+        let mut obj = BTreeMap::new();
+        obj.insert("service".to_string(), name.to_json());
+        obj.insert("state".to_string(), service.state.name().to_json());
+        obj.insert("message".to_string(), service.message.to_json());
+
+        req.emit_value(&format!("{}\t{}\t{}", name, service.state.name(), service.message),
+                       &name,
+                       Json::Object(obj));
+        match service.state {
+            health::State::Ready | health::State::Degraded => {}
+            health::State::Starting | health::State::Failed => not_ready.push(name),
+        }
+    }
+
+    if !not_ready.is_empty() {
+        return Err(CommandError::Internal(format!("Not ready: {}", not_ready.join(", "))));
+    }
+    Ok(())
+}
+
+fn make_health_command() -> Arc<Command> {
+    CommandBuilder::new("health", HealthHelpText).run(run_health)
+}
+
+fn run_reports(req: &request::Request) -> Result<(), CommandError> {
+    try!(write!(req.raw_output(), "{}", commands::help::render(req.command, "diag reports", false))
+             .map_err(|e| format!("Error writing help: {}", e)));
+    Ok(())
+}
+
+fn make_reports_command() -> Arc<Command> {
+    CommandBuilder::new("reports", ReportsHelpText)
+        .subcommand(make_ls_command())
+        .subcommand(make_rm_command())
+        .run(run_reports)
+}
+
+fn run_ls(req: &request::Request) -> Result<(), CommandError> {
+    for (id, report) in try!(crashreport::list(req.context.repo_dir.clone())) {
+        let mut obj = BTreeMap::new();
+        obj.insert("id".to_string(), id.to_json());
+        obj.insert("version".to_string(), report.version.to_json());
+        obj.insert("command".to_string(), report.command.to_json());
+        obj.insert("location".to_string(), report.location.to_json());
+        obj.insert("time".to_string(), report.time.to_json());
+
+        req.emit_value(&format!("{}\t{}\t{}\t{}",
+                                id,
+                                report.version,
+                                report.command,
+                                report.location),
+                       &id,
+                       Json::Object(obj));
+    }
+    Ok(())
+}
+
+fn make_ls_command() -> Arc<Command> {
+    CommandBuilder::new("ls", LsHelpText).run(run_ls)
+}
+
+fn run_rm(req: &request::Request) -> Result<(), CommandError> {
+    let ids = req.string_arg("id").unwrap();
+
+    let repo_dir = req.context.repo_dir.clone();
+
+    // keep going past reports that can't be removed, and report them at the end
+    let mut failed = 0;
+    util::run_concurrently(ids.to_vec(),
+                           MAX_CONCURRENT_REMOVES,
+                           move |id| crashreport::remove(repo_dir.clone(), &id),
+                           |i, result| {
+                               match result {
+                                   Ok(()) => req.emit(&format!("removed {}", ids[i]), &ids[i]),
+                                   Err(e) => {
+                                       failed += 1;
+                                       let _ = writeln!(io::stderr(), "{}", e);
+                                   }
+                               }
+                           });
+
+    if failed > 0 {
+        return Err(CommandError::Internal(format!("{} of {} reports could not be removed",
+                                                  failed,
+                                                  ids.len())));
+    }
+    Ok(())
+}
+
+fn make_rm_command() -> Arc<Command> {
+    let arg_id = Argument::new_string("id", true, true, "The id(s) of the reports to remove");
+
+    CommandBuilder::new("rm", RmHelpText)
+        .arg(arg_id)
+        .mutating()
+        .run(run_rm)
+}
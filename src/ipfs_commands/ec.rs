@@ -0,0 +1,360 @@
+use commands::{self, HelpText, Command, Argument, Opt, OptName, CommandError};
+use commands::builder::CommandBuilder;
+use commands::request;
+use blockstore::Blockstore;
+use erasure;
+use hash;
+use ipfs_commands;
+use merkledag::{Link, Node};
+
+use rust_multihash::Multihash;
+use rustc_serialize::json;
+use std::collections::HashSet;
+use std::io::Write;
+use std::sync::Arc;
+
+const EcHelpText: HelpText = HelpText {
+    tagline: "Erasure-code DAGs into shards (experimental)",
+    synopsis: "",
+    short_desc: r#"
+'ipfs ec encode' packs every block of the DAG under <root> into k data
+shards plus m parity shards, such that the DAG can be rebuilt from any k
+of them, and stores the shards along with a manifest object linking to
+them. 'ipfs ec decode' rebuilds the DAG from the manifest and whichever
+shards are still in the repo.
+"#,
+};
+
+const EncodeHelpText: HelpText = HelpText {
+    tagline: "Split a DAG into erasure-coded shards",
+    synopsis: "",
+    short_desc: "Prints the hash of each shard, and last the hash of the manifest.",
+};
+
+const DecodeHelpText: HelpText = HelpText {
+    tagline: "Rebuild a DAG from its erasure-coded shards",
+    synopsis: "",
+    short_desc: r#"
+Reads the manifest written by 'ipfs ec encode', reconstructs the DAG from
+the shards that are present and writes back any of its blocks that are
+missing. Prints the hash of the DAG's root.
+"#,
+};
+
+const DEFAULT_DATA_SHARDS: i32 = 4;
+const DEFAULT_PARITY_SHARDS: i32 = 2;
+
+// Stored as the data of the manifest object, whose links are the shards in order
+#[derive(RustcEncodable, RustcDecodable)]
+struct Manifest {
+    root: String,
+    data_shards: usize,
+    parity_shards: usize,
+    size: u64, // length of the packed blocks, before padding to whole shards
+}
+
+fn run_ec(req: &request::Request) -> Result<(), CommandError> {
+    try!(write!(req.raw_output(), "{}", commands::help::render(req.command, "ec", false))
+             .map_err(|e| format!("Error writing help: {}", e)));
+    Ok(())
+}
+
+pub fn make_command() -> Arc<Command> {
+    CommandBuilder::new("ec", EcHelpText)
+        .subcommand(make_encode_command())
+        .subcommand(make_decode_command())
+        .run(run_ec)
+}
+
+fn push_u32(buf: &mut Vec<u8>, n: u32) {
+    buf.extend_from_slice(&[(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]);
+}
+
+// Packs each block under `root` as <hash length><hash><data length><data>,
+// with big-endian u32 lengths
+fn pack_dag(blockstore: &Blockstore, root: &Multihash) -> Result<Vec<u8>, String> {
+    let mut packed = Vec::new();
+    let mut seen = HashSet::new();
+    let mut stack = vec![root.clone()];
+
+    while let Some(hash) = stack.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+
+        let block = try!(blockstore.get(&hash));
+        let dag_node = try!(Node::from_reader(&mut block.get_data()));
+        for link in dag_node.get_links() {
+            stack.push(link.clone_hash());
+        }
+
+        push_u32(&mut packed, hash.len() as u32);
+        packed.extend_from_slice(&hash);
+        push_u32(&mut packed, block.get_data().len() as u32);
+        packed.extend_from_slice(block.get_data());
+    }
+    Ok(packed)
+}
+
+fn take<'a>(packed: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+    if packed.len() < len {
+        return Err("Reconstructed shard data is truncated".to_string());
+    }
+    let (head, rest) = packed.split_at(len);
+    *packed = rest;
+    Ok(head)
+}
+
+fn take_u32(packed: &mut &[u8]) -> Result<usize, String> {
+    let b = try!(take(packed, 4));
+    Ok(((b[0] as usize) << 24) | ((b[1] as usize) << 16) | ((b[2] as usize) << 8) |
+       b[3] as usize)
+}
+
+fn unpack_dag(mut packed: &[u8]) -> Result<Vec<(Multihash, Vec<u8>)>, String> {
+    let mut blocks = Vec::new();
+    while !packed.is_empty() {
+        let hash_len = try!(take_u32(&mut packed));
+        if hash_len == 0 {
+            return Err("Reconstructed shard data contains an empty hash".to_string());
+        }
+        let hash = Multihash::from_vec(try!(take(&mut packed, hash_len)).to_vec());
+        let data_len = try!(take_u32(&mut packed));
+        let data = try!(take(&mut packed, data_len)).to_vec();
+        blocks.push((hash, data));
+    }
+    Ok(blocks)
+}
+
+fn shard_count(req: &request::Request, name: OptName, default: i32) -> usize {
+    req.option_int(name).unwrap_or(default) as usize
+}
+
+fn run_encode(req: &request::Request) -> Result<(), CommandError> {
+    let node = try!(req.context.get_node());
+    let root = try!(ipfs_commands::stored_hash(node, &req.string_arg("root").unwrap()[0]));
+    let k = shard_count(req, "data-shards", DEFAULT_DATA_SHARDS);
+    let m = shard_count(req, "parity-shards", DEFAULT_PARITY_SHARDS);
+
+    let packed = try!(pack_dag(&node.blockstore, &root));
+    let shards = try!(erasure::encode(&packed, k, m));
+
+    let mut links = Vec::with_capacity(shards.len());
+    for (i, shard) in shards.into_iter().enumerate() {
+        let size = shard.len() as u64;
+        let hash = try!(node.dagservice.add(&Node::from_data(shard)));
+        req.emit(&format!("shard {} {}", i, hash), &format!("{}", hash));
+        links.push(Link::new(format!("{}", i), hash, size));
+    }
+
+    let manifest = Manifest {
+        root: format!("{}", root),
+        data_shards: k,
+        parity_shards: m,
+        size: packed.len() as u64,
+    };
+    let encoded = try!(json::encode(&manifest)
+                           .map_err(|e| format!("Error encoding shard manifest: {}", e)));
+    let hash = try!(node.dagservice.add(&Node::new(encoded.into_bytes(), links)));
+    req.emit(&format!("manifest {}", hash), &format!("{}", hash));
+    Ok(())
+}
+
+fn validate_encode(req: &request::Request) -> Result<(), CommandError> {
+    for name in &["data-shards", "parity-shards"] {
+        if req.option_int(name).map_or(false, |n| n < 0) {
+            return Err(CommandError::Client(format!("--{} can't be negative", name)));
+        }
+    }
+    let k = shard_count(req, "data-shards", DEFAULT_DATA_SHARDS);
+    let m = shard_count(req, "parity-shards", DEFAULT_PARITY_SHARDS);
+    if k == 0 {
+        return Err(CommandError::Client("--data-shards must be at least 1".to_string()));
+    }
+    if k + m > erasure::MAX_SHARDS {
+        return Err(CommandError::Client(format!("--data-shards and --parity-shards can add up \
+                                                 to at most {}",
+                                                erasure::MAX_SHARDS)));
+    }
+    Ok(())
+}
+
+fn make_encode_command() -> Arc<Command> {
+    let arg_root = Argument::new_string("root",
+                                        true,
+                                        false,
+                                        "The root of the DAG to encode (a base58-encoded \
+                                         multihash)");
+
+    let data_shards = Opt::new_int(vec!["data-shards"], "How many shards the DAG is split into")
+                          .with_default(request::Opt::Int(DEFAULT_DATA_SHARDS));
+
+    let parity_shards = Opt::new_int(vec!["parity-shards"], "How many of the shards may be lost")
+                            .with_default(request::Opt::Int(DEFAULT_PARITY_SHARDS));
+
+    CommandBuilder::new("encode", EncodeHelpText)
+        .option(data_shards)
+        .option(parity_shards)
+        .arg(arg_root)
+        .mutating()
+        .validate(validate_encode)
+        .run(run_encode)
+}
+
+fn run_decode(req: &request::Request) -> Result<(), CommandError> {
+    let node = try!(req.context.get_node());
+    let manifest_hash = try!(ipfs_commands::stored_hash(node,
+                                                        &req.string_arg("manifest").unwrap()[0]));
+
+    let manifest_node = try!(node.dagservice.get(&manifest_hash));
+    let manifest_json = try!(String::from_utf8(manifest_node.get_data().to_vec())
+                                 .map_err(|_| "Shard manifest is not valid UTF-8".to_string()));
+    let manifest: Manifest = try!(json::decode(&manifest_json)
+                                      .map_err(|e| format!("Error decoding shard manifest: {}",
+                                                           e)));
+
+    let links = manifest_node.get_links();
+    if links.len() != manifest.data_shards + manifest.parity_shards {
+        return Err(CommandError::Internal(format!("Manifest lists {} shards, expected {}",
+                                                  links.len(),
+                                                  manifest.data_shards +
+                                                  manifest.parity_shards)));
+    }
+
+    let mut shards = Vec::with_capacity(links.len());
+    for link in links {
+        let hash = link.clone_hash();
+        if try!(node.blockstore.has(&hash)) {
+            let shard = try!(node.dagservice.get(&hash));
+            shards.push(Some(shard.get_data().to_vec()));
+        } else {
+            shards.push(None);
+        }
+    }
+
+    let mut packed = try!(erasure::reconstruct(&shards, manifest.data_shards)).concat();
+    if (packed.len() as u64) < manifest.size {
+        return Err(CommandError::Internal("Reconstructed shard data is truncated".to_string()));
+    }
+    packed.truncate(manifest.size as usize);
+
+    let mut restored = 0;
+    for (hash, data) in try!(unpack_dag(&packed)) {
+        let hash_fn = try!(hash::by_code(hash[0]));
+        if hash_fn.multihash(&data) != hash {
+            return Err(CommandError::Internal(format!("Reconstructed block {} does not match \
+                                                       its hash",
+                                                      hash)));
+        }
+        if !try!(node.blockstore.has(&hash)) {
+            try!(node.blockstore.put(&hash, &data));
+            restored += 1;
+        }
+    }
+
+    req.emit(&format!("rebuilt {} ({} blocks restored)", manifest.root, restored),
+             &manifest.root);
+    Ok(())
+}
+
+fn make_decode_command() -> Arc<Command> {
+    let arg_manifest = Argument::new_string("manifest",
+                                            true,
+                                            false,
+                                            "The hash of the manifest written by 'ipfs ec \
+                                             encode'");
+
+    CommandBuilder::new("decode", DecodeHelpText)
+        .arg(arg_manifest)
+        .mutating()
+        .run(run_decode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pack_dag, unpack_dag};
+    use blockstore::Blockstore;
+    use erasure;
+    use merkledag::{Link, Node};
+
+    use rust_multihash::Multihash;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn blockstore_path() -> PathBuf {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+        let path = env::temp_dir().join(format!("ec-test-{}", nanos));
+        fs::create_dir(&path).unwrap();
+        path
+    }
+
+    fn put(bs: &Blockstore, node: &Node) -> Multihash {
+        let mut encoded = Vec::new();
+        node.encode_to_writer(&mut encoded).unwrap();
+        let hash = node.multihash();
+        bs.put(&hash, &encoded[..]).unwrap();
+        hash
+    }
+
+    // A root with two leaves, the blocks of which are returned root first
+    fn dag(bs: &Blockstore) -> Vec<(Multihash, Vec<u8>)> {
+        let leaves = vec![Node::from_data(b"first leaf".to_vec()),
+                          Node::from_data(b"second leaf".to_vec())];
+        let links = leaves.iter()
+                          .enumerate()
+                          .map(|(i, leaf)| Link::new(format!("{}", i), put(bs, leaf), 0))
+                          .collect();
+        let root = put(bs, &Node::new(b"root".to_vec(), links));
+        let mut blocks = vec![root];
+        blocks.extend(leaves.iter().map(|leaf| leaf.multihash()));
+        blocks.into_iter()
+              .map(|hash| {
+                  let data = bs.get(&hash).unwrap().get_data().to_vec();
+                  (hash, data)
+              })
+              .collect()
+    }
+
+    #[test]
+    fn dags_survive_losing_up_to_the_parity_shards() {
+        let path = blockstore_path();
+        let bs = Blockstore::new(path.clone());
+        let mut blocks = dag(&bs);
+        let packed = pack_dag(&bs, &blocks[0].0).unwrap();
+        let shards = erasure::encode(&packed, 3, 2).unwrap();
+
+        let mut shards: Vec<Option<Vec<u8>>> = shards.into_iter().map(Some).collect();
+        shards[0] = None;
+        shards[2] = None;
+        let mut rebuilt = erasure::reconstruct(&shards, 3).unwrap().concat();
+        rebuilt.truncate(packed.len());
+        let mut unpacked = unpack_dag(&rebuilt).unwrap();
+
+        // the order the blocks are packed in doesn't matter
+        blocks.sort_by(|a, b| a.0[..].cmp(&b.0[..]));
+        unpacked.sort_by(|a, b| a.0[..].cmp(&b.0[..]));
+        assert_eq!(unpacked, blocks);
+
+        shards[4] = None;
+        assert!(erasure::reconstruct(&shards, 3).is_err());
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn truncated_packs_are_refused() {
+        let path = blockstore_path();
+        let bs = Blockstore::new(path.clone());
+        let blocks = dag(&bs);
+        let packed = pack_dag(&bs, &blocks[0].0).unwrap();
+
+        assert_eq!(unpack_dag(&packed).unwrap().len(), 3);
+        for len in vec![1, 4, 10, packed.len() - 1] {
+            assert_eq!(unpack_dag(&packed[..len]),
+                       Err("Reconstructed shard data is truncated".to_string()));
+        }
+        assert!(unpack_dag(&[0, 0, 0, 0]).is_err());
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
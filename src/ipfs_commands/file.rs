@@ -1,9 +1,15 @@
-use commands::{HelpText, Command, Argument};
+use commands::{HelpText, Command, Argument, Opt, CommandError};
+use commands::builder::CommandBuilder;
 use commands::request;
+use commands::response::Encoding;
+use ipfs_commands;
+use template::Template;
 use unixfs;
 
 use rust_multihash::Multihash;
-use std::collections::HashMap;
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
 use std::sync::Arc;
 
 const FileHelpText: HelpText = HelpText {
@@ -26,21 +32,21 @@ contents.
 The JSON output contains size information.  For files, the child size
 is the total size of the file contents.  For directories, the child
 size is the IPFS link size.
+
+With --cumulative, the size of each entry is instead the total size of
+the blocks in its DAG, as recorded when they were added, and it is shown
+next to each name.
 "#,
 };
 
-fn run_file(req: &request::Request) -> Result<(), String> {
+fn run_file(req: &request::Request) -> Result<(), CommandError> {
     unimplemented!()
 }
 
-ipfs_command!(FileCommand, run_file);
-
-pub fn make_command() -> Box<Command> {
-    Box::new(FileCommand::new("file",
-                              vec![],
-                              vec![],
-                              FileHelpText,
-                              vec![make_ls_command()]))
+pub fn make_command() -> Arc<Command> {
+    CommandBuilder::new("file", FileHelpText)
+        .subcommand(make_ls_command())
+        .run(run_file)
 }
 
 #[derive(Debug)]
@@ -49,6 +55,7 @@ struct LsLink {
     hash: Multihash,
     size: u64,
     ty: unixfs::pb::Data_DataType,
+    mime: Option<&'static str>,
 }
 
 #[derive(Debug)]
@@ -59,15 +66,50 @@ struct LsObject {
     pub links: Vec<LsLink>,
 }
 
+impl ToJson for LsLink {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert("name".to_string(), self.name.to_json());
+        obj.insert("hash".to_string(), format!("{}", self.hash).to_json());
+        obj.insert("size".to_string(), self.size.to_json());
+        obj.insert("type".to_string(), format!("{:?}", self.ty).to_json());
+        if let Some(mime) = self.mime {
+            obj.insert("mime".to_string(), mime.to_json());
+        }
+        Json::Object(obj)
+    }
+}
+
+impl ToJson for LsObject {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert("hash".to_string(), format!("{}", self.hash).to_json());
+        obj.insert("size".to_string(), self.size.to_json());
+        obj.insert("type".to_string(), format!("{:?}", self.ty).to_json());
+        obj.insert("links".to_string(), self.links.to_json());
+        Json::Object(obj)
+    }
+}
+
+const LS_FORMAT_FIELDS: &'static [&'static str] = &["hash", "name", "size", "type", "mime"];
+
 // TODO: this is only going to accept hashes for now. Need to implement
 // path resolver so it can do paths.
-fn run_ls(req: &request::Request) -> Result<(), String> {
+fn run_ls(req: &request::Request) -> Result<(), CommandError> {
     let node = try!(req.context.get_node());
 
+    let template = match req.option_str("format") {
+        Some(s) => Some(try!(Template::parse(s, LS_FORMAT_FIELDS).map_err(CommandError::Client))),
+        None => None,
+    };
+    let with_mime = req.option_bool("with-mime") ||
+                    template.as_ref().map_or(false, |t| t.uses("mime"));
+    let cumulative = req.option_bool("cumulative");
+
     let mut objects: HashMap<Multihash, LsObject> = HashMap::new();
 
     for path in req.string_arg("ipfs-path").unwrap() {
-        let mh = try!(Multihash::from_base58_str(&path));
+        let mh = try!(ipfs_commands::stored_hash(node, &path));
         // retrieve merkledag node for the path (multihash, at this point)
         let mut dag_node = try!(node.dagservice.get(&mh));
         let unixfs_data = try!(unixfs::from_reader(&mut dag_node.get_data()));
@@ -92,15 +134,28 @@ fn run_ls(req: &request::Request) -> Result<(), String> {
                     let ty = link_node_data.get_Type();
 
                     let size = match ty {
+                        _ if cumulative => {
+                            try!(node.dagservice.cumulative_size(&link.clone_hash(),
+                                                                 !req.context.read_only))
+                        }
                         unixfs::pb::Data_DataType::File => link_node_data.get_filesize(),
                         _ => link.get_target_size(),
                     };
 
+                    let mime = if !with_mime {
+                        None
+                    } else if ty == unixfs::pb::Data_DataType::Directory {
+                        Some("inode/directory")
+                    } else {
+                        Some(unixfs::mime::sniff(link_node_data.get_Data()))
+                    };
+
                     v.push(LsLink {
                         name: link.clone_name(),
                         hash: link.clone_hash(),
                         size: size,
                         ty: ty,
+                        mime: mime,
                     });
                 }
 
@@ -119,25 +174,80 @@ fn run_ls(req: &request::Request) -> Result<(), String> {
         objects.insert(dag_node.multihash(), ls_obj);
     }
 
+    if let Some(template) = template {
+        for obj in objects.values() {
+            for link in obj.links.iter() {
+                let mut values = HashMap::new();
+                values.insert("hash", format!("{}", link.hash));
+                values.insert("name", link.name.clone());
+                values.insert("size", format!("{}", link.size));
+                values.insert("type", format!("{:?}", link.ty));
+                values.insert("mime", link.mime.unwrap_or("").to_string());
+                req.emit(&template.render(&values), &format!("{}", link.hash));
+            }
+        }
+        return Ok(());
+    }
+
+    if req.verbosity() != request::Verbosity::Normal {
+        for obj in objects.values() {
+            for link in obj.links.iter() {
+                req.emit(&link.name, &format!("{}", link.hash));
+            }
+        }
+        return Ok(());
+    }
+
+    if try!(req.encoding()) == Encoding::Json {
+        for (hash, obj) in &objects {
+            let hash = format!("{}", hash);
+            req.emit_value(&hash, &hash, obj.to_json());
+        }
+        return Ok(());
+    }
+
+    let mut out = req.raw_output();
     for (hash, obj) in &objects {
-        println!("{}:", hash);
+        let mut listing = format!("{}:\n", hash);
         for link in obj.links.iter() {
-            println!("{}", link.name);
+            listing.push_str(&link.name);
+            if cumulative {
+                listing.push_str(&format!("\t{}", link.size));
+            }
+            if let Some(mime) = link.mime {
+                listing.push_str(&format!("\t{}", mime));
+            }
+            listing.push('\n');
         }
-        println!("");
+        try!(writeln!(out, "{}", listing).map_err(|e| format!("Error writing listing: {}", e)));
     }
     Ok(())
 }
 
-ipfs_command!(LsCommand, run_ls);
-
-fn make_ls_command() -> Box<Command> {
+fn make_ls_command() -> Arc<Command> {
     let arg_path = Argument::new_string("ipfs-path",
                                         true,
                                         true,
                                         "The path(s) to the IPFS object(s) to list \
                                          links from");
 
-
-    Box::new(LsCommand::new("ls", vec![], vec![arg_path], FileHelpText, vec![]))
+    let format = Opt::new_string(vec!["format"],
+                                 "Print each link using a template such as \
+                                  \"<hash> <size> <name>\". Fields: <hash>, <name>, \
+                                  <size>, <type>, <mime>");
+
+    let with_mime = Opt::new_bool(vec!["with-mime"],
+                                  "Show the content type of each entry, guessed from \
+                                   its first bytes");
+
+    let cumulative = Opt::new_bool(vec!["cumulative"],
+                                   "Show the total size of each entry's DAG instead of its \
+                                    declared size");
+
+    CommandBuilder::new("ls", LsHelpText)
+        .option(format)
+        .option(with_mime)
+        .option(cumulative)
+        .arg(arg_path)
+        .run(run_ls)
 }
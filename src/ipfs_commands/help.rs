@@ -0,0 +1,92 @@
+use commands::{self, HelpText, Command, Argument, Opt, CommandError};
+use commands::builder::CommandBuilder;
+use commands::request;
+use ipfs_commands::root;
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+const HELP_TEXT: HelpText = HelpText {
+    tagline: "Show the help page of a command",
+    synopsis: "",
+    short_desc: r#"
+'ipfs help <command>...' prints the same page as 'ipfs <command>... --help',
+e.g. 'ipfs help repo compress'.
+
+With --man, the page is printed as a man page instead. With --man-dir, the
+man pages of the command and of every command under it are written to the
+given directory, one file per command, e.g. ipfs-repo-compress.1:
+
+    ipfs help --man-dir /usr/local/share/man/man1
+"#,
+};
+
+fn run(req: &request::Request) -> Result<(), CommandError> {
+    let root = root::make_command();
+    let names = req.string_arg("command").unwrap_or(&[]);
+
+    // the path is made of the commands' own names, in case some were aliases
+    let mut cmd: &Command = &*root;
+    let mut path_names = Vec::new();
+    for name in names {
+        cmd = match cmd.get_subcommand(name) {
+            Some(subcmd) => subcmd,
+            None => {
+                return Err(CommandError::NotFound(format!("Unknown command: ipfs {}",
+                                                          names.join(" "))))
+            }
+        };
+        path_names.push(cmd.get_name());
+    }
+
+    let path = path_names.join(" ");
+    if let Some(dir) = req.option_str("man-dir") {
+        return write_man_pages(req, cmd, &path, Path::new(dir));
+    }
+
+    let page = if req.option_bool("man") {
+        commands::help::render_man(cmd, &path)
+    } else {
+        commands::help::render(cmd, &path, true)
+    };
+    try!(write!(req.raw_output(), "{}", page).map_err(|e| format!("Error writing help: {}", e)));
+    Ok(())
+}
+
+fn write_man_pages(req: &request::Request,
+                   cmd: &Command,
+                   path: &str,
+                   dir: &Path)
+                   -> Result<(), CommandError> {
+    try!(fs::create_dir_all(dir).map_err(|e| format!("Error creating {:?}: {}", dir, e)));
+    for (path, cmd) in commands::help::all_commands(cmd, path) {
+        let file_path = dir.join(format!("{}.1", commands::help::man_name(&path)));
+        try!(File::create(&file_path)
+                 .and_then(|mut f| f.write_all(commands::help::render_man(cmd, &path).as_bytes()))
+                 .map_err(|e| format!("Error writing {:?}: {}", file_path, e)));
+        let shown = file_path.to_string_lossy().into_owned();
+        req.emit(&shown, &shown);
+    }
+    Ok(())
+}
+
+pub fn make_command() -> Arc<Command> {
+    let arg_command = Argument::new_string("command",
+                                           false,
+                                           true,
+                                           "The command to show help for, e.g. repo compress");
+
+    let man = Opt::new_bool(vec!["man"], "Print the page in man page format");
+
+    let man_dir = Opt::new_string(vec!["man-dir"],
+                                  "Write the man pages of the command and its subcommands to \
+                                   this directory");
+
+    CommandBuilder::new("help", HELP_TEXT)
+        .option(man)
+        .option(man_dir)
+        .arg(arg_command)
+        .run(run)
+}
@@ -1,4 +1,5 @@
-use commands::{self, HelpText, Command};
+use commands::{self, HelpText, Command, CommandError};
+use commands::builder::CommandBuilder;
 use commands::request;
 use config;
 use fsrepo;
@@ -7,6 +8,7 @@ use util;
 use std::fs::{self, File};
 use std::io;
 use std::path;
+use std::sync::Arc;
 
 const InitHelpText: HelpText = HelpText {
     tagline: "Initializes IPFS config file",
@@ -14,17 +16,21 @@ const InitHelpText: HelpText = HelpText {
     short_desc: "Initializes IPFS configuration files and generates a new keypair.",
 };
 
-fn run(req: &request::Request) -> Result<(), String> {
+fn run(req: &request::Request) -> Result<(), CommandError> {
     let repo_dir = req.context.repo_dir.clone();
-    if try!(fsrepo::is_locked(repo_dir.clone())) {
-        return Err("Another process has locked the repo. Unable to continue."
-                       .to_string());
+    if let Some(outer) = try!(fsrepo::enclosing_repo(&repo_dir)) {
+        return Err(CommandError::Client(format!("Refusing to create a repo at {:?}, inside \
+                                                 the repo at {:?}.",
+                                                repo_dir,
+                                                outer)));
     }
 
     try!(check_and_prepare_repo_dir(repo_dir.clone()));
+    // held until the repo is set up, so two inits can't interleave
+    let _lock = try!(fsrepo::lock_repo(&repo_dir).map_err(CommandError::Client));
 
     if try!(fsrepo::is_initialized(repo_dir.clone())) {
-        if req.option("f").is_some() {
+        if req.option_bool("f") {
             try!(fsrepo::remove(&repo_dir));
             try!(util::ensure_dir_writable(&repo_dir).map_err(|e| {
                 format!("Error ensuring repo directory is writable after forced \
@@ -32,27 +38,29 @@ fn run(req: &request::Request) -> Result<(), String> {
                         e)
             }));
         } else {
-            return Err("IPFS repo already exists.\nReinitializing would overwrite your \
-                        keys.\n(Use -f to force reinitialization.)"
-                           .to_string());
+            return Err(CommandError::Client("IPFS repo already exists.\nReinitializing would \
+                                             overwrite your keys.\n(Use -f to force \
+                                             reinitialization.)"
+                                                .to_string()));
         }
     }
 
     let config = config::init(config::DEFAULT_KEYPAIR_NUM_BITS);
 
-    fsrepo::init(repo_dir, &config)
+    Ok(try!(fsrepo::init(repo_dir, &config)))
 }
 
-ipfs_command!(InitCommand, run);
-
-pub fn make_command() -> Box<Command> {
+pub fn make_command() -> Arc<Command> {
     let force = commands::Opt::new_bool(
         vec!["f", "force"],
         "Overwrite existing configuration (if it exists)"
     );
 
 
-    Box::new(InitCommand::new("init", vec![force], vec![], InitHelpText, vec![]))
+    CommandBuilder::new("init", InitHelpText)
+        .option(force)
+        .mutating()
+        .run(run)
 }
 
 
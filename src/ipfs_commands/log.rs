@@ -0,0 +1,55 @@
+use audit;
+use commands::{self, HelpText, Command, CommandError};
+use commands::builder::CommandBuilder;
+use commands::request;
+
+use rustc_serialize::json::ToJson;
+
+use std::io::Write;
+use std::sync::Arc;
+
+const LogHelpText: HelpText = HelpText {
+    tagline: "Interact with the repo's logs",
+    synopsis: "",
+    short_desc: "",
+};
+
+const AuditHelpText: HelpText = HelpText {
+    tagline: "List the commands that modified the repo",
+    synopsis: "",
+    short_desc: r#"
+Every command that may modify the repo is recorded in an append-only audit
+log in the repo, with the time it was run, who ran it, its options and
+whether it succeeded. 'ipfs log audit' prints the log, oldest first.
+"#,
+};
+
+fn run_log(req: &request::Request) -> Result<(), CommandError> {
+    try!(write!(req.raw_output(), "{}", commands::help::render(req.command, "log", false))
+             .map_err(|e| format!("Error writing help: {}", e)));
+    Ok(())
+}
+
+pub fn make_command() -> Arc<Command> {
+    CommandBuilder::new("log", LogHelpText)
+        .subcommand(make_audit_command())
+        .run(run_log)
+}
+
+fn run_audit(req: &request::Request) -> Result<(), CommandError> {
+    for entry in try!(audit::read(req.context.repo_dir.clone())) {
+        req.emit_value(&format!("{}\t{}\t{}\t{}\t{}",
+                                entry.time,
+                                entry.actor,
+                                entry.command,
+                                entry.options.join(" "),
+                                entry.result),
+                       &entry.command,
+                       entry.to_json());
+    }
+    Ok(())
+}
+
+fn make_audit_command() -> Arc<Command> {
+    CommandBuilder::new("audit", AuditHelpText).run(run_audit)
+}
@@ -1,107 +1,48 @@
-use commands::{CommandName, Opt, Argument, HelpText, Command, CommandOptions};
-
-use std::collections::HashMap;
-use std::slice;
-
-pub struct CommandInfo {
-    name: CommandName,
-    options: Vec<Opt>,
-    arguments: Vec<Argument>,
-    help_text: HelpText,
-    subcommands: HashMap<CommandName, Box<Command>>,
-}
-
-impl CommandInfo {
-    fn new(name: CommandName,
-           options: Vec<Opt>,
-           arguments: Vec<Argument>,
-           help_text: HelpText,
-           subcommands: Vec<Box<Command>>)
-           -> Self {
-                CommandInfo {
-                    name: name,
-                    options: options,
-                    arguments: arguments,
-                    help_text: help_text,
-                    subcommands: subcommands.into_iter()
-                                            .map(|cmd| (cmd.get_name(), cmd))
-                                            .collect(),
-                }
-    }
-
-    fn get_name(&self) -> CommandName { self.name }
-
-    fn get_help_text(&self) -> &HelpText { &self.help_text }
-
-    fn get_options(&self) -> CommandOptions {
-        CommandOptions::new(self.options.iter())
-    }
-
-    fn get_arguments(&self) -> slice::Iter<Argument> {
-        self.arguments.iter()
-    }
-
-    fn num_args(&self) -> usize { self.arguments.len() }
-
-    fn get_subcommand(&self, subcmd: &str) -> Option<&Command> {
-        self.subcommands.get(subcmd).map(|cmd| &**cmd)
+use commands::CommandError;
+use commands::request;
+use core::IpfsNode;
+use hash;
+
+use rust_multihash::Multihash;
+
+// Parses a hash given on the command line and checks that the object it names
+// is in the repo, so that a missing object is reported as not found rather
+// than as a failure to read it
+pub fn stored_hash(node: &IpfsNode, s: &str) -> Result<Multihash, CommandError> {
+    let hash = try!(hash::parse_multihash(s).map_err(CommandError::Client));
+    if !try!(node.blockstore.has(&hash)) {
+        return Err(CommandError::NotFound(format!("Object {} is not in the repo", hash)));
     }
+    Ok(hash)
 }
 
-// Defines a type that satisfies the `commands::Command` trait
-macro_rules! ipfs_command {
-    ($name:ident, $f:ident) => {
-        struct $name {
-            info: ::ipfs_commands::CommandInfo,
-        }
-
-        impl $name {
-            fn new(name: ::commands::CommandName,
-                   options: Vec<::commands::Opt>,
-                   arguments: Vec<::commands::Argument>,
-                   help_text: ::commands::HelpText,
-                   subcommands: Vec<Box<::commands::Command>>)
-                   -> Self {
-                        $name {
-                            info: ::ipfs_commands::CommandInfo::new(name, options, arguments, help_text, subcommands)
-                        }
-                    }
-                }
-
-        impl Command for $name {
-            fn get_name(&self) -> ::commands::CommandName {
-                self.info.get_name()
-            }
-            fn get_help_text(&self) -> &::commands::HelpText {
-                self.info.get_help_text()
-            }
-
-            fn get_options(&self) -> ::commands::CommandOptions {
-                self.info.get_options()
-            }
-
-            fn get_arguments(&self) -> ::std::slice::Iter<::commands::Argument> {
-                self.info.get_arguments()
-            }
-
-            fn num_args(&self) -> usize {
-                self.info.num_args()
-            }
-
-            fn get_subcommand(&self, subcmd: &str) -> Option<&::commands::Command> {
-                self.info.get_subcommand(subcmd)
-            }
-
-
-            fn run(&self, req: &::commands::request::Request) -> Result<(), String> {
-                $f(req)
-            }
-        }
-    }
+// A pre_run hook for commands that only need the repo to exist, rather than
+// anything from the node
+pub fn require_repo(req: &request::Request) -> Result<(), CommandError> {
+    req.context.get_node().map(|_| ())
 }
 
 pub mod add;
+pub mod batch;
+pub mod block;
+pub mod cat;
+pub mod completion;
+pub mod config;
+pub mod daemon;
+pub mod dag;
+pub mod diag;
+pub mod ec;
 pub mod file;
+pub mod help;
 pub mod init;
+pub mod log;
 pub mod object;
+pub mod provenance;
+pub mod refs;
+pub mod repo;
 pub mod root;
+pub mod shell;
+pub mod stats;
+
+#[cfg(test)]
+mod tests;
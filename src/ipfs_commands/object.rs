@@ -1,7 +1,11 @@
-use commands::{self, HelpText, Command};
+use commands::{self, HelpText, Command, CommandError};
+use commands::builder::CommandBuilder;
 use commands::request;
+use ipfs_commands;
 
 use rust_multihash::Multihash;
+use std::io::Write;
+use std::sync::Arc;
 
 const ObjectHelpText: HelpText = HelpText {
     tagline: "Interact with ipfs objects",
@@ -22,18 +26,14 @@ is a base58 encoded multihash.
 "#,
 };
 
-fn run_object(req: &request::Request) -> Result<(), String> {
+fn run_object(req: &request::Request) -> Result<(), CommandError> {
     unimplemented!()
 }
 
-ipfs_command!(ObjectCommand, run_object);
-
-pub fn make_command() -> Box<Command> {
-    Box::new(ObjectCommand::new("object",
-                                vec![],
-                                vec![],
-                                ObjectHelpText,
-                                vec![make_get_command()]))
+pub fn make_command() -> Arc<Command> {
+    CommandBuilder::new("object", ObjectHelpText)
+        .subcommand(make_get_command())
+        .run(run_object)
 }
 
 #[derive(Debug)]
@@ -49,11 +49,11 @@ struct Node<'a> {
     data: &'a [u8],
 }
 
-fn run_get(req: &request::Request) -> Result<(), String> {
+fn run_get(req: &request::Request) -> Result<(), CommandError> {
     let node = try!(req.context.get_node());
 
     let path = &req.string_arg("key").unwrap()[0];
-    let mh = try!(Multihash::from_base58_str(path));
+    let mh = try!(ipfs_commands::stored_hash(node, path));
     let mut dag_node = try!(node.dagservice.get(&mh));
 
     let mut links = Vec::new();
@@ -71,14 +71,13 @@ fn run_get(req: &request::Request) -> Result<(), String> {
         data: dag_node.get_data(),
     };
 
-    println!("{:?}", view_node);
+    try!(writeln!(req.raw_output(), "{:?}", view_node)
+             .map_err(|e| format!("Error writing object: {}", e)));
 
     Ok(())
 }
 
-ipfs_command!(GetCommand, run_get);
-
-fn make_get_command() -> Box<Command> {
+fn make_get_command() -> Arc<Command> {
     let arg_key = commands::Argument::new_string(
         "key",
         true,
@@ -86,5 +85,7 @@ fn make_get_command() -> Box<Command> {
         "Key of the object to retrieve (in base58-encoded multihash format)"
     );
 
-    Box::new(GetCommand::new("get", vec![], vec![arg_key], GetHelpText, vec![]))
+    CommandBuilder::new("get", GetHelpText)
+        .arg(arg_key)
+        .run(run_get)
 }
@@ -0,0 +1,83 @@
+use commands::{self, HelpText, Command, Argument, CommandError};
+use commands::builder::CommandBuilder;
+use commands::request;
+use hash;
+use provenance;
+
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::Arc;
+
+const ProvenanceHelpText: HelpText = HelpText {
+    tagline: "Interact with the provenance records of imported objects",
+    synopsis: "",
+    short_desc: "",
+};
+
+const LsHelpText: HelpText = HelpText {
+    tagline: "List where an object was imported from",
+    synopsis: "",
+    short_desc: r#"
+Lists the provenance records kept for <ipfs-path> by 'ipfs add --provenance',
+oldest first: the hash of each record, when the object was added, the path or
+URL it was added from, the peer id of the node that added it and whether the
+record's signature checks out.
+"#,
+};
+
+fn run_provenance(req: &request::Request) -> Result<(), CommandError> {
+    try!(write!(req.raw_output(), "{}", commands::help::render(req.command, "provenance", false))
+             .map_err(|e| format!("Error writing help: {}", e)));
+    Ok(())
+}
+
+pub fn make_command() -> Arc<Command> {
+    CommandBuilder::new("provenance", ProvenanceHelpText)
+        .subcommand(make_ls_command())
+        .run(run_provenance)
+}
+
+fn run_ls(req: &request::Request) -> Result<(), CommandError> {
+    let node = try!(req.context.get_node());
+    let content = try!(hash::parse_multihash(&req.string_arg("ipfs-path").unwrap()[0])
+                           .map_err(CommandError::Client));
+
+    let records = try!(provenance::list(node, req.context.repo_dir.clone(), &content));
+    for (record_hash, record) in records {
+        let verified = record.verify(&content);
+        let hash = format!("{}", record_hash);
+
+        let mut obj = BTreeMap::new();
+        obj.insert("hash".to_string(), hash.to_json());
+        obj.insert("time".to_string(), record.time.to_json());
+        obj.insert("source".to_string(), record.source.to_json());
+        obj.insert("importer".to_string(), record.importer.to_json());
+        obj.insert("verified".to_string(), verified.to_json());
+
+        req.emit_value(&format!("{}\t{}\t{}\t{}\t{}",
+                                hash,
+                                record.time,
+                                record.source,
+                                record.importer,
+                                if verified {
+                                    "signed"
+                                } else {
+                                    "BAD SIGNATURE"
+                                }),
+                       &hash,
+                       Json::Object(obj));
+    }
+    Ok(())
+}
+
+fn make_ls_command() -> Arc<Command> {
+    let arg_path = Argument::new_string("ipfs-path",
+                                        true,
+                                        false,
+                                        "The path to the IPFS object to list the records of");
+
+    CommandBuilder::new("ls", LsHelpText)
+        .arg(arg_path)
+        .run(run_ls)
+}
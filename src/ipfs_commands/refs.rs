@@ -0,0 +1,215 @@
+use commands::{HelpText, Command, Argument, Opt, CommandError};
+use commands::builder::CommandBuilder;
+use commands::request;
+use commands::response::Encoding;
+use core::IpfsNode;
+use ipfs_commands;
+use merkledag::Node;
+use unixfs;
+
+use rust_multihash::Multihash;
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Write};
+use std::sync::Arc;
+
+const HELP_TEXT: HelpText = HelpText {
+    tagline: "List hashes of links from an object",
+    synopsis: "",
+    short_desc: r#"
+Lists the hashes of all the links <ipfs-path> contains, one per line, or
+with --recursive all the hashes in the DAG under it.
+
+With --format dot or --format json, the whole DAG under each <ipfs-path>
+is printed as a graph instead: a DOT digraph for graphviz, or a JSON
+object with "nodes" and "links". Each node is labeled with the start of
+its hash, its size and its unixfs type.
+"#,
+};
+
+// how many characters of a hash are used as its label in a graph
+const LABEL_HASH_LEN: usize = 8;
+
+struct GraphNode {
+    hash: String,
+    size: usize,
+    ty: String,
+}
+
+struct GraphLink {
+    source: String,
+    target: String,
+    name: String,
+}
+
+struct Graph {
+    nodes: Vec<GraphNode>,
+    links: Vec<GraphLink>,
+}
+
+fn unixfs_type(dag_node: &Node) -> String {
+    match unixfs::from_reader(&mut dag_node.get_data()) {
+        Ok(data) => format!("{:?}", data.get_Type()),
+        Err(_) => "unknown".to_string(),
+    }
+}
+
+// Walks the DAGs under `roots`, adding each distinct block as a node once.
+// A link to an already visited block still becomes an edge.
+fn build_graph(node: &IpfsNode, roots: &[Multihash]) -> Result<Graph, String> {
+    let mut graph = Graph {
+        nodes: Vec::new(),
+        links: Vec::new(),
+    };
+    let mut seen = HashSet::new();
+    let mut stack: Vec<Multihash> = roots.iter().rev().cloned().collect();
+
+    while let Some(hash) = stack.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+
+        let block = try!(node.blockstore.get(&hash));
+        let dag_node = try!(Node::from_reader(&mut block.get_data()));
+
+        graph.nodes.push(GraphNode {
+            hash: format!("{}", hash),
+            size: block.get_data().len(),
+            ty: unixfs_type(&dag_node),
+        });
+
+        for link in dag_node.get_links().iter().rev() {
+            let target = link.clone_hash();
+            graph.links.push(GraphLink {
+                source: format!("{}", hash),
+                target: format!("{}", target),
+                name: link.clone_name(),
+            });
+            stack.push(target);
+        }
+    }
+    Ok(graph)
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace("\\", "\\\\").replace("\"", "\\\"")
+}
+
+fn write_dot(out: &mut Write, graph: &Graph) -> io::Result<()> {
+    try!(writeln!(out, "digraph dag {{"));
+    for node in graph.nodes.iter() {
+        let prefix: String = node.hash.chars().take(LABEL_HASH_LEN).collect();
+        try!(writeln!(out,
+                      "  \"{}\" [label=\"{}\\n{} bytes\\n{}\"];",
+                      node.hash,
+                      prefix,
+                      node.size,
+                      dot_escape(&node.ty)));
+    }
+    for link in graph.links.iter() {
+        try!(writeln!(out,
+                      "  \"{}\" -> \"{}\" [label=\"{}\"];",
+                      link.source,
+                      link.target,
+                      dot_escape(&link.name)));
+    }
+    writeln!(out, "}}")
+}
+
+impl ToJson for Graph {
+    fn to_json(&self) -> Json {
+        let nodes = self.nodes
+                        .iter()
+                        .map(|node| {
+                            let mut obj = BTreeMap::new();
+                            obj.insert("hash".to_string(), node.hash.to_json());
+                            obj.insert("size".to_string(), node.size.to_json());
+                            obj.insert("type".to_string(), node.ty.to_json());
+                            Json::Object(obj)
+                        })
+                        .collect();
+        let links = self.links
+                        .iter()
+                        .map(|link| {
+                            let mut obj = BTreeMap::new();
+                            obj.insert("source".to_string(), link.source.to_json());
+                            obj.insert("target".to_string(), link.target.to_json());
+                            obj.insert("name".to_string(), link.name.to_json());
+                            Json::Object(obj)
+                        })
+                        .collect();
+
+        let mut obj = BTreeMap::new();
+        obj.insert("nodes".to_string(), Json::Array(nodes));
+        obj.insert("links".to_string(), Json::Array(links));
+        Json::Object(obj)
+    }
+}
+
+fn run(req: &request::Request) -> Result<(), CommandError> {
+    let node = try!(req.context.get_node());
+
+    let mut roots = Vec::new();
+    for path in req.string_arg("ipfs-path").unwrap() {
+        roots.push(try!(ipfs_commands::stored_hash(node, path)));
+    }
+
+    match req.option_str("format") {
+        Some("dot") => {
+            let graph = try!(build_graph(node, &roots));
+            try!(write_dot(&mut *req.raw_output(), &graph)
+                     .map_err(|e| format!("Error writing graph: {}", e)));
+            return Ok(());
+        }
+        Some("json") => {
+            let graph = try!(build_graph(node, &roots)).to_json();
+            // on a line of its own, like any other output, with --encoding json
+            let graph = if req.encoding() == Ok(Encoding::Json) {
+                format!("{}", graph)
+            } else {
+                graph.pretty()
+            };
+            try!(writeln!(req.raw_output(), "{}", graph)
+                     .map_err(|e| format!("Error writing graph: {}", e)));
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let recursive = req.option_bool("r");
+    for root in roots.iter() {
+        if recursive {
+            let graph = try!(build_graph(node, &[root.clone()]));
+            for link in graph.links.iter() {
+                req.emit(&link.target, &link.target);
+            }
+        } else {
+            let dag_node = try!(node.dagservice.get(root));
+            for link in dag_node.get_links() {
+                let target = format!("{}", link.clone_hash());
+                req.emit(&target, &target);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn make_command() -> Arc<Command> {
+    let arg_path = Argument::new_string("ipfs-path",
+                                        true,
+                                        true,
+                                        "The path(s) to the IPFS object(s) to list refs from");
+
+    let recursive = Opt::new_bool(vec!["r", "recursive"],
+                                  "List all the hashes in the DAG, not just direct links");
+
+    let format = Opt::new_enum(vec!["format"],
+                               &["dot", "json"],
+                               "Print the DAG as a graph instead, in the given format");
+
+    CommandBuilder::new("refs", HELP_TEXT)
+        .option(recursive)
+        .option(format)
+        .arg(arg_path)
+        .run(run)
+}
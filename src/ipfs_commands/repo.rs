@@ -0,0 +1,232 @@
+use commands::{self, HelpText, Command, CommandError};
+use commands::builder::CommandBuilder;
+use commands::request;
+use config;
+use crypto;
+use fsrepo;
+
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+const RepoHelpText: HelpText = HelpText {
+    tagline: "Manipulate the IPFS repo",
+    synopsis: "",
+    short_desc: r#"
+'ipfs repo' is a plumbing command used to manipulate the repo.
+"#,
+};
+
+const CompressHelpText: HelpText = HelpText {
+    tagline: "Compress all blocks in the repo",
+    synopsis: "",
+    short_desc: r#"
+'ipfs repo compress' rewrites every block in the blockstore using zstd
+compression and enables compression for blocks added afterwards.
+"#,
+};
+
+const DecompressHelpText: HelpText = HelpText {
+    tagline: "Decompress all blocks in the repo",
+    synopsis: "",
+    short_desc: r#"
+'ipfs repo decompress' rewrites every block in the blockstore without
+compression and disables compression for blocks added afterwards.
+"#,
+};
+
+const EncryptHelpText: HelpText = HelpText {
+    tagline: "Encrypt all blocks in the repo",
+    synopsis: "",
+    short_desc: r#"
+'ipfs repo encrypt' rewrites every block in the blockstore encrypted with
+a key derived from the passphrase in IPFS_REPO_PASSPHRASE, or with the key
+printed (base64-encoded) by the command given with --key-command. Blocks
+added afterwards are encrypted as well.
+"#,
+};
+
+const RotateKeyHelpText: HelpText = HelpText {
+    tagline: "Re-encrypt all blocks in the repo with a new key",
+    synopsis: "",
+    short_desc: r#"
+'ipfs repo rotate-key' rewrites every block in an encrypted repo with a
+new key, derived from the passphrase in IPFS_NEW_REPO_PASSPHRASE or printed
+by the command given with --key-command. The current key is loaded as
+usual.
+"#,
+};
+
+fn run_repo(req: &request::Request) -> Result<(), CommandError> {
+    try!(write!(req.raw_output(), "{}", commands::help::render(req.command, "repo", false))
+             .map_err(|e| format!("Error writing help: {}", e)));
+    Ok(())
+}
+
+pub fn make_command() -> Arc<Command> {
+    CommandBuilder::new("repo", RepoHelpText)
+        .subcommand(make_compress_command())
+        .subcommand(make_decompress_command())
+        .subcommand(make_encrypt_command())
+        .subcommand(make_rotate_key_command())
+        .run(run_repo)
+}
+
+// Switches the repo's compression setting and migrates the existing blocks to match
+fn set_compression(req: &request::Request, compress: bool) -> Result<(), CommandError> {
+    let node = try!(req.context.get_node());
+
+    let rewritten = try!(node.blockstore.migrate(compress, node.blockstore.cipher()));
+
+    // the config is only updated once every block has been rewritten, so that
+    // an interrupted migration is finished by rerunning the command
+    let config_path = config::repo_path_to_config_file(req.context.repo_dir.clone());
+    let mut cfg = try!(fsrepo::read_config_file(&config_path));
+    cfg.set_compress_blocks(compress);
+    try!(fsrepo::update_config_file(&config_path, &cfg));
+
+    req.emit(&format!("rewrote {} blocks", rewritten), &format!("{}", rewritten));
+    Ok(())
+}
+
+// Re-encrypts the existing blocks under a new key and records it in the config
+fn set_encryption(req: &request::Request, passphrase_var: &str) -> Result<(), CommandError> {
+    let node = try!(req.context.get_node());
+
+    let key_command = req.option_str("key-command").map(|cmd| cmd.to_string());
+    let repo_dir = &req.context.repo_dir;
+    let salt = try!(key_salt(repo_dir));
+    let (cipher, encryption) = try!(crypto::new_repo_cipher(key_command, passphrase_var, salt));
+
+    let rewritten = try!(node.blockstore.migrate(node.blockstore.compresses(), Some(&cipher)));
+
+    let config_path = config::repo_path_to_config_file(repo_dir.clone());
+    let mut cfg = try!(fsrepo::read_config_file(&config_path));
+    cfg.set_encryption(encryption);
+    try!(fsrepo::update_config_file(&config_path, &cfg));
+    try!(fsrepo::remove_pending_salt(repo_dir));
+
+    req.emit(&format!("rewrote {} blocks", rewritten), &format!("{}", rewritten));
+    Ok(())
+}
+
+// The salt for the repo's new key. Every key gets a random salt of its own,
+// kept until the config records the key, so that an interrupted run is
+// finished with the same key.
+fn key_salt(repo_dir: &Path) -> Result<Vec<u8>, String> {
+    match try!(fsrepo::read_pending_salt(repo_dir)) {
+        Some(salt) => Ok(salt),
+        None => {
+            let salt = crypto::new_salt();
+            try!(fsrepo::write_pending_salt(repo_dir, &salt[..]));
+            Ok(salt)
+        }
+    }
+}
+
+fn key_command_opt() -> commands::Opt {
+    commands::Opt::new_string(
+        vec!["key-command"],
+        "Shell command printing the base64-encoded key, instead of using a passphrase"
+    )
+}
+
+fn run_compress(req: &request::Request) -> Result<(), CommandError> {
+    set_compression(req, true)
+}
+
+fn make_compress_command() -> Arc<Command> {
+    CommandBuilder::new("compress", CompressHelpText)
+        .mutating()
+        .run(run_compress)
+}
+
+fn run_decompress(req: &request::Request) -> Result<(), CommandError> {
+    set_compression(req, false)
+}
+
+fn make_decompress_command() -> Arc<Command> {
+    CommandBuilder::new("decompress", DecompressHelpText)
+        .mutating()
+        .run(run_decompress)
+}
+
+fn run_encrypt(req: &request::Request) -> Result<(), CommandError> {
+    set_encryption(req, crypto::ENV_NAME_REPO_PASSPHRASE)
+}
+
+fn validate_encrypt(req: &request::Request) -> Result<(), CommandError> {
+    let node = try!(req.context.get_node());
+    if node.blockstore.cipher().is_some() {
+        return Err(CommandError::Client("The repo is already encrypted. Use `ipfs repo \
+                                         rotate-key` to change the key."
+                                            .to_string()));
+    }
+    Ok(())
+}
+
+fn make_encrypt_command() -> Arc<Command> {
+    CommandBuilder::new("encrypt", EncryptHelpText)
+        .option(key_command_opt())
+        .mutating()
+        .validate(validate_encrypt)
+        .run(run_encrypt)
+}
+
+fn run_rotate_key(req: &request::Request) -> Result<(), CommandError> {
+    set_encryption(req, crypto::ENV_NAME_NEW_REPO_PASSPHRASE)
+}
+
+fn validate_rotate_key(req: &request::Request) -> Result<(), CommandError> {
+    let node = try!(req.context.get_node());
+    if node.blockstore.cipher().is_none() {
+        return Err(CommandError::Client("The repo is not encrypted. Use `ipfs repo encrypt` \
+                                         first."
+                                            .to_string()));
+    }
+    Ok(())
+}
+
+fn make_rotate_key_command() -> Arc<Command> {
+    CommandBuilder::new("rotate-key", RotateKeyHelpText)
+        .option(key_command_opt())
+        .mutating()
+        .validate(validate_rotate_key)
+        .run(run_rotate_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::key_salt;
+    use crypto;
+    use fsrepo;
+
+    use std::env;
+    use std::fs;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn an_interrupted_encryption_resumes_with_the_same_key() {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+        let repo_dir = env::temp_dir().join(format!("repo-encrypt-test-{}", nanos));
+        fs::create_dir(&repo_dir).unwrap();
+        // a variable of the test's own, since tests share the environment
+        let var = "IPFS_TEST_RESUMED_PASSPHRASE";
+        env::set_var(var, "correct horse battery staple");
+
+        let salt = key_salt(&repo_dir).unwrap();
+        let (cipher, _) = crypto::new_repo_cipher(None, var, salt.clone()).unwrap();
+        let sealed = cipher.seal(b"block", b"hash");
+
+        // rerun before the config recorded the key
+        let resumed_salt = key_salt(&repo_dir).unwrap();
+        assert_eq!(resumed_salt, salt);
+        let (resumed, _) = crypto::new_repo_cipher(None, var, resumed_salt).unwrap();
+        assert_eq!(resumed.open(&sealed[..], b"hash"), Ok(b"block".to_vec()));
+
+        // once it has, the next key gets a salt of its own
+        fsrepo::remove_pending_salt(&repo_dir).unwrap();
+        assert!(key_salt(&repo_dir).unwrap() != salt);
+        fs::remove_dir_all(&repo_dir).unwrap();
+    }
+}
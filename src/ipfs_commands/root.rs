@@ -1,7 +1,11 @@
 use super::*;
-use commands::{self, HelpText, Command};
+use commands::{self, HelpText, Command, CommandError};
+use commands::builder::CommandBuilder;
 use commands::request;
 
+use std::io::Write;
+use std::sync::Arc;
+
 const HELP_TEXT: HelpText = HelpText {
     tagline: "global p2p merkle-dag filesystem",
     synopsis: r#"
@@ -48,23 +52,22 @@ TOOL COMMANDS
     version       Show ipfs version information
     update        Download and apply go-ipfs updates
     commands      List all available commands
+    completion    Print a shell completion script
 
 Use 'ipfs <command> --help' to learn more about each command.
+
+Any other command runs the forty_situps-<command> executable on the PATH, if
+there is one, with the rest of the command line.
 "#,
 };
 
-fn run(req: &request::Request) -> Result<(), String> {
-    let help_text = req.command.get_help_text();
-    println!("{}\n{}\n{}",
-             help_text.tagline,
-             help_text.short_desc,
-             help_text.synopsis);
+fn run(req: &request::Request) -> Result<(), CommandError> {
+    try!(write!(req.raw_output(), "{}", commands::help::render(req.command, "", true))
+             .map_err(|e| format!("Error writing help: {}", e)));
     Ok(())
 }
 
-ipfs_command!(RootCommand, run);
-
-pub fn make_command() -> Box<commands::Command> {
+pub fn make_command() -> Arc<commands::Command> {
     let short_help = commands::Opt::new_bool(
         vec!["h"],
         "Show a short version of the command help text"
@@ -75,18 +78,96 @@ pub fn make_command() -> Box<commands::Command> {
         "Show the full command help text"
     );
 
+    let quiet = commands::Opt::new_bool(
+        vec!["q", "quiet"],
+        "Only print the essential part of each result (e.g. the hash)"
+    );
+
+    let quieter = commands::Opt::new_bool(
+        vec!["Q", "quieter"],
+        "Only print the essential part of the last result"
+    );
+
+    let repo_dir = commands::Opt::new_string(
+        vec!["repo-dir"],
+        "Use the repo at this path instead of $IPFS_PATH or ~/.rust-ipfs"
+    );
+
+    let read_only = commands::Opt::new_bool(
+        vec!["read-only"],
+        "Refuse to run commands that would modify the repo"
+    );
+
+    let offline = commands::Opt::new_bool(
+        vec!["offline"],
+        "Don't use the network, e.g. to fetch --from-url or to submit crash reports"
+    );
 
+    let trace = commands::Opt::new_bool(
+        vec!["trace"],
+        "Print a breakdown of where the command spent its time to stderr"
+    );
+
+    let trace_file = commands::Opt::new_string(
+        vec!["trace-file"],
+        "Write the timing breakdown as JSON to the given file"
+    );
+
+    let debug = commands::Opt::new_bool(
+        vec!["debug"],
+        "Print debug logging to stderr, unless RUST_LOG is set"
+    );
+
+    let progress = commands::Opt::new_bool(
+        vec![commands::progress::PROGRESS_OPTION],
+        "Show the progress of long-running commands like add on stderr"
+    );
+
+    let request_id = commands::Opt::new_string(
+        vec!["request-id"],
+        "An id for the command, so that the daemon runs a mutating command that's sent again \
+         with the same id only once"
+    );
+
+    let encoding = commands::Opt::new_enum(
+        vec!["encoding"],
+        commands::response::ENCODINGS,
+        "The format to print results in (default: text)"
+    );
 
-    Box::new(RootCommand::new(
-        "",
-        vec![short_help, long_help],
-        vec![],
-        HELP_TEXT,
-        vec![
-            init::make_command(),
-            add::make_command(),
-            file::make_command(),
-            object::make_command(),
-        ]
-    ))
+    CommandBuilder::new("", HELP_TEXT)
+        .option(short_help)
+        .option(long_help)
+        .option(quiet)
+        .option(quieter)
+        .option(repo_dir)
+        .option(read_only)
+        .option(offline)
+        .option(request_id)
+        .option(trace)
+        .option(trace_file)
+        .option(progress)
+        .option(encoding)
+        .option(debug)
+        .subcommand(init::make_command())
+        .subcommand(config::make_command())
+        .subcommand(add::make_command())
+        .subcommand(cat::make_command())
+        .subcommand(daemon::make_command())
+        .subcommand(file::make_command())
+        .subcommand(block::make_command())
+        .subcommand(object::make_command())
+        .subcommand(refs::make_command())
+        .subcommand(provenance::make_command())
+        .subcommand(dag::make_command())
+        .subcommand(ec::make_command())
+        .subcommand(repo::make_command())
+        .subcommand(diag::make_command())
+        .subcommand(log::make_command())
+        .subcommand(stats::make_command())
+        .subcommand(shell::make_command())
+        .subcommand(batch::make_command())
+        .subcommand(help::make_command())
+        .subcommand(completion::make_command())
+        .run(run)
 }
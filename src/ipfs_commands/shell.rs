@@ -0,0 +1,170 @@
+use commands::{HelpText, Command, CommandError};
+use commands::builder::CommandBuilder;
+use commands::completion;
+use commands::request;
+use ipfs_commands::root;
+use lineedit;
+use util;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const HELP_TEXT: HelpText = HelpText {
+    tagline: "Run ipfs commands from an interactive prompt",
+    synopsis: "",
+    short_desc: r#"
+'ipfs shell' reads command lines from a prompt and runs each one against
+the repo, which is opened only once. Lines are written the way they would
+be after 'ipfs' on the command line; a leading 'ipfs' is ignored.
+
+Besides the ipfs commands, the prompt understands:
+
+    history             Print the lines entered so far
+    complete <line>     List the words that could complete the line
+    exit, quit          Leave the shell (as does end of input)
+
+Tab completes the word being typed, listing the choices if there are
+several, and the up and down keys step through the history. The history is
+kept in the repo, so it carries over between sessions, unless the repo is
+read-only. Lines piped in are run without a prompt.
+
+While a daemon serves the repo, each line is sent to it, as it would be
+from the command line. Otherwise the shell holds the repo lock until it
+exits, so that no daemon can start and modify the repo under it.
+"#,
+};
+
+const PROMPT: &'static str = "ipfs> ";
+const HISTORY_FILE: &'static str = "shell_history";
+
+fn history_path(mut repo_path: PathBuf) -> PathBuf {
+    repo_path.push(HISTORY_FILE);
+    repo_path
+}
+
+fn load_history(path: &PathBuf) -> Vec<String> {
+    match File::open(path) {
+        Err(_) => vec![],
+        Ok(file) => BufReader::new(file).lines().filter_map(|line| line.ok()).collect(),
+    }
+}
+
+fn append_history(path: &PathBuf, line: &str) -> Result<(), String> {
+    let mut file = try!(OpenOptions::new()
+                            .append(true)
+                            .create(true)
+                            .open(path)
+                            .map_err(|e| format!("Error opening shell history {:?}: {}", path, e)));
+    writeln!(file, "{}", line).map_err(|e| format!("Error writing shell history {:?}: {}", path, e))
+}
+
+// The words that could complete the last word of `line`
+fn complete(root: &Command, line: &str) -> Result<Vec<String>, String> {
+    let mut words = try!(util::split_words(line));
+    let partial = if line.is_empty() || line.ends_with(char::is_whitespace) {
+        String::new()
+    } else {
+        words.pop().unwrap_or(String::new())
+    };
+    if words.first().map_or(false, |w| w == "ipfs") {
+        words.remove(0);
+    }
+    Ok(completion::candidates(root, &words[..], &partial))
+}
+
+fn run(req: &request::Request) -> Result<(), CommandError> {
+    let root = root::make_command();
+    let history_path = history_path(req.context.repo_dir.clone());
+    let mut history = load_history(&history_path);
+    let mut session = try!(::Session::start(&req.context.repo_dir, req.context.read_only));
+
+    // piped in lines are read as they are, without a prompt
+    let mut piped = match req.piped_stdin() {
+        Some(stdin) => Some(BufReader::new(try!(stdin.open()))),
+        None if lineedit::is_terminal() => None,
+        None => return Ok(()),
+    };
+    loop {
+        let line = match piped {
+            Some(ref mut piped) => {
+                let mut line = String::new();
+                let read = try!(piped.read_line(&mut line)
+                                     .map_err(|e| format!("Error reading command line: {}", e)));
+                if read == 0 {
+                    return Ok(());
+                }
+                line
+            }
+            None => {
+                let line = try!(lineedit::read_line(PROMPT, &history[..], |line| {
+                                    complete(&*root, line).unwrap_or(vec![])
+                                })
+                                .map_err(|e| format!("Error reading command line: {}", e)));
+                match line {
+                    Some(line) => line,
+                    None => return Ok(()),
+                }
+            }
+        };
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        match line {
+            "exit" | "quit" => return Ok(()),
+            "history" => {
+                for (i, past) in history.iter().enumerate() {
+                    req.emit(&format!("{:5}  {}", i + 1, past), past);
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        if line == "complete" || line.starts_with("complete ") {
+            match complete(&*root, &line["complete".len()..].trim_left()) {
+                Ok(candidates) => {
+                    for candidate in candidates {
+                        req.emit(&candidate, &candidate);
+                    }
+                }
+                Err(e) => {
+                    let _ = writeln!(io::stderr(), "{}", e);
+                }
+            }
+            continue;
+        }
+
+        if !req.context.read_only {
+            if let Err(e) = append_history(&history_path, line) {
+                let _ = writeln!(io::stderr(), "{}", e);
+            }
+        }
+        history.push(line.to_string());
+
+        let mut words = match util::split_words(line) {
+            Err(e) => {
+                let _ = writeln!(io::stderr(), "{}", e);
+                continue;
+            }
+            Ok(words) => words,
+        };
+        if words.first().map_or(false, |w| w == "ipfs") {
+            words.remove(0);
+        }
+
+        let context = req.context.clone();
+        let result = ::run_line(&*root, words, context, &mut session, &mut *req.raw_output());
+        if let Err(e) = result {
+            let _ = writeln!(io::stderr(), "{}", e);
+        }
+    }
+}
+
+pub fn make_command() -> Arc<Command> {
+    CommandBuilder::new("shell", HELP_TEXT).run(run)
+}
@@ -0,0 +1,79 @@
+use commands::{self, HelpText, Command, Opt, CommandError};
+use commands::builder::CommandBuilder;
+use commands::request;
+use stats;
+
+use rustc_serialize::json::{Json, ToJson};
+
+use std::io::Write;
+use std::sync::Arc;
+
+const StatsHelpText: HelpText = HelpText {
+    tagline: "Query usage statistics of the repo",
+    synopsis: "",
+    short_desc: "",
+};
+
+const CommandsHelpText: HelpText = HelpText {
+    tagline: "Summarize how long commands took and how often they failed",
+    synopsis: "",
+    short_desc: r#"
+Every command run against a writable repo records how long it took and
+its exit code in the repo. 'ipfs stats commands' prints, for each command,
+how many times it ran, how many of those runs failed, and its mean and
+maximum latency in milliseconds. The JSON output also contains the latency
+histogram.
+
+With --prometheus, the histograms and error counts are printed in the
+Prometheus text format instead, ready to be picked up by a textfile
+collector.
+"#,
+};
+
+fn run_stats(req: &request::Request) -> Result<(), CommandError> {
+    try!(write!(req.raw_output(), "{}", commands::help::render(req.command, "stats", false))
+             .map_err(|e| format!("Error writing help: {}", e)));
+    Ok(())
+}
+
+pub fn make_command() -> Arc<Command> {
+    CommandBuilder::new("stats", StatsHelpText)
+        .subcommand(make_commands_command())
+        .run(run_stats)
+}
+
+fn run_commands(req: &request::Request) -> Result<(), CommandError> {
+    let summary = try!(stats::summarize(req.context.repo_dir.clone()));
+
+    if req.option_bool("prometheus") {
+        try!(stats::write_prometheus(&mut *req.raw_output(), &summary)
+                 .map_err(|e| format!("Error writing stats: {}", e)));
+        return Ok(());
+    }
+
+    for (command, s) in &summary {
+        let mut value = s.to_json();
+        if let Json::Object(ref mut obj) = value {
+            obj.insert("command".to_string(), command.to_json());
+        }
+        req.emit_value(&format!("{}\t{}\t{}\t{:.1}%\t{}ms\t{}ms",
+                                command,
+                                s.count,
+                                s.errors,
+                                s.error_rate() * 100.0,
+                                s.mean_millis(),
+                                s.max_millis),
+                       command,
+                       value);
+    }
+    Ok(())
+}
+
+fn make_commands_command() -> Arc<Command> {
+    let prometheus = Opt::new_bool(vec!["prometheus"],
+                                   "Print the stats in the Prometheus text format");
+
+    CommandBuilder::new("commands", CommandsHelpText)
+        .option(prometheus)
+        .run(run_commands)
+}
@@ -0,0 +1,241 @@
+// Runs command lines through the whole front-end, from parsing to the
+// encoded output, with commands::testing. None of them need a repo.
+
+use commands::completion;
+use commands::request::{Context, Request};
+use commands::testing::{self, Outcome};
+use health::{self, Health};
+use ipfs_commands::root;
+
+use rustc_serialize::json::Json;
+use std::path::PathBuf;
+use std::thread;
+
+fn no_repo(read_only: bool) -> Context<'static> {
+    Context::new(PathBuf::from("/nonexistent/ipfs-repo"), None, read_only, true)
+}
+
+fn run(args: &[&str]) -> Outcome {
+    testing::execute(&*root::make_command(), args, None, no_repo(false))
+}
+
+fn stdout(outcome: &Outcome) -> String {
+    String::from_utf8_lossy(&outcome.stdout).into_owned()
+}
+
+// Each line of the output, which has to be a JSON object of its own
+fn json_lines(outcome: &Outcome) -> Vec<Json> {
+    stdout(outcome)
+        .lines()
+        .map(|line| {
+            let json = Json::from_str(line).expect(&format!("not JSON: {:?}", line));
+            assert!(json.is_object(), "not an object: {:?}", line);
+            json
+        })
+        .collect()
+}
+
+fn error_field<'a>(json: &'a Json, field: &str) -> Option<&'a Json> {
+    json.find("error").and_then(|error| error.find(field))
+}
+
+#[test]
+fn help_lists_the_subcommands() {
+    let outcome = run(&["repo", "--help"]);
+    assert_eq!(outcome.exit_code, 0);
+    assert!(stdout(&outcome).contains("compress"));
+    assert!(stdout(&outcome).contains("rotate-key"));
+}
+
+#[test]
+fn subcommands_have_help_of_their_own() {
+    let outcome = run(&["file", "ls", "--help"]);
+    assert_eq!(outcome.exit_code, 0);
+    assert!(stdout(&outcome).contains("Retrieves the object named by <ipfs-or-ipns-path>"));
+}
+
+#[test]
+fn unknown_subcommands_are_client_errors() {
+    let outcome = run(&["blok"]);
+    assert_eq!(outcome.exit_code, 2);
+    assert!(stdout(&outcome).contains("Subcommand blok not found. Did you mean block?"));
+}
+
+#[test]
+fn unknown_encodings_are_refused() {
+    let outcome = run(&["--encoding=xml", "repo", "compress"]);
+    assert_eq!(outcome.exit_code, 2);
+    assert!(stdout(&outcome).contains("Invalid value for option encoding"));
+}
+
+#[test]
+fn commands_report_a_missing_repo_as_not_found() {
+    let outcome = run(&["repo", "compress"]);
+    assert_eq!(outcome.exit_code, 3);
+    assert!(stdout(&outcome).contains("ipfs init"));
+    assert!(outcome.response.items().is_empty());
+}
+
+#[test]
+fn mutating_commands_are_refused_when_read_only() {
+    let root = root::make_command();
+    let outcome = testing::execute(&*root, &["repo", "compress"], None, no_repo(true));
+    assert_eq!(outcome.exit_code, 2);
+    assert!(stdout(&outcome).contains("The repo is read-only: `compress` would modify it"));
+}
+
+#[test]
+fn completion_scripts_cover_the_command_tree() {
+    let outcome = run(&["completion", "bash"]);
+    assert_eq!(outcome.exit_code, 0);
+    assert!(stdout(&outcome).contains("_ipfs()"));
+    assert!(stdout(&outcome).contains("rotate-key"));
+    assert!(outcome.stderr.is_empty());
+}
+
+#[test]
+fn batch_reads_its_script_from_stdin() {
+    let outcome = testing::execute(&*root::make_command(),
+                                   &["batch"],
+                                   Some(b"# a comment\n\nblok\n"),
+                                   no_repo(false));
+    assert_eq!(outcome.exit_code, 1);
+    assert!(stdout(&outcome).contains("line 3: blok: Subcommand blok not found"));
+    assert!(stdout(&outcome).contains("1 of 1 command lines failed"));
+
+    let outcome = testing::execute(&*root::make_command(), &["batch"], None, no_repo(false));
+    assert_eq!(outcome.exit_code, 2);
+    assert!(stdout(&outcome).contains("No script to run"));
+}
+
+#[test]
+fn batch_lines_write_to_the_batch_output() {
+    let outcome = testing::execute(&*root::make_command(),
+                                   &["batch"],
+                                   Some(b"repo --help\n"),
+                                   no_repo(false));
+    assert_eq!(outcome.exit_code, 0);
+    assert!(stdout(&outcome).contains("rotate-key"));
+}
+
+#[test]
+fn shell_runs_piped_lines_without_a_prompt() {
+    let outcome = testing::execute(&*root::make_command(),
+                                   &["shell"],
+                                   Some(b"repo --help\nhistory\ncomplete repo rot\nexit\nblok\n"),
+                                   no_repo(true));
+    assert_eq!(outcome.exit_code, 0);
+    let stdout = stdout(&outcome);
+    assert!(!stdout.contains("ipfs>"));
+    assert!(stdout.contains("compress"));
+    assert!(stdout.contains("    1  repo --help\n"));
+    assert!(stdout.ends_with("rotate-key\n"));
+}
+
+#[test]
+fn completions_come_from_the_command_tree() {
+    let root = root::make_command();
+    let words = |words: &[&str]| words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+    assert_eq!(completion::candidates(&*root, &words(&["repo"]), "rot"),
+               vec!["rotate-key"]);
+    assert!(completion::candidates(&*root, &words(&[]), "")
+                .contains(&"repo".to_string()));
+    assert_eq!(completion::candidates(&*root, &words(&["repo", "compress"]), "--enc"),
+               vec!["--encoding"]);
+    assert_eq!(completion::candidates(&*root, &words(&["--encoding"]), ""),
+               vec!["json", "text"]);
+    // options are only offered once a dash is typed
+    assert!(completion::candidates(&*root, &words(&[]), "")
+                .iter()
+                .all(|c| !c.starts_with("-")));
+}
+
+#[test]
+fn requests_run_concurrently_against_one_tree() {
+    fn assert_send<T: Send>() {}
+    // the daemon hands each request to a thread of its own
+    assert_send::<Request>();
+
+    let root = root::make_command();
+    let runs: Vec<_> = (0..4)
+                           .map(|_| {
+                               let root = root.clone();
+                               thread::spawn(move || {
+                                   testing::execute(&*root, &["repo"], None, no_repo(false))
+                               })
+                           })
+                           .collect();
+    for run in runs {
+        let outcome = run.join().unwrap();
+        assert_eq!(outcome.exit_code, 0);
+        assert!(stdout(&outcome).contains("rotate-key"));
+    }
+}
+
+#[test]
+fn errors_are_json_objects_with_json_encoding() {
+    let outcome = run(&["--encoding=json", "repo", "compress"]);
+    assert_eq!(outcome.exit_code, 3);
+    let lines = json_lines(&outcome);
+    assert_eq!(lines.len(), 1);
+    assert_eq!(error_field(&lines[0], "type").and_then(|t| t.as_string()),
+               Some("not_found"));
+    assert_eq!(error_field(&lines[0], "code").and_then(|c| c.as_i64()), Some(3));
+    assert!(error_field(&lines[0], "message")
+                .and_then(|m| m.as_string())
+                .map_or(false, |m| m.contains("ipfs init")));
+
+    // even if the command line can't be parsed
+    let outcome = run(&["--encoding", "json", "blok"]);
+    assert_eq!(outcome.exit_code, 2);
+    let lines = json_lines(&outcome);
+    assert_eq!(lines.len(), 1);
+    assert_eq!(error_field(&lines[0], "type").and_then(|t| t.as_string()), Some("client"));
+}
+
+#[test]
+fn json_output_has_one_object_per_line() {
+    let outcome = testing::execute(&*root::make_command(),
+                                   &["--encoding=json", "batch"],
+                                   Some(b"blok\nrepo \"unclosed\n"),
+                                   no_repo(false));
+    assert_eq!(outcome.exit_code, 1);
+    let lines = json_lines(&outcome);
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0].find("line").and_then(|l| l.as_i64()), Some(1));
+    assert_eq!(lines[1].find("line").and_then(|l| l.as_i64()), Some(2));
+    assert_eq!(error_field(&lines[2], "type").and_then(|t| t.as_string()),
+               Some("internal"));
+    assert_eq!(outcome.response.items().len(), 2);
+}
+
+#[test]
+fn diag_health_reports_each_service_and_fails_until_all_are_up() {
+    let outcome = run(&["diag", "health"]);
+    assert_eq!(outcome.exit_code, 3);
+    assert!(stdout(&outcome).contains("No daemon is running"));
+
+    let health = Health::new();
+    health.set("api", health::State::Starting, "");
+    health.set("network", health::State::Degraded, "offline");
+    let mut context = no_repo(false);
+    context.health = Some(health.clone());
+    let outcome = testing::execute(&*root::make_command(),
+                                   &["--encoding=json", "diag", "health"],
+                                   None,
+                                   context.clone());
+    assert_eq!(outcome.exit_code, 1);
+    let lines = json_lines(&outcome);
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0].find("state").and_then(|s| s.as_string()), Some("starting"));
+    assert_eq!(lines[1].find("message").and_then(|m| m.as_string()), Some("offline"));
+    assert!(error_field(&lines[2], "message")
+                .and_then(|m| m.as_string())
+                .map_or(false, |m| m.contains("Not ready: api")));
+
+    // degraded services still count as up
+    health.set("api", health::State::Ready, "");
+    let outcome = testing::execute(&*root::make_command(), &["diag", "health"], None, context);
+    assert_eq!(outcome.exit_code, 0);
+    assert_eq!(stdout(&outcome), "api\tready\t\nnetwork\tdegraded\toffline\n");
+}
@@ -0,0 +1,242 @@
+// A small line editor for interactive prompts like `ipfs shell`'s. It puts
+// the terminal in raw mode while a line is read, so it sees each key as it's
+// typed, and understands:
+//
+//     Tab               complete the word being typed, or list the choices
+//     Up, Down          step through the history
+//     Backspace         delete the last character
+//     Ctrl-C            discard the line
+//     Ctrl-D            end the input, on an empty line
+//
+// The prompt and what's typed are drawn on stderr, which is the terminal even
+// when the output is redirected. Lines are edited at their end only.
+
+use std::io::{self, Read, Write};
+
+const TAB: u8 = b'\t';
+const CTRL_C: u8 = 3;
+const CTRL_D: u8 = 4;
+const BACKSPACE: u8 = 8;
+const DELETE: u8 = 127;
+const ESCAPE: u8 = 27;
+
+// Whether the prompt can be edited, i.e. stdin and stderr are a terminal
+#[cfg(unix)]
+pub fn is_terminal() -> bool {
+    use libc;
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 && libc::isatty(libc::STDERR_FILENO) == 1 }
+}
+
+#[cfg(not(unix))]
+pub fn is_terminal() -> bool {
+    true
+}
+
+// Reads a line typed at `prompt`, offering the earlier lines in `history` and
+// the words `complete` gives for the last word of the line so far. Returns
+// None at the end of the input.
+#[cfg(unix)]
+pub fn read_line<F>(prompt: &str, history: &[String], complete: F) -> io::Result<Option<String>>
+    where F: Fn(&str) -> Vec<String>
+{
+    let _raw = try!(RawMode::enable());
+    let mut editor = Editor {
+        prompt: prompt,
+        line: Vec::new(),
+        history: history,
+        // one past the newest line, standing for the line being typed
+        position: history.len(),
+        typed: Vec::new(),
+    };
+    try!(editor.redraw());
+    let stdin = io::stdin();
+    let mut keys = stdin.lock().bytes();
+    loop {
+        let key = match keys.next() {
+            Some(key) => try!(key),
+            None => return Ok(None),
+        };
+        match key {
+            b'\r' | b'\n' => {
+                try!(editor.write(b"\r\n"));
+                return Ok(Some(String::from_utf8_lossy(&editor.line[..]).into_owned()));
+            }
+            CTRL_D if editor.line.is_empty() => {
+                try!(editor.write(b"\r\n"));
+                return Ok(None);
+            }
+            CTRL_C => {
+                try!(editor.write(b"^C\r\n"));
+                editor.line.clear();
+                editor.position = history.len();
+            }
+            BACKSPACE | DELETE => {
+                // a character may be several bytes of UTF-8
+                while let Some(b) = editor.line.pop() {
+                    if b & 0xc0 != 0x80 {
+                        break;
+                    }
+                }
+            }
+            TAB => try!(editor.complete(&complete)),
+            ESCAPE => {
+                // arrow keys send ESC [ A and the like; other sequences are
+                // read to their end and ignored
+                let mut sequence = Vec::new();
+                while let Some(b) = keys.next() {
+                    let b = try!(b);
+                    sequence.push(b);
+                    if b != b'[' && b != b'O' && !(b >= b'0' && b <= b'9') && b != b';' {
+                        break;
+                    }
+                }
+                match sequence.last() {
+                    Some(&b'A') => editor.step_history(-1),
+                    Some(&b'B') => editor.step_history(1),
+                    _ => {}
+                }
+            }
+            key if key >= b' ' => editor.line.push(key),
+            _ => {}
+        }
+        try!(editor.redraw());
+    }
+}
+
+// Without raw mode the terminal edits the line itself, so there's no
+// completion or history to step through
+#[cfg(not(unix))]
+pub fn read_line<F>(prompt: &str, _history: &[String], _complete: F) -> io::Result<Option<String>>
+    where F: Fn(&str) -> Vec<String>
+{
+    let stderr = io::stderr();
+    let mut stderr = stderr.lock();
+    try!(write!(stderr, "{}", prompt));
+    try!(stderr.flush());
+
+    let mut line = String::new();
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+    if try!(io::BufRead::read_line(&mut stdin, &mut line)) == 0 {
+        try!(writeln!(stderr, ""));
+        return Ok(None);
+    }
+    Ok(Some(line.trim_right_matches(|c| c == '\r' || c == '\n').to_string()))
+}
+
+struct Editor<'a> {
+    prompt: &'a str,
+    line: Vec<u8>,
+    history: &'a [String],
+    // which line of the history is shown; history.len() for the typed one
+    position: usize,
+    typed: Vec<u8>, // the line being typed, kept while the history is shown
+}
+
+impl<'a> Editor<'a> {
+    fn write(&self, bytes: &[u8]) -> io::Result<()> {
+        let stderr = io::stderr();
+        let mut stderr = stderr.lock();
+        try!(stderr.write_all(bytes));
+        stderr.flush()
+    }
+
+    // Draws the prompt and the line over whatever the cursor's line held
+    fn redraw(&self) -> io::Result<()> {
+        let mut out = b"\r\x1b[K".to_vec();
+        out.extend_from_slice(self.prompt.as_bytes());
+        out.extend_from_slice(&self.line[..]);
+        self.write(&out[..])
+    }
+
+    fn step_history(&mut self, step: isize) {
+        let position = self.position as isize + step;
+        if position < 0 || position > self.history.len() as isize {
+            return;
+        }
+        if self.position == self.history.len() {
+            self.typed = self.line.clone();
+        }
+        self.position = position as usize;
+        self.line = match self.history.get(self.position) {
+            Some(past) => past.as_bytes().to_vec(),
+            None => self.typed.clone(),
+        };
+    }
+
+    // Completes the last word of the line as far as all the choices for it
+    // agree, ending it if there's only one, and lists the choices if that
+    // doesn't add anything
+    fn complete<F>(&mut self, complete: &F) -> io::Result<()>
+        where F: Fn(&str) -> Vec<String>
+    {
+        let line = String::from_utf8_lossy(&self.line[..]).into_owned();
+        let word_start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &line[word_start..];
+        let choices = complete(&line);
+        let common = match choices.first() {
+            None => return Ok(()),
+            Some(first) => {
+                choices.iter().fold(first.clone(), |common, choice| common_prefix(&common, choice))
+            }
+        };
+
+        if common.len() > word.len() && common.starts_with(word) {
+            self.line.truncate(word_start);
+            self.line.extend_from_slice(common.as_bytes());
+            // a directory is completed inside rather than ended
+            if choices.len() == 1 && !common.ends_with('/') {
+                self.line.push(b' ');
+            }
+        } else if choices.len() > 1 {
+            let mut listing = b"\r\n".to_vec();
+            listing.extend_from_slice(choices.join("  ").as_bytes());
+            listing.extend_from_slice(b"\r\n");
+            try!(self.write(&listing[..]));
+        }
+        Ok(())
+    }
+}
+
+fn common_prefix(a: &str, b: &str) -> String {
+    a.chars().zip(b.chars()).take_while(|&(x, y)| x == y).map(|(x, _)| x).collect()
+}
+
+// Raw mode, for as long as this is kept. The terminal is put back as it was
+// when it's dropped, including when a command panics.
+#[cfg(unix)]
+struct RawMode {
+    original: ::libc::termios,
+}
+
+#[cfg(unix)]
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        use libc;
+        use std::mem;
+
+        let mut original: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = original;
+        // keys arrive one at a time, unechoed, and Ctrl-C is a key like any other
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+        raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RawMode { original: original })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            ::libc::tcsetattr(::libc::STDIN_FILENO, ::libc::TCSANOW, &self.original);
+        }
+    }
+}
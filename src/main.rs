@@ -1,94 +1,405 @@
 extern crate atomicwrites;
+extern crate backtrace;
+#[cfg(feature = "blake3")]
+extern crate blake3;
 extern crate env_logger;
+extern crate hyper;
 extern crate libc;
 #[macro_use] extern crate log;
 extern crate openssl;
 extern crate protobuf;
+extern crate crypto as rust_crypto;
 extern crate rust_multihash;
 extern crate rustc_serialize;
+extern crate zstd;
 
 mod ipfs_commands;
+mod api;
+mod audit;
 mod block;
 mod blockstore;
+mod clock;
 mod commands;
 mod config;
+mod crashreport;
 mod core;
 mod crypto;
+mod delta;
+mod erasure;
 mod fsrepo;
+mod hash;
+mod health;
+mod ignore;
+mod importer;
+mod lineedit;
 mod merkledag;
+mod plugin;
+mod provenance;
+mod stats;
+mod template;
+mod trace;
 mod util;
 mod unixfs;
 
 use blockstore::Blockstore;
+use commands::CommandError;
+use commands::progress;
 use commands::request;
+use commands::response::{self, Encoding};
 use core::IpfsNode;
 
+use rustc_serialize::json::ToJson;
 use std::env;
-use std::path::PathBuf;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
 
 struct CommandInvocation<'a, 'b> {
     pub request: request::Request<'a, 'b>,
     pub command: &'a commands::Command,
+    pub path: String, // the names of the command and its parents, e.g. "repo compress"
 }
 
-type ParseError = String;
-
 impl<'a, 'b> CommandInvocation<'a, 'b> {
-    fn from_cli_parse<I>(args: I,
-                         root: &'a commands::Command,
-                         context: request::Context<'b>)
-                         -> Result<Self, ParseError>
-        where I: Iterator<Item = String>
-    {
-        let (cmd, args, opts) = try!(commands::cli::parse(args, root));
+    fn new(parse: commands::cli::ParseResult<'a>,
+           path: String,
+           context: request::Context<'b>)
+           -> Self {
+        let (cmd, args, opts) = parse;
         let req = request::Request::new(cmd, args, opts, context);
-        Ok(CommandInvocation {
+        CommandInvocation {
             request: req,
             command: cmd,
-        })
+            path: path,
+        }
     }
 
-    fn run(&mut self) -> Result<(), String> {
-        self.command.run(&mut self.request)
+    fn run(&mut self) -> Result<(), CommandError> {
+        commands::dispatch(&self.request, &self.path)
     }
 }
 
 fn main() {
-    env_logger::init().unwrap();
-
     let root = ipfs_commands::root::make_command();
 
-    let path = match fsrepo::best_known_path() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    // errors are printed in the encoding asked for, even if it's the command
+    // line that's wrong
+    let encoding = Encoding::requested(&args[..]);
+
+    // the command line is parsed before the repo is opened, since it may say
+    // which repo to open
+    let parse = match commands::cli::parse(args.clone().into_iter(), &*root) {
         Err(e) => {
-            println!("{}", e);
-            return;
+            // a command that isn't built in may be a plugin's
+            if let Some(plugin) = plugin::find(&*root, &args[..]) {
+                match plugin::run(&plugin) {
+                    Ok(code) => process::exit(code),
+                    Err(e) => exit_with(e, encoding),
+                }
+            }
+            exit_with(CommandError::Client(e), encoding)
+        }
+        Ok(parse) => parse,
+    };
+
+    // logging is set up once the command line is known, since --debug turns it on
+    if parse.2.iter().any(|&(name, _)| name == "debug") && env::var_os("RUST_LOG").is_none() {
+        env::set_var("RUST_LOG", "debug");
+    }
+    env_logger::init().unwrap();
+
+    let repo_dir = parse.2.iter().filter_map(|&(name, ref opt)| {
+        match *opt {
+            request::Opt::String(ref dir) if name == "repo-dir" => Some(dir.clone()),
+            _ => None,
         }
+    }).last();
+
+    let path = match fsrepo::best_known_path(repo_dir.as_ref().map(|dir| &dir[..])) {
+        Err(e) => exit_with(CommandError::Client(e), encoding),
         Ok(path) => path,
     };
 
-    let node = match construct_node(path.clone()) {
-        Err(e) => {
-            println!("{}", e);
-            return;
+    // with a daemon up, it runs the command on the repo instead
+    let command_path = crashreport::command_path(&*root, &args[..]);
+    if !api::runs_locally(&command_path) {
+        match api::forward_to_daemon(&path, &command_path, &parse, true, &mut io::stdout()) {
+            None => {}
+            Some(Ok(())) => return,
+            Some(Err(e)) => exit_with(e, encoding),
         }
+    }
+
+    let node = match construct_node(path.clone()) {
+        Err(e) => exit_with(CommandError::Internal(e), encoding),
         Ok(node) => node,
     };
 
-    let context = request::Context::new(path, node.as_ref());
+    let online = !parse.2.iter().any(|&(name, _)| name == "offline");
+    let read_only = parse.2.iter().any(|&(name, _)| name == "read-only");
+    let context = request::Context::new(path.clone(), node.as_ref(), read_only, online);
 
-    let mut invoc = match CommandInvocation::from_cli_parse(env::args().skip(1),
-                                                            &*root,
-                                                            context) {
-        Err(e) => {
-            println!("{}", e);
-            return;
-        }
-        Ok(invoc) => invoc,
+    // reports are still saved offline, just not submitted, and aren't saved
+    // to a read-only repo
+    let endpoint = if online {
+        node.as_ref()
+            .and_then(|n| n.config.report_endpoint())
+            .map(|e| e.to_string())
+    } else {
+        None
+    };
+    let proxy = node.as_ref()
+                    .and_then(|n| n.config.http_proxy())
+                    .map(|p| p.to_string());
+    let reports_repo = if context.read_only {
+        None
+    } else {
+        Some(path.clone())
     };
+    crashreport::install_hook(reports_repo,
+                              command_path.clone(),
+                              endpoint,
+                              proxy,
+                              context.clock.clone());
+
+    let mut invoc = CommandInvocation::new(parse, command_path.clone(), context);
+
+    let trace_file = invoc.request.option_str("trace-file").map(PathBuf::from);
+    let tracing = trace_file.is_some() || invoc.request.option_bool("trace");
+    if tracing {
+        trace::enable();
+    }
+
+    let (events, received) = mpsc::channel();
+    let bar = progress::show_on_stderr(received);
+    invoc.request.set_progress(events);
+
+    let started = invoc.request.context.clock.monotonic();
+    let result = invoc.run();
+    invoc.request.end_progress();
+    let _ = bar.join();
+    if let Err(ref e) = result {
+        println!("{}", encoding.error_line(e));
+    }
+
+    record_stats(&invoc,
+                 audit::ACTOR_CLI,
+                 command_path.clone(),
+                 path.clone(),
+                 started,
+                 &result);
+    record_audit(&invoc, audit::ACTOR_CLI, command_path, path, &result);
 
-    match invoc.run() {
-        Err(e) => println!("{}", e),
-        _ => {}
+    if tracing {
+        if let Err(e) = write_trace(trace::finish(), trace_file) {
+            let _ = writeln!(io::stderr(), "{}", e);
+        }
+    }
+
+    if let Err(e) = result {
+        process::exit(e.exit_code());
+    }
+}
+
+fn exit_with(e: CommandError, encoding: Encoding) -> ! {
+    println!("{}", encoding.error_line(&e));
+    process::exit(e.exit_code())
+}
+
+// Records the invocation in the audit log if the command may have modified
+// the repo. A read-only repo isn't written to, and refused the command anyway.
+fn record_audit(invoc: &CommandInvocation,
+                actor: &str,
+                command_path: String,
+                repo_path: PathBuf,
+                result: &Result<(), CommandError>) {
+    // a failed init leaves no repo to record it in
+    if !invoc.command.is_mutating() || invoc.request.context.refuses(invoc.command) ||
+       !fsrepo::is_initialized(repo_path.clone()).unwrap_or(false) {
+        return;
+    }
+    let entry = audit::AuditEntry::new(actor, command_path, &invoc.request, result);
+    if let Err(e) = audit::record(repo_path, &entry) {
+        let _ = writeln!(io::stderr(), "Could not record command in the audit log: {}", e);
+    }
+}
+
+// Records how long the command took and whether it failed, unless the repo
+// mustn't be written to
+fn record_stats(invoc: &CommandInvocation,
+                actor: &str,
+                command_path: String,
+                repo_path: PathBuf,
+                started: Duration,
+                result: &Result<(), CommandError>) {
+    if invoc.request.context.read_only ||
+       !fsrepo::is_initialized(repo_path.clone()).unwrap_or(false) {
+        return;
+    }
+    let exit_code = result.as_ref().err().map_or(0, |e| e.exit_code());
+    let sample = stats::Sample::new(actor,
+                                    command_path,
+                                    started,
+                                    &*invoc.request.context.clock,
+                                    exit_code);
+    if let Err(e) = stats::record(repo_path, &sample) {
+        let _ = writeln!(io::stderr(), "Could not record command stats: {}", e);
+    }
+}
+
+// How the command lines of a session, like `shell`'s or `batch`'s, reach the
+// repo. With a daemon serving it they're sent to the daemon, as they would be
+// from the command line, so they can't modify the repo at the same time as
+// the daemon's commands. Otherwise they run in the session's process, which
+// holds the repo lock until the session ends, so that no daemon can start
+// under it.
+pub enum Session {
+    Daemon,
+    // no lock is held by a read-only session, which doesn't write the repo,
+    // for a repo that isn't initialized yet, or by a session run inside
+    // another that holds it already
+    Local(Option<SessionLock>),
+}
+
+// how many sessions in this process hold the repo lock. A session inside
+// another mustn't take the lock again: it's an fcntl lock, which is released
+// when any file it was taken through is closed.
+static SESSIONS_LOCKED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+pub struct SessionLock {
+    _lock: fsrepo::RepoLock,
+}
+
+impl Drop for SessionLock {
+    fn drop(&mut self) {
+        SESSIONS_LOCKED.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Session {
+    pub fn start(repo_path: &Path, read_only: bool) -> Result<Self, CommandError> {
+        match fsrepo::read_api_file(repo_path) {
+            Ok(Some(_)) => Ok(Session::Daemon),
+            Ok(None) => Session::local(repo_path, read_only),
+            Err(e) => Err(CommandError::Internal(e)),
+        }
+    }
+
+    fn local(repo_path: &Path, read_only: bool) -> Result<Self, CommandError> {
+        if read_only || SESSIONS_LOCKED.load(Ordering::SeqCst) > 0 ||
+           !try!(fsrepo::is_initialized(repo_path.to_path_buf())) {
+            return Ok(Session::Local(None));
+        }
+        let lock = try!(fsrepo::lock_repo(repo_path).map_err(CommandError::Client));
+        SESSIONS_LOCKED.fetch_add(1, Ordering::SeqCst);
+        Ok(Session::Local(Some(SessionLock { _lock: lock })))
+    }
+}
+
+// Parses and runs one command line of a session against a repo that is
+// already open, writing its output to `out`. The process's stdin belongs to
+// the session, so the line doesn't get to read it.
+fn run_line(root: &commands::Command,
+            args: Vec<String>,
+            context: request::Context,
+            session: &mut Session,
+            out: &mut Write)
+            -> Result<(), CommandError> {
+    let command_path = crashreport::command_path(root, &args[..]);
+    // it would take the lock the session holds, or serve the repo while the
+    // session modifies it
+    if command_path == "daemon" {
+        return Err(CommandError::Client("The daemon can't be started from a shell or batch \
+                                         session"
+                                            .to_string()));
+    }
+    let mut parse = try!(commands::cli::parse(args.into_iter(), root)
+                             .map_err(CommandError::Client));
+
+    if let Session::Daemon = *session {
+        if !api::runs_locally(&command_path) {
+            // the session's own --read-only and --offline hold for its lines
+            if context.read_only_requested && !parse.2.iter().any(|&(n, _)| n == "read-only") {
+                parse.2.push(("read-only", request::Opt::Bool(true)));
+            }
+            if !context.online && !parse.2.iter().any(|&(n, _)| n == "offline") {
+                parse.2.push(("offline", request::Opt::Bool(true)));
+            }
+            match api::forward_to_daemon(&context.repo_dir, &command_path, &parse, false, out) {
+                Some(result) => return result,
+                // the daemon is gone, so the rest of the session runs here
+                None => *session = try!(Session::local(&context.repo_dir, context.read_only)),
+            }
+        }
+    }
+
+    let (events, received) = mpsc::channel();
+    let bar = progress::show_on_stderr(received);
+    let output = response::SharedBuffer::new();
+    let result = run_parsed(parse,
+                            command_path,
+                            context,
+                            audit::ACTOR_CLI,
+                            Box::new(output.clone()),
+                            false,
+                            events);
+    let _ = bar.join();
+    try!(out.write_all(&output.contents()[..])
+            .and_then(|_| out.flush())
+            .map_err(|e| format!("Error writing output: {}", e)));
+    result
+}
+
+// Runs a parsed command line on behalf of `actor`, with its output going to
+// `out` and, with --progress, its progress to `events`, and records it like
+// any other command. Unless `stdin` is set, the command runs as if stdin were
+// a terminal, so it never reads the process's.
+fn run_parsed(parse: commands::cli::ParseResult,
+              command_path: String,
+              context: request::Context,
+              actor: &str,
+              out: Box<Write + Send>,
+              stdin: bool,
+              events: mpsc::Sender<progress::Event>)
+              -> Result<(), CommandError> {
+    let repo_path = context.repo_dir.clone();
+    let mut invoc = CommandInvocation::new(parse, command_path.clone(), context);
+    if !stdin {
+        invoc.request.set_stdin(None);
+    }
+    invoc.request.set_output(out);
+    invoc.request.set_progress(events);
+    let started = invoc.request.context.clock.monotonic();
+    let result = invoc.run();
+    record_stats(&invoc, actor, command_path.clone(), repo_path.clone(), started, &result);
+    record_audit(&invoc, actor, command_path, repo_path, &result);
+    result
+}
+
+// Writes the trace as JSON to `file` if given, else as a tree to stderr
+fn write_trace(spans: Vec<trace::SpanRecord>, file: Option<PathBuf>) -> Result<(), String> {
+    match file {
+        Some(path) => {
+            let mut f = try!(File::create(&path).map_err(|e| {
+                format!("Error creating trace file {:?}: {}", path, e)
+            }));
+            write!(f, "{}", spans.to_json())
+                .map_err(|e| format!("Error writing trace file {:?}: {}", path, e))
+        }
+        None => {
+            let stderr = io::stderr();
+            let mut stderr = stderr.lock();
+            for span in spans.iter() {
+                try!(span.write_tree(&mut stderr, 0)
+                         .map_err(|e| format!("Error writing trace: {}", e)));
+            }
+            Ok(())
+        }
     }
 }
 
@@ -96,10 +407,16 @@ fn construct_node(repo_path: PathBuf) -> Result<Option<IpfsNode>, String> {
     if !try!(fsrepo::is_initialized(repo_path.clone())) {
         return Ok(None);
     }
+    try!(fsrepo::check_version(repo_path.clone()));
     let config_path = config::repo_path_to_config_file(repo_path.clone());
     let config = try!(fsrepo::read_config_file(&config_path));
+    let sizes = merkledag::sizes::SizeIndex::new(repo_path.clone());
     let mut blockstore_path = repo_path;
     blockstore_path.push(blockstore::BLOCKSTORE_DIR);
-    let bs = Blockstore::new(blockstore_path);
-    Ok(Some(IpfsNode::new(bs, config)))
+    let mut bs = Blockstore::with_compression(blockstore_path, config.compress_blocks())
+                     .with_delta_encoding(config.delta_blocks());
+    if let Some(encryption) = config.encryption() {
+        bs = bs.with_cipher(try!(crypto::load_repo_cipher(encryption)));
+    }
+    Ok(Some(IpfsNode::new(bs, sizes, config)))
 }
@@ -1,7 +1,11 @@
 mod pb;
+pub mod sizes;
 
 use block::Block;
 use blockstore::Blockstore;
+use hash::HashFn;
+use self::sizes::SizeIndex;
+use trace;
 use util;
 
 use protobuf::{self, Message, RepeatedField};
@@ -23,6 +27,15 @@ pub struct Node {
 }
 
 impl Link {
+    pub fn new(name: String, hash: Multihash, target_size: u64) -> Self {
+        Link {
+            name: name,
+            hash: hash,
+            target_size: target_size,
+            node: None,
+        }
+    }
+
     pub fn get_name(&self) -> &str {
         &self.name
     }
@@ -91,12 +104,28 @@ impl Node {
         }
     }
 
+    // The multihash of the node under the given hash function. Unlike
+    // `multihash` this isn't cached, since the cache holds the sha2-256 hash.
+    pub fn multihash_with(&self, hash_fn: &HashFn) -> Multihash {
+        let mut buf = Vec::new();
+        self.encode_to_writer(&mut buf);
+        hash_fn.multihash(&buf[..])
+    }
+
     fn calculate_multihash(&self) -> Multihash {
         let mut buf = Vec::new();
         self.encode_to_writer(&mut buf);
         util::hash(&buf[..])
     }
 
+    pub fn new(data: Vec<u8>, links: Vec<Link>) -> Self {
+        Node {
+            data: data,
+            links: links,
+            multihash: RwLock::new(None),
+        }
+    }
+
     // creates a Node with no Links, only data
     pub fn from_data(data: Vec<u8>) -> Self {
         Node {
@@ -146,22 +175,74 @@ impl Node {
 // TODO: wrap a Blockservice instead
 pub struct DagService {
     blockstore: Arc<Blockstore>,
+    sizes: SizeIndex,
 }
 
 impl DagService {
-    pub fn new(blockstore: Arc<Blockstore>) -> Self {
-        DagService { blockstore: blockstore }
+    pub fn new(blockstore: Arc<Blockstore>, sizes: SizeIndex) -> Self {
+        DagService {
+            blockstore: blockstore,
+            sizes: sizes,
+        }
     }
 
     pub fn add<'a>(&self, node: &'a Node) -> Result<Multihash, String> {
+        let _span = trace::span("DagService::add");
         let hash = node.multihash();
+        self.put(node, hash)
+    }
+
+    pub fn add_with(&self, node: &Node, hash_fn: &HashFn) -> Result<Multihash, String> {
+        let _span = trace::span("DagService::add");
+        let hash = node.multihash_with(hash_fn);
+        self.put(node, hash)
+    }
+
+    fn put(&self, node: &Node, hash: Multihash) -> Result<Multihash, String> {
         let mut data = Vec::new();
         try!(node.encode_to_writer(&mut data));
         try!(self.blockstore.put(&hash, &data[..]));
+
+        // the children of a node are usually added before it, so their sizes
+        // are already known. If one isn't stored at all, the size is left to
+        // be computed once it is.
+        match self.links_size(node, true) {
+            Ok(links_size) => try!(self.sizes.insert(&hash, data.len() as u64 + links_size)),
+            Err(e) => {
+                trace!("DagService::put, no cumulative size for {}: {}", hash, e);
+            }
+        }
         Ok(hash)
     }
 
+    fn links_size(&self, node: &Node, record: bool) -> Result<u64, String> {
+        let mut size = 0;
+        for link in node.get_links() {
+            size += try!(self.cumulative_size(&link.clone_hash(), record));
+        }
+        Ok(size)
+    }
+
+    // The size of the block for `hash` plus everything under it, from the
+    // size index, or by walking the DAG if it isn't indexed yet. The sizes
+    // walked for are added to the index if `record` is set, which it can't be
+    // for a read-only repo.
+    pub fn cumulative_size(&self, hash: &Multihash, record: bool) -> Result<u64, String> {
+        if let Some(size) = try!(self.sizes.get(hash)) {
+            return Ok(size);
+        }
+        let _span = trace::span("DagService::cumulative_size");
+        let block = try!(self.blockstore.get(hash));
+        let node = try!(Node::from_reader(&mut block.get_data()));
+        let size = block.get_data().len() as u64 + try!(self.links_size(&node, record));
+        if record {
+            try!(self.sizes.insert(hash, size));
+        }
+        Ok(size)
+    }
+
     pub fn get(&self, hash: &Multihash) -> Result<Arc<Node>, String> {
+        let _span = trace::span("DagService::get");
         let block = try!(self.blockstore.get(hash));
         let data = block.take_data();
         Node::from_reader(&mut &data[..]).map(|node| Arc::new(node))
@@ -0,0 +1,83 @@
+// An index of the cumulative size of each DAG in the repo: the size of a
+// node's block plus the cumulative sizes of everything it links to. Sizes are
+// recorded as nodes are added, so listings can report them without walking
+// the DAGs again. The index is kept in <repo>/sizes.index as one
+// "<hash> <size>" pair per line, and loaded on first use.
+
+use hash;
+
+use rust_multihash::Multihash;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub const SIZE_INDEX_FILE: &'static str = "sizes.index";
+
+pub struct SizeIndex {
+    path: PathBuf,
+    sizes: Mutex<Option<HashMap<Multihash, u64>>>,
+}
+
+impl SizeIndex {
+    pub fn new(mut repo_path: PathBuf) -> Self {
+        repo_path.push(SIZE_INDEX_FILE);
+        SizeIndex {
+            path: repo_path,
+            sizes: Mutex::new(None),
+        }
+    }
+
+    fn load(&self) -> Result<HashMap<Multihash, u64>, String> {
+        let mut sizes = HashMap::new();
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(_) => return Ok(sizes),
+        };
+        for line in BufReader::new(file).lines() {
+            let line = try!(line.map_err(|e| {
+                format!("Error reading size index {:?}: {}", self.path, e)
+            }));
+            let mut fields = line.split_whitespace();
+            // a line cut short by an interrupted write is skipped; the size
+            // is recomputed when it's next needed
+            if let (Some(hash), Some(size)) = (fields.next(), fields.next()) {
+                if let (Ok(hash), Ok(size)) = (hash::parse_multihash(hash), size.parse()) {
+                    sizes.insert(hash, size);
+                }
+            }
+        }
+        Ok(sizes)
+    }
+
+    pub fn get(&self, hash: &Multihash) -> Result<Option<u64>, String> {
+        let mut sizes = self.sizes.lock().unwrap();
+        if sizes.is_none() {
+            *sizes = Some(try!(self.load()));
+        }
+        Ok(sizes.as_ref().unwrap().get(hash).cloned())
+    }
+
+    pub fn insert(&self, hash: &Multihash, size: u64) -> Result<(), String> {
+        if try!(self.get(hash)) == Some(size) {
+            return Ok(());
+        }
+
+        let mut sizes = self.sizes.lock().unwrap();
+        let mut file = try!(OpenOptions::new()
+                                .append(true)
+                                .create(true)
+                                .open(&self.path)
+                                .map_err(|e| {
+                                    format!("Error opening size index {:?}: {}", self.path, e)
+                                }));
+        // in one write, so that a line appended by another process at the
+        // same time doesn't end up in the middle of it
+        try!(file.write_all(format!("{} {}\n", hash, size).as_bytes()).map_err(|e| {
+            format!("Error writing size index {:?}: {}", self.path, e)
+        }));
+        sizes.as_mut().unwrap().insert(hash.clone(), size);
+        Ok(())
+    }
+}
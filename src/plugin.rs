@@ -0,0 +1,142 @@
+// External commands. A top-level command that isn't built in is looked up on
+// the PATH as an executable named forty_situps-<command>, which is run with
+// the rest of the command line, so
+//
+//     ipfs --repo-dir /srv/ipfs pack --level 3 foo
+//
+// runs `forty_situps-pack --level 3 foo`. The global options before the
+// command aren't passed on as they are; what a plugin needs to know of them
+// is in its environment instead: the path of the repo, from --repo-dir,
+// $IPFS_PATH or the home dir, the address of the repo's daemon API if a
+// daemon is running, and whether --read-only or --offline was given.
+
+use commands::{Command, CommandError, OptType};
+use fsrepo;
+use util;
+
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+pub const PLUGIN_PREFIX: &'static str = "forty_situps-";
+
+// the environment variables a plugin is run with
+pub const ENV_NAME_REPO_PATH: &'static str = "FORTY_SITUPS_REPO_PATH";
+pub const ENV_NAME_API_ADDR: &'static str = "FORTY_SITUPS_API_ADDR"; // unset without a daemon
+pub const ENV_NAME_READ_ONLY: &'static str = "FORTY_SITUPS_READ_ONLY"; // 1 if set
+pub const ENV_NAME_OFFLINE: &'static str = "FORTY_SITUPS_OFFLINE"; // 1 if set
+
+pub struct Plugin {
+    pub executable: PathBuf,
+    pub args: Vec<String>, // everything after the command's name
+    // the global options before it
+    repo_dir: Option<String>, // the last --repo-dir
+    read_only: bool,
+    offline: bool,
+}
+
+// The plugin `args` asks for, if its first command isn't one of root's
+// subcommands and an executable for it is on the PATH
+pub fn find(root: &Command, args: &[String]) -> Option<Plugin> {
+    let mut repo_dir = None;
+    let mut read_only = false;
+    let mut offline = false;
+    let mut tokens = args.iter();
+    while let Some(token) = tokens.next() {
+        if token == "--" {
+            return None;
+        }
+        if !token.starts_with("-") || token == "-" {
+            if root.get_subcommand(token).is_some() {
+                return None;
+            }
+            return lookup(token).map(|executable| {
+                Plugin {
+                    executable: executable,
+                    args: tokens.cloned().collect(),
+                    repo_dir: repo_dir,
+                    read_only: read_only,
+                    offline: offline,
+                }
+            });
+        }
+
+        let body = token.trim_left_matches('-');
+        let (name, value) = match body.find('=') {
+            Some(i) => (&body[..i], Some(body[i + 1..].to_string())),
+            None => (body, None),
+        };
+        // a group of single-letter options like -qQ takes a value if its
+        // last letter does
+        let last = if token.starts_with("--") {
+            String::new()
+        } else {
+            name.chars().last().map(|c| c.to_string()).unwrap_or(String::new())
+        };
+        let takes_value = match root.get_options()
+                                    .find(|&(opt_name, _)| opt_name == name || opt_name == last) {
+            Some((_, opt)) => {
+                match opt.opt_type {
+                    OptType::Bool => false,
+                    _ => true,
+                }
+            }
+            None => false,
+        };
+        let value = if takes_value && value.is_none() {
+            tokens.next().cloned()
+        } else {
+            value
+        };
+        match name {
+            "repo-dir" => repo_dir = value,
+            "read-only" => read_only = value.map_or(true, |value| value == "true"),
+            "offline" => offline = value.map_or(true, |value| value == "true"),
+            _ => {}
+        }
+    }
+    None
+}
+
+// The first executable named after the command on the PATH
+fn lookup(name: &str) -> Option<PathBuf> {
+    // a name that's a path could run anything
+    if name.is_empty() || name.contains('/') || name.contains('\\') {
+        return None;
+    }
+    let file_name = format!("{}{}{}", PLUGIN_PREFIX, name, env::consts::EXE_SUFFIX);
+    let path = match env::var_os("PATH") {
+        Some(path) => path,
+        None => return None,
+    };
+    env::split_paths(&path).map(|dir| dir.join(&file_name)).find(|file| util::is_executable(file))
+}
+
+// Runs the plugin with this process's stdin, stdout and stderr, and returns
+// the code it exited with
+pub fn run(plugin: &Plugin) -> Result<i32, CommandError> {
+    let repo_path = try!(fsrepo::best_known_path(plugin.repo_dir.as_ref().map(|dir| &dir[..]))
+                             .map_err(CommandError::Client));
+    let api_addr = try!(fsrepo::read_api_file(&repo_path).map_err(CommandError::Internal));
+
+    let mut command = process::Command::new(&plugin.executable);
+    command.args(&plugin.args).env(ENV_NAME_REPO_PATH, &repo_path);
+    // none of these are inherited from a plugin that runs another
+    command.env_remove(ENV_NAME_API_ADDR)
+           .env_remove(ENV_NAME_READ_ONLY)
+           .env_remove(ENV_NAME_OFFLINE);
+    if let Some(addr) = api_addr {
+        command.env(ENV_NAME_API_ADDR, addr);
+    }
+    if plugin.read_only {
+        command.env(ENV_NAME_READ_ONLY, "1");
+    }
+    if plugin.offline {
+        command.env(ENV_NAME_OFFLINE, "1");
+    }
+    let status = try!(command.status().map_err(|e| {
+        format!("Error running {}: {}", plugin.executable.display(), e)
+    }));
+    // killed by a signal
+    Ok(status.code().unwrap_or(1))
+}
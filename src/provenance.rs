@@ -0,0 +1,146 @@
+// Provenance records for imported content: where it came from, when it was
+// imported and a signature by the importing node's key. Each record is stored
+// as a DAG node, with the JSON-encoded record as its data and a "content" link
+// to the imported object. <repo>/provenance.index maps content hashes to their
+// records, one "<content> <record>" pair per line, so they can be listed again.
+
+use clock::Clock;
+use core::IpfsNode;
+use crypto;
+use hash;
+use merkledag::{Link, Node};
+use util;
+
+use rust_multihash::Multihash;
+use rustc_serialize::base64::{self, FromBase64, ToBase64};
+use rustc_serialize::json;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+const INDEX_FILE: &'static str = "provenance.index";
+const CONTENT_LINK: &'static str = "content";
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct Provenance {
+    pub source: String, // the path or URL the content was imported from
+    pub time: u64, // seconds since the unix epoch
+    pub importer: String, // the peer id of the importing node
+    // base64-encoded, so the signature can be checked without the
+    // importer's config
+    pub public_key: String,
+    pub signature: String,
+}
+
+// What the signature covers, binding the record to the content it describes
+fn signed_bytes(content: &Multihash, source: &str, time: u64) -> Vec<u8> {
+    format!("{}\n{}\n{}", content, source, time).into_bytes()
+}
+
+impl Provenance {
+    // A record of `content` being imported from `source` now, as `clock` has it
+    pub fn new(node: &IpfsNode,
+               content: &Multihash,
+               source: String,
+               clock: &Clock)
+               -> Result<Self, String> {
+        let time = clock.unix_time();
+        let (signature, public_key) = try!(crypto::sign(&node.config.identity,
+                                                        &signed_bytes(content, &source, time)));
+        Ok(Provenance {
+            source: source,
+            time: time,
+            importer: format!("{}", node.config.identity.peer_id),
+            public_key: public_key.to_base64(base64::STANDARD),
+            signature: signature.to_base64(base64::STANDARD),
+        })
+    }
+
+    // Whether the signature was made for `content` by the key of the
+    // importer the record names
+    pub fn verify(&self, content: &Multihash) -> bool {
+        match (self.public_key.from_base64(), self.signature.from_base64()) {
+            (Ok(public_key), Ok(signature)) => {
+                format!("{}", util::hash(&public_key[..])) == self.importer &&
+                crypto::verify(&public_key[..],
+                               &signed_bytes(content, &self.source, self.time),
+                               &signature[..])
+            }
+            _ => false,
+        }
+    }
+}
+
+fn index_file(mut repo_path: PathBuf) -> PathBuf {
+    repo_path.push(INDEX_FILE);
+    repo_path
+}
+
+// Stores the record and adds it to the index, returning the record's hash
+pub fn record(node: &IpfsNode,
+              repo_path: PathBuf,
+              content: &Multihash,
+              provenance: &Provenance)
+              -> Result<Multihash, String> {
+    let encoded = try!(json::encode(provenance)
+                           .map_err(|e| format!("Error encoding provenance record: {}", e)));
+    let content_size = try!(node.blockstore.get(content)).get_data().len() as u64;
+    let record_node = Node::new(encoded.into_bytes(),
+                                vec![Link::new(CONTENT_LINK.to_string(),
+                                               content.clone(),
+                                               content_size)]);
+    let record_hash = try!(node.dagservice.add(&record_node));
+
+    let path = index_file(repo_path);
+    let mut file = try!(OpenOptions::new()
+                            .append(true)
+                            .create(true)
+                            .open(&path)
+                            .map_err(|e| {
+                                format!("Error opening provenance index {:?}: {}", path, e)
+                            }));
+    try!(writeln!(file, "{} {}", content, record_hash).map_err(|e| {
+        format!("Error writing provenance index {:?}: {}", path, e)
+    }));
+    Ok(record_hash)
+}
+
+// The records for `content`, oldest first, along with their hashes
+pub fn list(node: &IpfsNode,
+            repo_path: PathBuf,
+            content: &Multihash)
+            -> Result<Vec<(Multihash, Provenance)>, String> {
+    let path = index_file(repo_path);
+    if !try!(util::file_exists(&path).map_err(|e| format!("{}", e))) {
+        return Ok(vec![]);
+    }
+
+    let file = try!(File::open(&path).map_err(|e| {
+        format!("Error opening provenance index {:?}: {}", path, e)
+    }));
+    let content = format!("{}", content);
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = try!(line.map_err(|e| {
+            format!("Error reading provenance index {:?}: {}", path, e)
+        }));
+        let mut fields = line.split_whitespace();
+        if fields.next() != Some(&content[..]) {
+            continue;
+        }
+        let record_hash = match fields.next() {
+            Some(h) => try!(hash::parse_multihash(h)),
+            None => continue,
+        };
+
+        let record_node = try!(node.dagservice.get(&record_hash));
+        let data = try!(String::from_utf8(record_node.get_data().to_vec()).map_err(|e| {
+            format!("Provenance record {} is not valid UTF-8: {}", record_hash, e)
+        }));
+        let provenance = try!(json::decode(&data).map_err(|e| {
+            format!("Error decoding provenance record {}: {}", record_hash, e)
+        }));
+        records.push((record_hash, provenance));
+    }
+    Ok(records)
+}
@@ -0,0 +1,219 @@
+// How long each command took and how it ended, so slow or failing commands
+// can be spotted. Each line of <repo>/logs/commands.log is one JSON-encoded
+// sample; `ipfs stats commands` summarizes them into histograms.
+
+use clock::Clock;
+use fsrepo;
+use util;
+
+use rustc_serialize::json::{self, Json, ToJson};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+const COMMANDS_LOG_FILE: &'static str = "commands.log";
+
+// Upper bounds of the latency histogram buckets, in milliseconds. Samples
+// above the last bound only count towards the total.
+pub const LATENCY_BUCKETS: &'static [u64] = &[1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000,
+                                              10000];
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct Sample {
+    pub command: String,
+    pub actor: String,
+    pub millis: u64,
+    pub exit_code: i32, // 0 if the command succeeded
+}
+
+impl Sample {
+    // A sample of a command that started when `clock` read `started`, as
+    // Clock::monotonic, and has just ended
+    pub fn new(actor: &str,
+               command: String,
+               started: Duration,
+               clock: &Clock,
+               exit_code: i32)
+               -> Self {
+        let elapsed = clock.monotonic() - started;
+        Sample {
+            command: command,
+            actor: actor.to_string(),
+            millis: elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1000000) as u64,
+            exit_code: exit_code,
+        }
+    }
+}
+
+// The samples of one command, bucketed
+pub struct CommandStats {
+    pub count: u64,
+    pub errors: u64,
+    pub total_millis: u64,
+    pub max_millis: u64,
+    pub buckets: Vec<u64>, // cumulative counts, one per bound in LATENCY_BUCKETS
+}
+
+impl CommandStats {
+    fn new() -> Self {
+        CommandStats {
+            count: 0,
+            errors: 0,
+            total_millis: 0,
+            max_millis: 0,
+            buckets: vec![0; LATENCY_BUCKETS.len()],
+        }
+    }
+
+    fn add(&mut self, sample: &Sample) {
+        self.count += 1;
+        if sample.exit_code != 0 {
+            self.errors += 1;
+        }
+        self.total_millis += sample.millis;
+        if sample.millis > self.max_millis {
+            self.max_millis = sample.millis;
+        }
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(self.buckets.iter_mut()) {
+            if sample.millis <= *bound {
+                *count += 1;
+            }
+        }
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.errors as f64 / self.count as f64
+        }
+    }
+
+    pub fn mean_millis(&self) -> u64 {
+        if self.count == 0 {
+            0
+        } else {
+            self.total_millis / self.count
+        }
+    }
+}
+
+impl ToJson for CommandStats {
+    fn to_json(&self) -> Json {
+        let mut buckets = BTreeMap::new();
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(self.buckets.iter()) {
+            buckets.insert(format!("{}", bound), count.to_json());
+        }
+        let mut obj = BTreeMap::new();
+        obj.insert("count".to_string(), self.count.to_json());
+        obj.insert("errors".to_string(), self.errors.to_json());
+        obj.insert("error_rate".to_string(), self.error_rate().to_json());
+        obj.insert("mean_millis".to_string(), self.mean_millis().to_json());
+        obj.insert("max_millis".to_string(), self.max_millis.to_json());
+        obj.insert("buckets".to_string(), Json::Object(buckets));
+        Json::Object(obj)
+    }
+}
+
+fn log_file(mut repo_path: PathBuf) -> PathBuf {
+    repo_path.push(fsrepo::LOGS_DIR);
+    repo_path.push(COMMANDS_LOG_FILE);
+    repo_path
+}
+
+pub fn record(repo_path: PathBuf, sample: &Sample) -> Result<(), String> {
+    let mut logs_dir = repo_path.clone();
+    logs_dir.push(fsrepo::LOGS_DIR);
+    try!(util::ensure_dir_writable(&logs_dir)
+             .map_err(|e| format!("Error checking writability of logs dir: {}", e)));
+
+    let encoded = try!(json::encode(sample)
+                           .map_err(|e| format!("Error encoding command sample: {}", e)));
+    let path = log_file(repo_path);
+    let mut file = try!(OpenOptions::new()
+                            .append(true)
+                            .create(true)
+                            .open(&path)
+                            .map_err(|e| format!("Error opening command log {:?}: {}", path, e)));
+    // in one write, so that commands finishing at the same time, e.g. in the
+    // daemon, don't interleave their lines
+    file.write_all(format!("{}\n", encoded).as_bytes())
+        .map_err(|e| format!("Error writing command log {:?}: {}", path, e))
+}
+
+// The recorded samples, summarized by command
+pub fn summarize(repo_path: PathBuf) -> Result<BTreeMap<String, CommandStats>, String> {
+    let mut stats = BTreeMap::new();
+    let path = log_file(repo_path);
+    if !try!(util::file_exists(&path).map_err(|e| format!("{}", e))) {
+        return Ok(stats);
+    }
+
+    let file = try!(File::open(&path)
+                        .map_err(|e| format!("Error opening command log {:?}: {}", path, e)));
+    for line in BufReader::new(file).lines() {
+        let line = try!(line.map_err(|e| format!("Error reading command log {:?}: {}", path, e)));
+        if line.is_empty() {
+            continue;
+        }
+        let sample: Sample = try!(json::decode(&line).map_err(|e| {
+            format!("Error decoding command sample {:?}: {}", line, e)
+        }));
+        stats.entry(sample.command.clone()).or_insert_with(CommandStats::new).add(&sample);
+    }
+    Ok(stats)
+}
+
+// Writes the stats in the Prometheus text exposition format, e.g. for the
+// node exporter's textfile collector
+pub fn write_prometheus(out: &mut Write, stats: &BTreeMap<String, CommandStats>) -> io::Result<()> {
+    try!(writeln!(out, "# HELP ipfs_command_duration_seconds How long commands took to run."));
+    try!(writeln!(out, "# TYPE ipfs_command_duration_seconds histogram"));
+    for (command, s) in stats {
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(s.buckets.iter()) {
+            try!(writeln!(out,
+                          "ipfs_command_duration_seconds_bucket{{command=\"{}\",le=\"{}\"}} {}",
+                          command,
+                          *bound as f64 / 1000.0,
+                          count));
+        }
+        try!(writeln!(out,
+                      "ipfs_command_duration_seconds_bucket{{command=\"{}\",le=\"+Inf\"}} {}",
+                      command,
+                      s.count));
+        try!(writeln!(out,
+                      "ipfs_command_duration_seconds_sum{{command=\"{}\"}} {}",
+                      command,
+                      s.total_millis as f64 / 1000.0));
+        try!(writeln!(out,
+                      "ipfs_command_duration_seconds_count{{command=\"{}\"}} {}",
+                      command,
+                      s.count));
+    }
+
+    try!(writeln!(out, "# HELP ipfs_command_errors_total How many runs of a command failed."));
+    try!(writeln!(out, "# TYPE ipfs_command_errors_total counter"));
+    for (command, s) in stats {
+        try!(writeln!(out, "ipfs_command_errors_total{{command=\"{}\"}} {}", command, s.errors));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sample;
+    use clock::{Clock, MockClock};
+
+    use std::time::Duration;
+
+    #[test]
+    fn samples_take_their_latency_from_the_clock() {
+        let clock = MockClock::new(1500000000);
+        let started = clock.monotonic();
+        clock.advance(Duration::from_millis(250));
+        let sample = Sample::new("cli", "add".to_string(), started, &clock, 0);
+        assert_eq!(sample.millis, 250);
+    }
+}
@@ -0,0 +1,106 @@
+// Output templates for listing commands, e.g. `--format "<hash>\t<size>"`.
+// Each <field> is replaced with the value of that field for the listed item.
+// The escapes \t, \n and \\ are understood, since shells pass them literally.
+
+use std::collections::HashMap;
+
+enum Part {
+    Literal(String),
+    Field(String),
+}
+
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    // `fields` lists the field names the command provides. Referring to any
+    // other field is an error, so a typo doesn't silently print nothing.
+    pub fn parse(s: &str, fields: &[&str]) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+
+        loop {
+            let c = match chars.next() {
+                None => break,
+                Some(c) => c,
+            };
+
+            match c {
+                '\\' => {
+                    match chars.next() {
+                        Some('t') => literal.push('\t'),
+                        Some('n') => literal.push('\n'),
+                        Some('\\') => literal.push('\\'),
+                        Some(other) => {
+                            literal.push('\\');
+                            literal.push(other);
+                        }
+                        None => literal.push('\\'),
+                    }
+                }
+                '<' => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '>' {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+
+                    if !closed {
+                        return Err(format!("Unterminated field <{} in format string", name));
+                    }
+                    if !fields.contains(&&name[..]) {
+                        return Err(format!("Unknown field <{}> in format string. Available \
+                                            fields are: {}",
+                                           name,
+                                           fields.iter()
+                                                 .map(|f| format!("<{}>", f))
+                                                 .collect::<Vec<_>>()
+                                                 .join(" ")));
+                    }
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(literal));
+                        literal = String::new();
+                    }
+                    parts.push(Part::Field(name));
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts: parts })
+    }
+
+    pub fn uses(&self, field: &str) -> bool {
+        self.parts.iter().any(|part| {
+            match *part {
+                Part::Field(ref name) => name == field,
+                _ => false,
+            }
+        })
+    }
+
+    pub fn render(&self, values: &HashMap<&str, String>) -> String {
+        let mut out = String::new();
+        for part in self.parts.iter() {
+            match *part {
+                Part::Literal(ref s) => out.push_str(s),
+                Part::Field(ref name) => {
+                    if let Some(value) = values.get(&name[..]) {
+                        out.push_str(value);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
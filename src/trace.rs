@@ -0,0 +1,219 @@
+// Timing spans for a single command invocation. Tracing is off unless `enable`
+// has been called, in which case every `span` guard records how long it was
+// alive, nested under whichever span was alive when it was created.
+//
+// Only the thread that called `enable` is traced, and threads that `attach`
+// a `handle` taken on a traced thread: their spans nest under the span that
+// was alive where the handle was taken. `util::run_concurrently` does this
+// for its workers.
+
+use rustc_serialize::json::{Json, ToJson};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+thread_local!(static TRACER: RefCell<Option<Tracer>> = RefCell::new(None));
+
+static NEXT_SPAN_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// What the threads tracing one command share
+struct Shared {
+    roots: Vec<SpanRecord>, // finished spans that have no parent
+    // finished spans of other threads, with the id of the span they're under
+    adopted: Vec<(usize, SpanRecord)>,
+}
+
+struct Tracer {
+    shared: Arc<Mutex<Shared>>,
+    parent: Option<usize>, // the span on another thread this one's spans are under
+    stack: Vec<(usize, SpanRecord, Instant)>, // spans that are currently alive
+}
+
+pub struct SpanRecord {
+    pub name: String,
+    pub duration: Duration,
+    pub children: Vec<SpanRecord>,
+}
+
+// Guard returned by `span`. The span ends when it is dropped.
+pub struct Span {
+    active: bool,
+}
+
+// Lets another thread be traced along with the one it was taken on
+#[derive(Clone)]
+pub struct Handle {
+    shared: Arc<Mutex<Shared>>,
+    parent: Option<usize>,
+}
+
+pub fn enable() {
+    TRACER.with(|t| {
+        *t.borrow_mut() = Some(Tracer {
+            shared: Arc::new(Mutex::new(Shared {
+                roots: Vec::new(),
+                adopted: Vec::new(),
+            })),
+            parent: None,
+            stack: Vec::new(),
+        })
+    });
+}
+
+// A handle on this thread's tracing, or None if it isn't traced
+pub fn handle() -> Option<Handle> {
+    TRACER.with(|t| {
+        t.borrow().as_ref().map(|tracer| {
+            Handle {
+                shared: tracer.shared.clone(),
+                parent: tracer.stack.last().map(|&(id, _, _)| id).or(tracer.parent),
+            }
+        })
+    })
+}
+
+// Traces this thread's spans under the span `handle` was taken in, or stops
+// tracing it if `handle` is None
+pub fn attach(handle: Option<Handle>) {
+    TRACER.with(|t| {
+        *t.borrow_mut() = handle.map(|handle| {
+            Tracer {
+                shared: handle.shared,
+                parent: handle.parent,
+                stack: Vec::new(),
+            }
+        })
+    });
+}
+
+pub fn span(name: &str) -> Span {
+    let active = TRACER.with(|t| {
+        match *t.borrow_mut() {
+            None => false,
+            Some(ref mut tracer) => {
+                let record = SpanRecord {
+                    name: name.to_string(),
+                    duration: Duration::new(0, 0),
+                    children: Vec::new(),
+                };
+                let id = NEXT_SPAN_ID.fetch_add(1, Ordering::SeqCst);
+                tracer.stack.push((id, record, Instant::now()));
+                true
+            }
+        }
+    });
+    Span { active: active }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if !self.active {
+            return;
+        }
+
+        TRACER.with(|t| {
+            if let Some(ref mut tracer) = *t.borrow_mut() {
+                if let Some((id, mut record, start)) = tracer.stack.pop() {
+                    record.duration = start.elapsed();
+                    let mut shared = tracer.shared.lock().unwrap();
+                    // spans other threads had under this one
+                    let adopted = mem::replace(&mut shared.adopted, Vec::new());
+                    for (parent, child) in adopted {
+                        if parent == id {
+                            record.children.push(child);
+                        } else {
+                            shared.adopted.push((parent, child));
+                        }
+                    }
+                    match (tracer.stack.last_mut(), tracer.parent) {
+                        (Some(&mut (_, ref mut parent, _)), _) => parent.children.push(record),
+                        (None, Some(parent)) => shared.adopted.push((parent, record)),
+                        (None, None) => shared.roots.push(record),
+                    }
+                }
+            }
+        });
+    }
+}
+
+// Disables tracing and returns the finished top-level spans, along with any
+// of other threads that outlived the span they were under
+pub fn finish() -> Vec<SpanRecord> {
+    TRACER.with(|t| {
+        t.borrow_mut()
+         .take()
+         .map(|tracer| {
+             let mut shared = tracer.shared.lock().unwrap();
+             let mut roots = mem::replace(&mut shared.roots, Vec::new());
+             roots.extend(shared.adopted.drain(..).map(|(_, record)| record));
+             roots
+         })
+         .unwrap_or(vec![])
+    })
+}
+
+fn millis(d: &Duration) -> f64 {
+    d.as_secs() as f64 * 1000.0 + d.subsec_nanos() as f64 / 1000000.0
+}
+
+impl SpanRecord {
+    // Writes the span and its children as an indented tree, one span per line
+    pub fn write_tree<W: Write>(&self, writer: &mut W, depth: usize) -> io::Result<()> {
+        try!(writeln!(writer,
+                      "{:indent$}{:<width$} {:>10.3}ms",
+                      "",
+                      self.name,
+                      millis(&self.duration),
+                      indent = 2 * depth,
+                      width = 40usize.saturating_sub(2 * depth)));
+        for child in self.children.iter() {
+            try!(child.write_tree(writer, depth + 1));
+        }
+        Ok(())
+    }
+}
+
+impl ToJson for SpanRecord {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert("name".to_string(), self.name.to_json());
+        obj.insert("duration_ms".to_string(), Json::F64(millis(&self.duration)));
+        obj.insert("children".to_string(), self.children.to_json());
+        Json::Object(obj)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{attach, enable, finish, handle, span};
+    use std::thread;
+
+    #[test]
+    fn spans_on_attached_threads_nest_under_the_span_they_were_started_in() {
+        enable();
+        {
+            let _outer = span("outer");
+            let handle = handle();
+            let worker = thread::spawn(move || {
+                attach(handle);
+                let _inner = span("inner");
+            });
+            worker.join().unwrap();
+            let _sibling = span("sibling");
+        }
+        // a thread that isn't attached isn't traced
+        let untraced = thread::spawn(|| {
+            let _span = span("untraced");
+        });
+        untraced.join().unwrap();
+
+        let roots = finish();
+        assert_eq!(roots.len(), 1);
+        let children: Vec<&str> = roots[0].children.iter().map(|c| &c.name[..]).collect();
+        assert_eq!((&roots[0].name[..], children), ("outer", vec!["sibling", "inner"]));
+    }
+}
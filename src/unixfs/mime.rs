@@ -0,0 +1,89 @@
+// Guesses a content type from the first bytes of a file's data
+
+use std::str;
+
+// how much of the file `sniff` looks at
+pub const SNIFF_LEN: usize = 512;
+
+const MAGIC: &'static [(&'static [u8], &'static str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"BM", "image/bmp"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"BZh", "application/x-bzip2"),
+    (b"\xfd7zXZ\x00", "application/x-xz"),
+    (b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"\x7fELF", "application/x-executable"),
+    (b"\x00asm", "application/wasm"),
+    (b"OggS", "audio/ogg"),
+    (b"fLaC", "audio/flac"),
+    (b"ID3", "audio/mpeg"),
+    (b"\x1a\x45\xdf\xa3", "video/webm"),
+];
+
+// (prefix, offset of second marker, second marker, type) for RIFF-style containers
+const RIFF_MAGIC: &'static [(&'static [u8], usize, &'static [u8], &'static str)] = &[
+    (b"RIFF", 8, b"WEBP", "image/webp"),
+    (b"RIFF", 8, b"WAVE", "audio/wav"),
+    (b"RIFF", 8, b"AVI ", "video/x-msvideo"),
+    (b"", 4, b"ftyp", "video/mp4"),
+];
+
+const TEXT_MAGIC: &'static [(&'static str, &'static str)] = &[
+    ("<!doctype html", "text/html"),
+    ("<html", "text/html"),
+    ("<?xml", "text/xml"),
+    ("<svg", "image/svg+xml"),
+    ("{", "application/json"),
+    ("[", "application/json"),
+];
+
+pub fn sniff(data: &[u8]) -> &'static str {
+    let data = &data[..::std::cmp::min(data.len(), SNIFF_LEN)];
+
+    for &(magic, ty) in MAGIC {
+        if data.starts_with(magic) {
+            return ty;
+        }
+    }
+
+    for &(prefix, offset, marker, ty) in RIFF_MAGIC {
+        if data.starts_with(prefix) && data.len() >= offset + marker.len() &&
+           &data[offset..offset + marker.len()] == marker {
+            return ty;
+        }
+    }
+
+    if is_text(data) {
+        let text = str::from_utf8(data).unwrap_or("").trim_left().to_lowercase();
+        for &(magic, ty) in TEXT_MAGIC {
+            if text.starts_with(magic) {
+                return ty;
+            }
+        }
+        return "text/plain";
+    }
+
+    "application/octet-stream"
+}
+
+// Text is valid UTF-8 (allowing a code point cut off at the end of the sniffed
+// bytes) without any control characters other than whitespace
+fn is_text(data: &[u8]) -> bool {
+    let valid = match str::from_utf8(data) {
+        Ok(s) => s,
+        Err(e) => {
+            // a code point cut off by SNIFF_LEN is at most 3 bytes
+            if data.len() - e.valid_up_to() > 3 {
+                return false;
+            }
+            str::from_utf8(&data[..e.valid_up_to()]).unwrap()
+        }
+    };
+    valid.chars().all(|c| !c.is_control() || c == '\n' || c == '\r' || c == '\t' || c == '\x0c')
+}
@@ -1,6 +1,9 @@
+use merkledag::{DagService, Node};
+
 use protobuf::{self, Message};
 use std::io::{Read, Write};
 
+pub mod mime;
 pub mod pb;
 
 pub struct FSNode {
@@ -20,6 +23,25 @@ impl FSNode {
         }
     }
 
+    // A file whose content is in the nodes linked to, of the given sizes
+    pub fn file_from_blocks(blocksizes: Vec<u64>) -> FSNode {
+        FSNode {
+            data: vec![],
+            subtotal: blocksizes.iter().sum(),
+            blocksizes: blocksizes,
+            ty: pb::Data_DataType::File,
+        }
+    }
+
+    pub fn directory() -> FSNode {
+        FSNode {
+            data: vec![],
+            blocksizes: vec![],
+            subtotal: 0,
+            ty: pb::Data_DataType::Directory,
+        }
+    }
+
     pub fn from_reader<R: Read>(reader: &mut R) -> Result<Self, String> {
         let mut pb_node = try!(from_reader(reader));
         let data = pb_node.take_Data();
@@ -49,3 +71,20 @@ pub fn from_reader<R: Read>(reader: &mut R) -> Result<pb::Data, String> {
     protobuf::parse_from_reader::<pb::Data>(reader)
         .map_err(|e| format!("Error parsing encoded Unixfs data: {}", e))
 }
+
+// Writes the content of the file whose DAG is rooted at `node` to `out`: the
+// node's own data, followed by that of its children in order
+pub fn write_file(dagservice: &DagService, node: &Node, out: &mut Write) -> Result<(), String> {
+    let data = try!(from_reader(&mut node.get_data()));
+    match data.get_Type() {
+        pb::Data_DataType::File | pb::Data_DataType::Raw => {}
+        ty => return Err(format!("Expected a file node, found a {:?} node", ty)),
+    }
+
+    try!(out.write_all(data.get_Data()).map_err(|e| format!("Error writing file content: {}", e)));
+    for link in node.get_links() {
+        let child = try!(dagservice.get(&link.clone_hash()));
+        try!(write_file(dagservice, &child, out));
+    }
+    Ok(())
+}
@@ -1,8 +1,18 @@
+use libc;
+use trace;
 use rust_multihash as multihash;
 
+use hyper::Client;
+use std::cmp;
+use std::env;
+use std::any::Any;
 use std::fs::{self, File};
 use std::io;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
+use std::process::Command;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 
 pub fn hash<'a>(data: &'a [u8]) -> multihash::Multihash {
     multihash::multihash(data, multihash::HashType::SHA2_256)
@@ -28,3 +38,231 @@ pub fn ensure_dir_writable<P: AsRef<Path>>(path: P) -> io::Result<()> {
     try!(File::create(&path));
     fs::remove_file(path)
 }
+
+// A command running `cmd` through the platform's shell
+#[cfg(unix)]
+pub fn shell_command(cmd: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(cmd);
+    command
+}
+
+#[cfg(windows)]
+pub fn shell_command(cmd: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(cmd);
+    command
+}
+
+// Whether `path` is a file this process may run
+#[cfg(unix)]
+pub fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+                      .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+pub fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+// Whether stdin is a pipe or file rather than a terminal
+#[cfg(unix)]
+pub fn stdin_is_piped() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 0 }
+}
+
+#[cfg(not(unix))]
+pub fn stdin_is_piped() -> bool {
+    false
+}
+
+// An HTTP client connecting through `proxy` ("host:port", optionally prefixed
+// with http://), or through the proxy in the http_proxy environment variable
+// if no proxy is configured
+pub fn http_client(proxy: Option<&str>) -> Result<Client, String> {
+    let proxy = match proxy.map(|p| p.to_string())
+                           .or_else(|| env::var("http_proxy").ok())
+                           .or_else(|| env::var("HTTP_PROXY").ok()) {
+        Some(ref p) if !p.is_empty() => p.clone(),
+        _ => return Ok(Client::new()),
+    };
+
+    let (host, port) = try!(parse_proxy(&proxy));
+    Ok(Client::with_http_proxy(host, port))
+}
+
+// Splits a proxy address as `http_client` takes it into its host and port,
+// which is 80 if it's left out
+pub fn parse_proxy(proxy: &str) -> Result<(String, u16), String> {
+    let addr = proxy.trim_left_matches("http://").trim_right_matches('/');
+    let (host, port) = try!(split_host_port(addr)
+                                .map_err(|e| format!("{} in proxy address {}", e, proxy)));
+    Ok((host.to_string(), port.unwrap_or(80)))
+}
+
+// Splits "host:port", or just "host", into the host and the port. An IPv6
+// host is given in brackets, e.g. "[::1]:8080".
+pub fn split_host_port(addr: &str) -> Result<(&str, Option<u16>), String> {
+    let (host, port) = match addr.rfind(':') {
+        Some(i) if !addr.ends_with(']') => {
+            let port = try!(addr[i + 1..].parse::<u16>().map_err(|_| "Invalid port".to_string()));
+            (&addr[..i], Some(port))
+        }
+        _ => (addr, None),
+    };
+    if host.is_empty() || host.contains(|c: char| c == '/' || c.is_whitespace()) {
+        return Err("Invalid host".to_string());
+    }
+    Ok((host, port))
+}
+
+// Splits a command line into words at whitespace, the way a shell would for
+// the simple cases: 'single' and "double" quotes group words, and a backslash
+// outside single quotes escapes the next character
+pub fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), c) => word.push(c),
+            (_, '\\') => {
+                match chars.next() {
+                    Some(escaped) => word.push(escaped),
+                    None => return Err("Command line ends with a backslash".to_string()),
+                }
+                in_word = true;
+            }
+            (Some('"'), '"') => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(word);
+                    word = String::new();
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if quote.is_some() {
+        return Err("Unterminated quote in command line".to_string());
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+// Parses a byte count such as "512", "64K", "10MB" or "2G" (binary multiples)
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let trimmed = s.trim();
+    let upper = trimmed.to_uppercase();
+    let digits = upper.trim_right_matches('B');
+    let (num, multiplier) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 1 << 10),
+        Some('M') => (&digits[..digits.len() - 1], 1 << 20),
+        Some('G') => (&digits[..digits.len() - 1], 1 << 30),
+        Some('T') => (&digits[..digits.len() - 1], 1 << 40),
+        _ => (digits, 1),
+    };
+
+    let n = try!(num.trim()
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid size: {}", trimmed)));
+    n.checked_mul(multiplier).ok_or(format!("Size too large: {}", trimmed))
+}
+
+// Runs `f` on every item using up to `workers` threads. `on_result` is called
+// on the calling thread with each item's index and result as soon as it
+// finishes, so results arrive in completion order rather than input order.
+// `f` panicking on an item is that item's error, so every item has a result.
+// If the calling thread is traced, so are the workers, under its current span.
+pub fn run_concurrently<T, R, F, G>(items: Vec<T>, workers: usize, f: F, mut on_result: G)
+    where T: Send + 'static,
+          R: Send + 'static,
+          F: Fn(T) -> Result<R, String> + Send + Sync + 'static,
+          G: FnMut(usize, Result<R, String>)
+{
+    let num_items = items.len();
+    let queue = Arc::new(Mutex::new(items.into_iter().enumerate()));
+    let f = Arc::new(f);
+    let (tx, rx) = mpsc::channel();
+    let tracing = trace::handle();
+
+    for _ in 0..cmp::min(workers, num_items) {
+        let queue = queue.clone();
+        let f = f.clone();
+        let tx = tx.clone();
+        let tracing = tracing.clone();
+        thread::spawn(move || {
+            trace::attach(tracing);
+            loop {
+                // the lock is only held while taking the next item
+                let next = queue.lock().unwrap().next();
+                match next {
+                    None => break,
+                    Some((i, item)) => {
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| f(item)))
+                                         .unwrap_or_else(|panic| Err(panic_message(panic)));
+                        if tx.send((i, result)).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+    }
+    drop(tx);
+
+    for (i, result) in rx.iter() {
+        on_result(i, result);
+    }
+}
+
+fn panic_message(panic: Box<Any + Send>) -> String {
+    let message = match panic.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => panic.downcast_ref::<String>().cloned().unwrap_or(String::new()),
+    };
+    format!("Panicked: {}", message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run_concurrently;
+
+    #[test]
+    fn panicking_items_have_errors_for_results() {
+        let mut results = vec![None; 6];
+        run_concurrently((0..6).collect(),
+                         3,
+                         |n: usize| {
+                             if n % 2 == 1 {
+                                 panic!("odd {}", n);
+                             }
+                             Ok(n * 10)
+                         },
+                         |i, result| results[i] = Some(result));
+        for (i, result) in results.into_iter().enumerate() {
+            match result.unwrap() {
+                Ok(n) => assert_eq!((i % 2, n), (0, i * 10)),
+                Err(e) => assert_eq!((i % 2, e), (1, format!("Panicked: odd {}", i))),
+            }
+        }
+    }
+}